@@ -0,0 +1,83 @@
+use crate::apu::filter::Filter;
+use crate::apu::resampler::Resampler;
+use crate::apu::ring_buffer::AudioProducer;
+use crate::region::Region;
+
+// Called once a frame with the fill level of the ring buffer, returns the
+// ratio to scale the output rate by. Keeps the buffer from running dry or
+// overflowing when the host's clock drifts from the emulated one.
+pub type RateControl = Box<dyn FnMut(f32) -> f64>;
+
+// Speeds up by at most max_delta while the buffer is under half full and
+// slows down by as much over it. 0.005 is inaudible.
+pub fn dynamic_rate_control(max_delta: f64) -> RateControl {
+    Box::new(move |fill| 1.0 + (1.0 - 2.0 * fill as f64) * max_delta)
+}
+
+// The way from the mixed APU level to the host: resampled, filtered like
+// the NES output and queued for the audio callback.
+pub struct AudioOutput {
+    resampler: Resampler,
+    filters: [Filter; 3],
+    producer: AudioProducer,
+    rate_control: Option<RateControl>,
+}
+
+impl AudioOutput {
+    pub fn new(region: Region, sample_rate: u32, producer: AudioProducer) -> Self {
+        AudioOutput {
+            resampler: Resampler::new(region.cpu_clock_rate(), sample_rate as f64),
+            filters: Filter::nes_chain(sample_rate as f32),
+            producer,
+            rate_control: None,
+        }
+    }
+
+    pub fn set_rate_control(&mut self, rate_control: Option<RateControl>) {
+        self.rate_control = rate_control;
+    }
+
+    // one CPU cycle of output
+    pub fn clock(&mut self, level: f32) {
+        let filters = &mut self.filters;
+        let producer = &mut self.producer;
+        self.resampler.clock(level, |sample| {
+            let sample = filters.iter_mut().fold(sample, |sample, filter| filter.process(sample));
+            producer.push(sample);
+        });
+    }
+
+    pub fn end_frame(&mut self) {
+        if let Some(rate_control) = &mut self.rate_control {
+            let ratio = rate_control(self.producer.fill_level());
+            self.resampler.set_rate_adjustment(ratio);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::apu::ring_buffer::audio_ring;
+
+    #[test]
+    fn test_rate_control() {
+        let mut control = dynamic_rate_control(0.005);
+        assert_eq!(control(0.5), 1.0);
+        assert!(control(0.0) > 1.0);
+        assert!(control(1.0) < 1.0);
+
+        let (producer, mut consumer) = audio_ring(4096);
+        let mut output = AudioOutput::new(Region::Ntsc, 48_000, producer);
+        output.set_rate_control(Some(dynamic_rate_control(0.01)));
+        output.end_frame();
+        for i in 0..29_830 {
+            output.clock((i / 100 % 2) as f32);
+        }
+        // an empty buffer asks for 1% more than the 800 samples of a frame
+        assert!((805..=810).contains(&consumer.len()), "{}", consumer.len());
+        let mut out = vec![0.0; 1000];
+        consumer.pop_into(&mut out);
+        assert!(out.iter().any(|sample| *sample > 0.1));
+    }
+}
@@ -0,0 +1,155 @@
+use std::fs::File;
+use std::io::{self, BufWriter};
+use std::path::Path;
+
+use crate::apu::filter::Filter;
+use crate::apu::mixer;
+use crate::apu::resampler::Resampler;
+use crate::region::Region;
+use crate::wav::{SampleFormat, WavWriter};
+
+// Records what the console outputs into a WAV file, either the mix as one
+// track or every channel in a track of its own (in the order of TRACKS).
+// Each track goes through the same resampling and filters as playback, the
+// split tracks are each channel mixed alone.
+
+pub const TRACKS: [&str; 6] = ["pulse 1", "pulse 2", "triangle", "noise", "dmc", "expansion"];
+
+struct Track {
+    resampler: Resampler,
+    filters: [Filter; 3],
+    pending: Vec<f32>,
+}
+
+pub struct AudioCapture {
+    wav: WavWriter<BufWriter<File>>,
+    tracks: Vec<Track>,
+    frame: Vec<f32>,
+    error: Option<io::Error>,
+}
+
+impl AudioCapture {
+    pub fn create(
+        path: impl AsRef<Path>,
+        region: Region,
+        sample_rate: u32,
+        format: SampleFormat,
+        split_channels: bool,
+    ) -> io::Result<Self> {
+        let count = if split_channels { TRACKS.len() } else { 1 };
+        let wav = WavWriter::create(path, sample_rate, count as u16, format)?;
+        let tracks = (0..count)
+            .map(|_| Track {
+                resampler: Resampler::new(region.cpu_clock_rate(), sample_rate as f64),
+                filters: Filter::nes_chain(sample_rate as f32),
+                pending: vec![],
+            })
+            .collect();
+        Ok(AudioCapture {
+            wav,
+            tracks,
+            frame: vec![0.0; count],
+            error: None,
+        })
+    }
+
+    // one CPU cycle, the APU channel levels and the expansion output
+    pub fn clock(&mut self, channels: [f32; 5], expansion: f32) {
+        let [pulse1, pulse2, triangle, noise, dmc] = channels;
+        if self.tracks.len() == 1 {
+            let level = mixer::mix_levels(pulse1, pulse2, triangle, noise, dmc) + expansion;
+            self.tracks[0].clock(level);
+        } else {
+            self.tracks[0].clock(mixer::mix_levels(pulse1, 0.0, 0.0, 0.0, 0.0));
+            self.tracks[1].clock(mixer::mix_levels(0.0, pulse2, 0.0, 0.0, 0.0));
+            self.tracks[2].clock(mixer::mix_levels(0.0, 0.0, triangle, 0.0, 0.0));
+            self.tracks[3].clock(mixer::mix_levels(0.0, 0.0, 0.0, noise, 0.0));
+            self.tracks[4].clock(mixer::mix_levels(0.0, 0.0, 0.0, 0.0, dmc));
+            self.tracks[5].clock(expansion);
+        }
+
+        // all tracks resample in step
+        let ready = self.tracks[0].pending.len();
+        for i in 0..ready {
+            for (sample, track) in self.frame.iter_mut().zip(&self.tracks) {
+                *sample = track.pending[i];
+            }
+            if self.error.is_none() {
+                self.error = self.wav.write_frame(&self.frame).err();
+            }
+        }
+        for track in &mut self.tracks {
+            track.pending.clear();
+        }
+    }
+
+    pub fn frames(&self) -> u32 {
+        self.wav.frames()
+    }
+
+    // completes the file, or returns the first write error
+    pub fn finish(self) -> io::Result<()> {
+        if let Some(e) = self.error {
+            return Err(e);
+        }
+        self.wav.finish().map(|_| ())
+    }
+}
+
+impl Track {
+    fn clock(&mut self, level: f32) {
+        let filters = &mut self.filters;
+        let pending = &mut self.pending;
+        self.resampler.clock(level, |sample| {
+            pending.push(filters.iter_mut().fold(sample, |sample, filter| filter.process(sample)));
+        });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bus::Bus;
+    use crate::cartridge::test::test_rom;
+
+    fn capture(split_channels: bool) -> Vec<u8> {
+        let path = std::env::temp_dir().join(format!("capture_test_{}_{}.wav", std::process::id(), split_channels));
+        let mut bus = Bus::new(test_rom()).unwrap();
+        // a square on pulse 2
+        bus.write_mem(0x4015, 0b0010);
+        bus.write_mem(0x4004, 0b1011_1111);
+        bus.write_mem(0x4006, 0xFD);
+        bus.write_mem(0x4007, 0x08);
+        let capture = AudioCapture::create(&path, Region::Ntsc, 48_000, SampleFormat::Pcm16, split_channels).unwrap();
+        bus.start_audio_capture(capture);
+        for _ in 0..29_830 / 10 {
+            bus.tick(1);
+        }
+        let capture = bus.stop_audio_capture().unwrap();
+        assert_eq!(capture.frames(), 80);
+        capture.finish().unwrap();
+        let data = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        data[44..].to_vec()
+    }
+
+    fn track(data: &[u8], channels: usize, track: usize) -> Vec<i16> {
+        data.chunks(2 * channels).map(|frame| i16::from_le_bytes([frame[2 * track], frame[2 * track + 1]])).collect()
+    }
+
+    #[test]
+    fn test_mixed_capture() {
+        let data = capture(false);
+        assert_eq!(data.len(), 80 * 2);
+        assert!(track(&data, 1, 0).iter().any(|sample| *sample > 1000));
+    }
+
+    #[test]
+    fn test_split_capture() {
+        let data = capture(true);
+        assert_eq!(data.len(), 80 * 2 * TRACKS.len());
+        assert!(track(&data, 6, 0).iter().all(|sample| *sample == 0));
+        assert!(track(&data, 6, 1).iter().any(|sample| *sample > 1000));
+        assert!(track(&data, 6, 5).iter().all(|sample| *sample == 0));
+    }
+}
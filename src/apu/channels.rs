@@ -0,0 +1,89 @@
+// Mute, solo and volume for every sound channel, the five APU channels first
+// and the cartridge's expansion channels after them. Only the gains that go
+// into the mix change, the channels themselves keep running.
+
+pub const APU_CHANNELS: [&str; 5] = ["Pulse 1", "Pulse 2", "Triangle", "Noise", "DMC"];
+
+pub struct ChannelControls {
+    names: Vec<&'static str>,
+    volumes: Vec<f32>,
+    muted: Vec<bool>,
+    solo: Vec<bool>,
+}
+
+impl ChannelControls {
+    pub fn new(expansion: &[&'static str]) -> Self {
+        let names: Vec<&'static str> = APU_CHANNELS.iter().chain(expansion).copied().collect();
+        let count = names.len();
+        ChannelControls {
+            names,
+            volumes: vec![1.0; count],
+            muted: vec![false; count],
+            solo: vec![false; count],
+        }
+    }
+
+    pub fn names(&self) -> &[&'static str] {
+        &self.names
+    }
+
+    pub fn volume(&self, channel: usize) -> f32 {
+        self.volumes[channel]
+    }
+
+    // 1.0 is the normal level
+    pub fn set_volume(&mut self, channel: usize, volume: f32) {
+        self.volumes[channel] = volume.max(0.0);
+    }
+
+    pub fn muted(&self, channel: usize) -> bool {
+        self.muted[channel]
+    }
+
+    pub fn set_muted(&mut self, channel: usize, muted: bool) {
+        self.muted[channel] = muted;
+    }
+
+    pub fn solo(&self, channel: usize) -> bool {
+        self.solo[channel]
+    }
+
+    // while any channel is soloed only the soloed ones are heard
+    pub fn set_solo(&mut self, channel: usize, solo: bool) {
+        self.solo[channel] = solo;
+    }
+
+    pub fn gains(&self) -> Vec<f32> {
+        let any_solo = self.solo.contains(&true);
+        (0..self.names.len())
+            .map(|i| {
+                let heard = if any_solo { self.solo[i] } else { !self.muted[i] };
+                if heard { self.volumes[i] } else { 0.0 }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_mute_solo_volume() {
+        let mut channels = ChannelControls::new(&["VRC6 Saw"]);
+        assert_eq!(channels.names().len(), 6);
+        assert_eq!(channels.gains(), [1.0; 6]);
+
+        channels.set_muted(0, true);
+        channels.set_volume(5, 0.5);
+        assert_eq!(channels.gains(), [0.0, 1.0, 1.0, 1.0, 1.0, 0.5]);
+
+        // solo wins over mute
+        channels.set_solo(0, true);
+        channels.set_solo(2, true);
+        assert_eq!(channels.gains(), [1.0, 0.0, 1.0, 0.0, 0.0, 0.0]);
+        channels.set_solo(0, false);
+        channels.set_solo(2, false);
+        assert_eq!(channels.gains(), [0.0, 1.0, 1.0, 1.0, 1.0, 0.5]);
+    }
+}
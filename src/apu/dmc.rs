@@ -0,0 +1,218 @@
+// The delta modulation channel, $4010-$4013.
+//
+// IL-- RRRR   IRQ enable, loop, rate index
+// -DDD DDDD   direct load of the output level ($4011)
+// AAAA AAAA   sample address, $C000 + A * 64 ($4012)
+// LLLL LLLL   sample length, L * 16 + 1 bytes ($4013)
+//
+// The memory reader asks the bus for the next sample byte whenever the one
+// byte buffer is empty, the bus fetches it with a DMA that stalls the CPU.
+// The output unit shifts the bits out one per timer period, each moving the
+// 7 bit level up or down by 2.
+pub struct Dmc {
+    irq_enabled: bool,
+    looping: bool,
+    rate: u16,
+    timer: u16,
+    level: u8,
+
+    sample_addr: u16,
+    sample_length: u16,
+    current_addr: u16,
+    bytes_remaining: u16,
+    buffer: Option<u8>,
+
+    shift: u8,
+    bits_remaining: u8,
+    silence: bool,
+    irq: bool,
+}
+
+impl Dmc {
+    pub fn new(rates: &[u16; 16]) -> Self {
+        Dmc {
+            irq_enabled: false,
+            looping: false,
+            rate: rates[0],
+            timer: 0,
+            level: 0,
+            sample_addr: 0xC000,
+            sample_length: 1,
+            current_addr: 0xC000,
+            bytes_remaining: 0,
+            buffer: None,
+            shift: 0,
+            bits_remaining: 8,
+            silence: true,
+            irq: false,
+        }
+    }
+
+    // the rates come from the region's table
+    pub fn write(&mut self, reg: u16, data: u8, rates: &[u16; 16]) {
+        match reg {
+            0 => {
+                self.irq_enabled = data & 0x80 != 0;
+                self.looping = data & 0x40 != 0;
+                self.rate = rates[(data & 0x0F) as usize];
+                if !self.irq_enabled {
+                    self.irq = false;
+                }
+            }
+            1 => self.level = data & 0x7F,
+            2 => self.sample_addr = 0xC000 + data as u16 * 64,
+            _ => self.sample_length = data as u16 * 16 + 1,
+        }
+    }
+
+    // bit 4 of $4015, writing it also acknowledges the IRQ
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.irq = false;
+        if !enabled {
+            self.bytes_remaining = 0;
+        } else if self.bytes_remaining == 0 {
+            self.restart();
+        }
+    }
+
+    fn restart(&mut self) {
+        self.current_addr = self.sample_addr;
+        self.bytes_remaining = self.sample_length;
+    }
+
+    pub fn active(&self) -> bool {
+        self.bytes_remaining > 0
+    }
+
+    pub fn irq(&self) -> bool {
+        self.irq
+    }
+
+    // the address the memory reader wants fetched
+    pub fn dma_request(&self) -> Option<u16> {
+        if self.buffer.is_none() && self.bytes_remaining > 0 {
+            Some(self.current_addr)
+        } else {
+            None
+        }
+    }
+
+    pub fn fill(&mut self, data: u8) {
+        self.buffer = Some(data);
+        // the address wraps around to $8000
+        self.current_addr = self.current_addr.checked_add(1).unwrap_or(0x8000);
+        self.bytes_remaining -= 1;
+        if self.bytes_remaining == 0 {
+            if self.looping {
+                self.restart();
+            } else if self.irq_enabled {
+                self.irq = true;
+            }
+        }
+    }
+
+    // every CPU cycle, the rates are in CPU cycles
+    pub fn clock_timer(&mut self) {
+        if self.timer > 0 {
+            self.timer -= 1;
+            return;
+        }
+        self.timer = self.rate - 1;
+
+        if !self.silence {
+            if self.shift & 1 != 0 {
+                if self.level <= 125 {
+                    self.level += 2;
+                }
+            } else if self.level >= 2 {
+                self.level -= 2;
+            }
+        }
+        self.shift >>= 1;
+        self.bits_remaining -= 1;
+        if self.bits_remaining == 0 {
+            self.bits_remaining = 8;
+            match self.buffer.take() {
+                Some(data) => {
+                    self.shift = data;
+                    self.silence = false;
+                }
+                None => self.silence = true,
+            }
+        }
+    }
+
+    pub fn output(&self) -> u8 {
+        self.level
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::region::Region;
+
+    fn dmc() -> Dmc {
+        let rates = Region::Ntsc.dmc_rates();
+        let mut dmc = Dmc::new(rates);
+        dmc.write(0, 0x8F, rates);
+        dmc.write(2, 0xFF, rates);
+        dmc.write(3, 0x04, rates);
+        dmc
+    }
+
+    #[test]
+    fn test_sample_fetch_and_irq() {
+        let mut dmc = dmc();
+        assert_eq!(dmc.dma_request(), None);
+        dmc.set_enabled(true);
+        assert_eq!(dmc.dma_request(), Some(0xFFC0));
+        for _ in 0..65 {
+            assert!(!dmc.irq());
+            dmc.fill(0);
+            dmc.buffer = None;
+        }
+        // 65 bytes from $FFC0 wrap around to $8000
+        assert_eq!(dmc.current_addr, 0x8001);
+        assert!(dmc.irq());
+        assert!(!dmc.active());
+        dmc.set_enabled(false);
+        assert!(!dmc.irq());
+    }
+
+    #[test]
+    fn test_loop_restarts() {
+        let rates = Region::Ntsc.dmc_rates();
+        let mut dmc = dmc();
+        dmc.write(0, 0xCF, rates);
+        dmc.set_enabled(true);
+        for _ in 0..65 {
+            dmc.fill(0);
+            dmc.buffer = None;
+        }
+        assert!(!dmc.irq());
+        assert_eq!(dmc.dma_request(), Some(0xFFC0));
+    }
+
+    #[test]
+    fn test_output_level() {
+        let rates = Region::Ntsc.dmc_rates();
+        let mut dmc = dmc();
+        dmc.write(1, 64, rates);
+        dmc.set_enabled(true);
+        dmc.fill(0b0000_0111);
+        // the first 8 bits go by in silence while the buffer is moved in
+        for _ in 0..(54 * 8) {
+            dmc.clock_timer();
+        }
+        assert_eq!(dmc.output(), 64);
+        for _ in 0..(54 * 8) {
+            dmc.clock_timer();
+        }
+        assert_eq!(dmc.output(), 64 + 3 * 2 - 5 * 2);
+
+        // the direct load just sets the level
+        dmc.write(1, 0xFF, rates);
+        assert_eq!(dmc.output(), 0x7F);
+    }
+}
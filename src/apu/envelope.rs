@@ -0,0 +1,86 @@
+// Volume envelope of the pulse and noise channels, clocked every quarter
+// frame. Either a constant volume or a sawtooth decaying from 15, looping
+// when the length counter is halted.
+//
+// --LC VVVV   L loop, C constant volume, V volume or divider period
+pub struct Envelope {
+    start: bool,
+    looping: bool,
+    constant: bool,
+    period: u8,
+    divider: u8,
+    decay: u8,
+}
+
+impl Envelope {
+    pub fn new() -> Self {
+        Envelope {
+            start: false,
+            looping: false,
+            constant: false,
+            period: 0,
+            divider: 0,
+            decay: 0,
+        }
+    }
+
+    pub fn write(&mut self, data: u8) {
+        self.looping = data & 0x20 != 0;
+        self.constant = data & 0x10 != 0;
+        self.period = data & 0x0F;
+    }
+
+    // writing the fourth register of the channel restarts the decay
+    pub fn restart(&mut self) {
+        self.start = true;
+    }
+
+    pub fn clock(&mut self) {
+        if self.start {
+            self.start = false;
+            self.decay = 15;
+            self.divider = self.period;
+        } else if self.divider == 0 {
+            self.divider = self.period;
+            if self.decay > 0 {
+                self.decay -= 1;
+            } else if self.looping {
+                self.decay = 15;
+            }
+        } else {
+            self.divider -= 1;
+        }
+    }
+
+    pub fn volume(&self) -> u8 {
+        if self.constant { self.period } else { self.decay }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_decay() {
+        let mut envelope = Envelope::new();
+        envelope.write(0b0000_0001);
+        envelope.restart();
+        envelope.clock();
+        assert_eq!(envelope.volume(), 15);
+        // the divider runs period + 1 clocks per step
+        envelope.clock();
+        envelope.clock();
+        assert_eq!(envelope.volume(), 14);
+        for _ in 0..28 {
+            envelope.clock();
+        }
+        assert_eq!(envelope.volume(), 0);
+        envelope.clock();
+        envelope.clock();
+        assert_eq!(envelope.volume(), 0);
+
+        envelope.write(0b0011_0111);
+        assert_eq!(envelope.volume(), 7);
+    }
+}
@@ -0,0 +1,69 @@
+use std::f32::consts::PI;
+
+// The first order filters between the APU and the audio out of a NES:
+// high-pass at 90 Hz and 440 Hz, low-pass at 14 kHz.
+pub enum Filter {
+    HighPass { alpha: f32, prev_in: f32, prev_out: f32 },
+    LowPass { alpha: f32, prev_out: f32 },
+}
+
+impl Filter {
+    pub fn high_pass(sample_rate: f32, cutoff: f32) -> Filter {
+        let rc = 1.0 / (2.0 * PI * cutoff);
+        Filter::HighPass { alpha: rc / (rc + 1.0 / sample_rate), prev_in: 0.0, prev_out: 0.0 }
+    }
+
+    pub fn low_pass(sample_rate: f32, cutoff: f32) -> Filter {
+        let rc = 1.0 / (2.0 * PI * cutoff);
+        let dt = 1.0 / sample_rate;
+        Filter::LowPass { alpha: dt / (rc + dt), prev_out: 0.0 }
+    }
+
+    // the chain a NES has
+    pub fn nes_chain(sample_rate: f32) -> [Filter; 3] {
+        [
+            Filter::high_pass(sample_rate, 90.0),
+            Filter::high_pass(sample_rate, 440.0),
+            Filter::low_pass(sample_rate, 14_000.0),
+        ]
+    }
+
+    pub fn process(&mut self, sample: f32) -> f32 {
+        match self {
+            Filter::HighPass { alpha, prev_in, prev_out } => {
+                *prev_out = *alpha * (*prev_out + sample - *prev_in);
+                *prev_in = sample;
+                *prev_out
+            }
+            Filter::LowPass { alpha, prev_out } => {
+                *prev_out += *alpha * (sample - *prev_out);
+                *prev_out
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_high_pass_removes_dc() {
+        let mut filters = Filter::nes_chain(48_000.0);
+        let mut out = 0.0;
+        for _ in 0..48_000 {
+            out = filters.iter_mut().fold(0.5, |sample, filter| filter.process(sample));
+        }
+        assert!(out.abs() < 0.001);
+    }
+
+    #[test]
+    fn test_low_pass_settles() {
+        let mut filter = Filter::low_pass(48_000.0, 14_000.0);
+        let mut out = 0.0;
+        for _ in 0..100 {
+            out = filter.process(1.0);
+        }
+        assert!((out - 1.0).abs() < 0.001);
+    }
+}
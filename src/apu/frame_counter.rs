@@ -0,0 +1,170 @@
+// The frame sequencer behind $4017, it clocks the envelopes and the linear
+// counter every quarter frame and the length counters and sweeps every half.
+//
+// MI-- ----   M 5-step mode, I IRQ inhibit
+//
+// NTSC 4-step:  7457 Q, 14913 QH, 22371 Q, 29828 F, 29829 QHF, 29830 F and wrap
+// NTSC 5-step:  7457 Q, 14913 QH, 22371 Q, 37281 QH, 37282 wrap
+//
+// The frame IRQ (F) is only raised in 4-step mode and stays up until $4015 is
+// read or the inhibit bit is set. A write to $4017 restarts the sequence 3 CPU
+// cycles later when it lands on an even cycle, 4 on an odd one, and in 5-step
+// mode clocks a half frame right away.
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum FrameClock {
+    None,
+    Quarter,
+    // a half frame clocks the quarter frame units too
+    Half,
+}
+
+pub struct FrameCounter {
+    five_step: bool,
+    inhibit_irq: bool,
+    irq: bool,
+    cycle: usize,
+    // (cycles until the write takes effect, value)
+    pending_write: Option<(u8, u8)>,
+}
+
+impl FrameCounter {
+    pub fn new() -> Self {
+        FrameCounter {
+            five_step: false,
+            inhibit_irq: false,
+            irq: false,
+            cycle: 0,
+            pending_write: None,
+        }
+    }
+
+    // odd_cycle is the parity of the CPU cycle the write happened on
+    pub fn write(&mut self, data: u8, odd_cycle: bool) {
+        self.inhibit_irq = data & 0x40 != 0;
+        if self.inhibit_irq {
+            self.irq = false;
+        }
+        self.pending_write = Some((if odd_cycle { 4 } else { 3 }, data));
+    }
+
+    pub fn irq(&self) -> bool {
+        self.irq
+    }
+
+    pub fn acknowledge(&mut self) {
+        self.irq = false;
+    }
+
+    fn raise_irq(&mut self) {
+        if !self.inhibit_irq {
+            self.irq = true;
+        }
+    }
+
+    // one CPU cycle
+    pub fn clock(&mut self, steps: &[usize; 5]) -> FrameClock {
+        if let Some((delay, data)) = self.pending_write {
+            if delay > 1 {
+                self.pending_write = Some((delay - 1, data));
+            } else {
+                self.pending_write = None;
+                self.five_step = data & 0x80 != 0;
+                self.cycle = 0;
+                if self.five_step {
+                    return FrameClock::Half;
+                }
+                return FrameClock::None;
+            }
+        }
+
+        self.cycle += 1;
+        match self.cycle {
+            c if c == steps[0] || c == steps[2] => FrameClock::Quarter,
+            c if c == steps[1] => FrameClock::Half,
+            c if !self.five_step && c == steps[3] - 1 => {
+                self.raise_irq();
+                FrameClock::None
+            }
+            c if !self.five_step && c == steps[3] => {
+                self.raise_irq();
+                FrameClock::Half
+            }
+            c if !self.five_step && c == steps[3] + 1 => {
+                self.raise_irq();
+                self.cycle = 0;
+                FrameClock::None
+            }
+            c if self.five_step && c == steps[4] => FrameClock::Half,
+            c if self.five_step && c == steps[4] + 1 => {
+                self.cycle = 0;
+                FrameClock::None
+            }
+            _ => FrameClock::None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::region::Region;
+
+    fn run(counter: &mut FrameCounter, cycles: usize) -> Vec<(usize, FrameClock)> {
+        let steps = Region::Ntsc.frame_steps();
+        (1..=cycles)
+            .map(|cycle| (cycle, counter.clock(steps)))
+            .filter(|(_, clock)| *clock != FrameClock::None)
+            .collect()
+    }
+
+    #[test]
+    fn test_four_step_sequence() {
+        let mut counter = FrameCounter::new();
+        let clocks = run(&mut counter, 29830 + 7457);
+        assert_eq!(
+            clocks,
+            [
+                (7457, FrameClock::Quarter),
+                (14913, FrameClock::Half),
+                (22371, FrameClock::Quarter),
+                (29829, FrameClock::Half),
+                (29830 + 7457, FrameClock::Quarter),
+            ]
+        );
+        assert!(counter.irq());
+        counter.acknowledge();
+        assert!(!counter.irq());
+    }
+
+    #[test]
+    fn test_five_step_sequence() {
+        let mut counter = FrameCounter::new();
+        counter.write(0x80, false);
+        let clocks = run(&mut counter, 3 + 37282);
+        assert_eq!(
+            clocks,
+            [
+                (3, FrameClock::Half),
+                (3 + 7457, FrameClock::Quarter),
+                (3 + 14913, FrameClock::Half),
+                (3 + 22371, FrameClock::Quarter),
+                (3 + 37281, FrameClock::Half),
+            ]
+        );
+        assert!(!counter.irq());
+    }
+
+    #[test]
+    fn test_write_delay_and_inhibit() {
+        let mut counter = FrameCounter::new();
+        run(&mut counter, 29828);
+        assert!(counter.irq());
+        counter.write(0x40, true);
+        assert!(!counter.irq());
+        // the old sequence runs on until the restart 4 cycles later
+        assert_eq!(run(&mut counter, 4 + 7457), [(1, FrameClock::Half), (4 + 7457, FrameClock::Quarter)]);
+        run(&mut counter, 29830);
+        assert!(!counter.irq());
+    }
+}
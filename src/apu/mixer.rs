@@ -0,0 +1,60 @@
+// The APU mixes its channels with resistors, so the loudness of one channel
+// depends on the others. The two pulses share one curve and triangle, noise
+// and DMC another:
+//
+//  pulse = 95.52 / (8128 / (pulse1 + pulse2) + 100)
+//  tnd   = 163.67 / (24329 / (3 * triangle + 2 * noise + dmc) + 100)
+//
+// Both are looked up from tables, the sum is about 0.0 to 1.0. With channel
+// volumes the levels aren't whole steps anymore and the formulas are used.
+
+const fn pulse_table() -> [f32; 31] {
+    let mut table = [0.0; 31];
+    let mut i = 1;
+    while i < 31 {
+        table[i] = 95.52 / (8128.0 / i as f32 + 100.0);
+        i += 1;
+    }
+    table
+}
+
+const fn tnd_table() -> [f32; 203] {
+    let mut table = [0.0; 203];
+    let mut i = 1;
+    while i < 203 {
+        table[i] = 163.67 / (24329.0 / i as f32 + 100.0);
+        i += 1;
+    }
+    table
+}
+
+static PULSE_TABLE: [f32; 31] = pulse_table();
+static TND_TABLE: [f32; 203] = tnd_table();
+
+pub fn mix(pulse1: u8, pulse2: u8, triangle: u8, noise: u8, dmc: u8) -> f32 {
+    let pulse = PULSE_TABLE[(pulse1 + pulse2) as usize];
+    let tnd = TND_TABLE[3 * triangle as usize + 2 * noise as usize + dmc as usize];
+    pulse + tnd
+}
+
+pub fn mix_levels(pulse1: f32, pulse2: f32, triangle: f32, noise: f32, dmc: f32) -> f32 {
+    let pulse = pulse1 + pulse2;
+    let tnd = 3.0 * triangle + 2.0 * noise + dmc;
+    let pulse = if pulse > 0.0 { 95.52 / (8128.0 / pulse + 100.0) } else { 0.0 };
+    let tnd = if tnd > 0.0 { 163.67 / (24329.0 / tnd + 100.0) } else { 0.0 };
+    pulse + tnd
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_mix() {
+        assert_eq!(mix(0, 0, 0, 0, 0), 0.0);
+        assert!((mix(15, 15, 15, 15, 127) - 1.0).abs() < 0.001);
+        // two pulses are less than twice as loud as one
+        assert!(mix(15, 15, 0, 0, 0) < 2.0 * mix(15, 0, 0, 0, 0));
+        assert!((mix_levels(15.0, 7.0, 3.0, 2.0, 100.0) - mix(15, 7, 3, 2, 100)).abs() < 0.0001);
+    }
+}
@@ -0,0 +1,15 @@
+pub mod nes_apu;
+pub mod audio;
+pub mod capture;
+pub mod channels;
+pub mod filter;
+pub mod resampler;
+pub mod ring_buffer;
+mod dmc;
+mod envelope;
+mod frame_counter;
+mod length_counter;
+mod mixer;
+mod pulse;
+mod triangle;
+mod noise;
@@ -0,0 +1,303 @@
+use crate::apu::dmc::Dmc;
+use crate::apu::frame_counter::{FrameClock, FrameCounter};
+use crate::apu::mixer;
+use crate::apu::noise::Noise;
+use crate::apu::pulse::Pulse;
+use crate::apu::triangle::Triangle;
+use crate::region::Region;
+
+//------------------------------------------------| 0x4017
+//                 Frame counter                  |
+//------------------------------------------------| 0x4015
+//             Status / channel enable            |
+//------------------------------------------------| 0x4010
+//                 DMC (0x4010-0x4013)            |
+//------------------------------------------------| 0x400C
+//                     Noise                      |
+//------------------------------------------------| 0x4008
+//                   Triangle                     |
+//------------------------------------------------| 0x4004
+//                   Pulse 2                      |
+//------------------------------------------------| 0x4000
+//                   Pulse 1                      |
+//------------------------------------------------|
+
+pub struct APU {
+    pub(super) pulse1: Pulse,
+    pub(super) pulse2: Pulse,
+    pub(super) triangle: Triangle,
+    pub(super) noise: Noise,
+    pub(super) dmc: Dmc,
+    frame_counter: FrameCounter,
+    gains: [f32; 5],
+    region: Region,
+    pub cycles: usize,
+}
+
+impl APU {
+    pub fn new() -> Self {
+        APU {
+            pulse1: Pulse::new(1),
+            pulse2: Pulse::new(2),
+            triangle: Triangle::new(),
+            noise: Noise::new(),
+            dmc: Dmc::new(Region::Ntsc.dmc_rates()),
+            frame_counter: FrameCounter::new(),
+            gains: [1.0; 5],
+            region: Region::Ntsc,
+            cycles: 0,
+        }
+    }
+
+    // the noise and DMC periods differ between regions
+    pub fn set_region(&mut self, region: Region) {
+        self.region = region;
+    }
+
+    pub fn write_register(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x4000..=0x4003 => self.pulse1.write(addr & 3, data),
+            0x4004..=0x4007 => self.pulse2.write(addr & 3, data),
+            0x4008..=0x400B => self.triangle.write(addr & 3, data),
+            0x400C..=0x400F => self.noise.write(addr & 3, data, self.region.noise_periods()),
+            0x4010..=0x4013 => self.dmc.write(addr & 3, data, self.region.dmc_rates()),
+            // ---D NT21
+            0x4015 => {
+                self.pulse1.length.set_enabled(data & 0b0001 != 0);
+                self.pulse2.length.set_enabled(data & 0b0010 != 0);
+                self.triangle.length.set_enabled(data & 0b0100 != 0);
+                self.noise.length.set_enabled(data & 0b1000 != 0);
+                self.dmc.set_enabled(data & 0b1_0000 != 0);
+            }
+            0x4017 => self.frame_counter.write(data, self.cycles % 2 == 1),
+            _ => {}
+        }
+    }
+
+    // IF-D NT21, which length counters are still running, whether the DMC
+    // has bytes left and the two IRQs. Reading acknowledges the frame IRQ.
+    pub fn read_status(&mut self) -> u8 {
+        let status = (self.pulse1.length.active() as u8)
+            | (self.pulse2.length.active() as u8) << 1
+            | (self.triangle.length.active() as u8) << 2
+            | (self.noise.length.active() as u8) << 3
+            | (self.dmc.active() as u8) << 4
+            | (self.frame_counter.irq() as u8) << 6
+            | (self.dmc.irq() as u8) << 7;
+        self.frame_counter.acknowledge();
+        status
+    }
+
+    pub fn irq_pending(&self) -> bool {
+        self.frame_counter.irq() || self.dmc.irq()
+    }
+
+    // the sample byte the DMC wants next, the bus fetches it and stalls the CPU
+    pub fn dmc_dma_request(&self) -> Option<u16> {
+        self.dmc.dma_request()
+    }
+
+    pub fn dmc_dma_fill(&mut self, data: u8) {
+        self.dmc.fill(data);
+    }
+
+    // one CPU cycle, the pulse timers run at half that
+    pub fn tick(&mut self) {
+        self.cycles += 1;
+        if self.cycles.is_multiple_of(2) {
+            self.pulse1.clock_timer();
+            self.pulse2.clock_timer();
+        }
+        self.triangle.clock_timer();
+        self.noise.clock_timer();
+        self.dmc.clock_timer();
+
+        match self.frame_counter.clock(self.region.frame_steps()) {
+            FrameClock::Quarter => self.quarter_frame(),
+            FrameClock::Half => {
+                self.quarter_frame();
+                self.half_frame();
+            }
+            FrameClock::None => {}
+        }
+    }
+
+    // envelopes and the triangle's linear counter
+    fn quarter_frame(&mut self) {
+        self.pulse1.envelope.clock();
+        self.pulse2.envelope.clock();
+        self.noise.envelope.clock();
+        self.triangle.clock_linear_counter();
+    }
+
+    // length counters and sweeps
+    fn half_frame(&mut self) {
+        self.pulse1.length.clock();
+        self.pulse2.length.clock();
+        self.triangle.length.clock();
+        self.noise.length.clock();
+        self.pulse1.clock_sweep();
+        self.pulse2.clock_sweep();
+    }
+
+    // per channel volume in the order of channel_outputs, 1.0 is normal
+    pub fn set_gains(&mut self, gains: &[f32]) {
+        for (gain, value) in self.gains.iter_mut().zip(gains) {
+            *gain = *value;
+        }
+    }
+
+    // 0.0 to about 1.0
+    pub fn output(&self) -> f32 {
+        if self.gains == [1.0; 5] {
+            let [pulse1, pulse2, triangle, noise, dmc] = self.channel_outputs();
+            return mixer::mix(pulse1, pulse2, triangle, noise, dmc);
+        }
+        let [pulse1, pulse2, triangle, noise, dmc] = self.channel_levels();
+        mixer::mix_levels(pulse1, pulse2, triangle, noise, dmc)
+    }
+
+    // channel_outputs with the volumes applied
+    pub fn channel_levels(&self) -> [f32; 5] {
+        let outputs = self.channel_outputs();
+        std::array::from_fn(|i| outputs[i] as f32 * self.gains[i])
+    }
+
+    // the raw levels of pulse 1, pulse 2, triangle, noise and DMC
+    pub fn channel_outputs(&self) -> [u8; 5] {
+        [
+            self.pulse1.output(),
+            self.pulse2.output(),
+            self.triangle.output(),
+            self.noise.output(),
+            self.dmc.output(),
+        ]
+    }
+}
+
+impl Default for APU {
+    fn default() -> Self {
+        APU::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_status_and_enable() {
+        let mut apu = APU::new();
+        // length loads are ignored while the channel is off
+        apu.write_register(0x4003, 0x08);
+        assert_eq!(apu.read_status(), 0);
+
+        apu.write_register(0x4015, 0b1111);
+        apu.write_register(0x4003, 0x08);
+        apu.write_register(0x400B, 0x08);
+        apu.write_register(0x400F, 0x08);
+        assert_eq!(apu.read_status(), 0b1101);
+
+        apu.write_register(0x4015, 0b1001);
+        assert_eq!(apu.read_status(), 0b1001);
+    }
+
+    #[test]
+    fn test_length_counter_runs_out() {
+        let mut apu = APU::new();
+        apu.write_register(0x4015, 0b0001);
+        // index 3 is a length of 2
+        apu.write_register(0x4003, 3 << 3);
+        apu.half_frame();
+        assert_eq!(apu.read_status(), 1);
+        apu.half_frame();
+        assert_eq!(apu.read_status(), 0);
+
+        // halted counters keep their value
+        apu.write_register(0x4000, 0x20);
+        apu.write_register(0x4003, 3 << 3);
+        apu.half_frame();
+        apu.half_frame();
+        assert_eq!(apu.read_status(), 1);
+    }
+
+    #[test]
+    fn test_frame_irq() {
+        let mut apu = APU::new();
+        for _ in 0..29829 {
+            apu.tick();
+        }
+        assert!(apu.irq_pending());
+        assert_eq!(apu.read_status() & 0x40, 0x40);
+        assert!(!apu.irq_pending());
+        assert_eq!(apu.read_status() & 0x40, 0);
+
+        apu.write_register(0x4017, 0x40);
+        for _ in 0..29830 {
+            apu.tick();
+        }
+        assert!(!apu.irq_pending());
+    }
+
+    #[test]
+    fn test_dmc_irq_in_status() {
+        let mut apu = APU::new();
+        apu.write_register(0x4010, 0x80);
+        apu.write_register(0x4013, 0);
+        apu.write_register(0x4015, 0b1_0000);
+        assert_eq!(apu.read_status(), 0b0001_0000);
+        assert_eq!(apu.dmc_dma_request(), Some(0xC000));
+        apu.dmc_dma_fill(0x55);
+        assert_eq!(apu.dmc_dma_request(), None);
+        assert!(apu.irq_pending());
+        // unlike the frame IRQ, reading doesn't acknowledge it
+        assert_eq!(apu.read_status(), 0b1000_0000);
+        assert_eq!(apu.read_status(), 0b1000_0000);
+        apu.write_register(0x4015, 0);
+        assert!(!apu.irq_pending());
+    }
+
+    #[test]
+    fn test_dmc_dma_stalls_the_cpu() {
+        let mut bus = crate::bus::Bus::new(crate::cartridge::test::test_rom()).unwrap();
+        bus.write_mem(0x4013, 1);
+        bus.write_mem(0x4015, 0b1_0000);
+        bus.tick(1);
+        assert_eq!(bus.cycles, 1 + 4);
+        // the second byte waits until the first one is shifted out
+        bus.tick(100);
+        assert_eq!(bus.cycles, 1 + 4 + 100);
+        assert!(bus.apu.read_status() & 0b1_0000 != 0);
+    }
+
+    #[test]
+    fn test_pulse_output() {
+        let mut apu = APU::new();
+        apu.write_register(0x4015, 0b0001);
+        apu.write_register(0x4000, 0b1011_1111);
+        apu.write_register(0x4002, 0x40);
+        apu.write_register(0x4003, 0x08);
+        let mut high = 0;
+        for _ in 0..(0x41 * 2 * 8) {
+            apu.tick();
+            if apu.pulse1.output() > 0 {
+                high += 1;
+            }
+        }
+        // duty 2 is high half the time
+        assert_eq!(high, 0x41 * 2 * 4);
+    }
+
+    #[test]
+    fn test_channel_gains() {
+        let mut apu = APU::new();
+        apu.write_register(0x4011, 100);
+        let full = apu.output();
+        apu.set_gains(&[1.0, 1.0, 1.0, 1.0, 0.5]);
+        assert_eq!(apu.channel_levels()[4], 50.0);
+        assert!(apu.output() < full && apu.output() > 0.0);
+        // the triangle idles at a level of its own
+        apu.set_gains(&[1.0, 1.0, 0.0, 1.0, 0.0]);
+        assert_eq!(apu.output(), 0.0);
+    }
+}
@@ -0,0 +1,98 @@
+use crate::apu::envelope::Envelope;
+use crate::apu::length_counter::LengthCounter;
+
+// The noise channel, $400C-$400F.
+//
+// --LC VVVV   length halt / envelope loop, envelope
+// M--- PPPP   mode, period index ($400E)
+// LLLL L---   length load ($400F)
+//
+// A 15 bit LFSR shifted once per timer period. The feedback is bit 0 xor
+// bit 1, or bit 0 xor bit 6 in mode 1 which gives a short loop of 93 (or 31)
+// steps. The channel is silent while bit 0 is set.
+pub struct Noise {
+    pub envelope: Envelope,
+    pub length: LengthCounter,
+    short_mode: bool,
+    period: u16,
+    timer: u16,
+    shift: u16,
+}
+
+impl Noise {
+    pub fn new() -> Self {
+        Noise {
+            envelope: Envelope::new(),
+            length: LengthCounter::new(),
+            short_mode: false,
+            period: 4,
+            timer: 0,
+            shift: 1,
+        }
+    }
+
+    // the periods come from the region's table
+    pub fn write(&mut self, reg: u16, data: u8, periods: &[u16; 16]) {
+        match reg {
+            0 => {
+                self.length.halt = data & 0x20 != 0;
+                self.envelope.write(data);
+            }
+            1 => {}
+            2 => {
+                self.short_mode = data & 0x80 != 0;
+                self.period = periods[(data & 0x0F) as usize];
+            }
+            _ => {
+                self.length.load(data);
+                self.envelope.restart();
+            }
+        }
+    }
+
+    // every CPU cycle, the periods are in CPU cycles
+    pub fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.period - 1;
+            let tap = if self.short_mode { 6 } else { 1 };
+            let feedback = (self.shift ^ (self.shift >> tap)) & 1;
+            self.shift = (self.shift >> 1) | (feedback << 14);
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    pub fn output(&self) -> u8 {
+        if !self.length.active() || self.shift & 1 != 0 {
+            0
+        } else {
+            self.envelope.volume()
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn loop_length(short_mode: bool) -> usize {
+        let mut noise = Noise::new();
+        noise.short_mode = short_mode;
+        noise.period = 1;
+        let start = noise.shift;
+        let mut steps = 0;
+        loop {
+            noise.clock_timer();
+            steps += 1;
+            if noise.shift == start {
+                return steps;
+            }
+        }
+    }
+
+    #[test]
+    fn test_lfsr_modes() {
+        assert_eq!(loop_length(false), 32767);
+        assert_eq!(loop_length(true), 93);
+    }
+}
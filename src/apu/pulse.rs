@@ -0,0 +1,182 @@
+use crate::apu::envelope::Envelope;
+use crate::apu::length_counter::LengthCounter;
+
+// The two square channels, $4000-$4003 and $4004-$4007.
+//
+// DDLC VVVV   duty, length halt / envelope loop, envelope
+// EPPP NSSS   sweep enable, divider period, negate, shift
+// TTTT TTTT   timer low
+// LLLL LTTT   length load, timer high
+//
+// The timer runs every other CPU cycle and steps through the 8 step duty
+// sequence. The sweep unit moves the period every half frame. Pulse 1 negates
+// with ones' complement and pulse 2 with two's complement, so with the same
+// settings pulse 1 ends up one lower.
+const DUTY_TABLE: [[u8; 8]; 4] = [
+    [0, 1, 0, 0, 0, 0, 0, 0],
+    [0, 1, 1, 0, 0, 0, 0, 0],
+    [0, 1, 1, 1, 1, 0, 0, 0],
+    [1, 0, 0, 1, 1, 1, 1, 1],
+];
+
+pub struct Pulse {
+    ones_complement: bool,
+    pub envelope: Envelope,
+    pub length: LengthCounter,
+    duty: u8,
+    step: u8,
+    period: u16,
+    timer: u16,
+
+    sweep_enabled: bool,
+    sweep_period: u8,
+    sweep_negate: bool,
+    sweep_shift: u8,
+    sweep_divider: u8,
+    sweep_reload: bool,
+}
+
+impl Pulse {
+    // channel 1 or 2
+    pub fn new(channel: u8) -> Self {
+        Pulse {
+            ones_complement: channel == 1,
+            envelope: Envelope::new(),
+            length: LengthCounter::new(),
+            duty: 0,
+            step: 0,
+            period: 0,
+            timer: 0,
+            sweep_enabled: false,
+            sweep_period: 0,
+            sweep_negate: false,
+            sweep_shift: 0,
+            sweep_divider: 0,
+            sweep_reload: false,
+        }
+    }
+
+    pub fn write(&mut self, reg: u16, data: u8) {
+        match reg {
+            0 => {
+                self.duty = data >> 6;
+                self.length.halt = data & 0x20 != 0;
+                self.envelope.write(data);
+            }
+            1 => {
+                self.sweep_enabled = data & 0x80 != 0;
+                self.sweep_period = (data >> 4) & 0b111;
+                self.sweep_negate = data & 0x08 != 0;
+                self.sweep_shift = data & 0b111;
+                self.sweep_reload = true;
+            }
+            2 => self.period = (self.period & 0x0700) | data as u16,
+            _ => {
+                self.period = (self.period & 0x00FF) | ((data & 0b111) as u16) << 8;
+                self.length.load(data);
+                self.envelope.restart();
+                self.step = 0;
+            }
+        }
+    }
+
+    // every other CPU cycle
+    pub fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.period;
+            self.step = (self.step + 1) & 7;
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    fn target_period(&self) -> u16 {
+        let change = self.period >> self.sweep_shift;
+        if !self.sweep_negate {
+            self.period + change
+        } else if self.ones_complement {
+            self.period.saturating_sub(change + 1)
+        } else {
+            self.period.saturating_sub(change)
+        }
+    }
+
+    // the sweep mutes the channel even when it's disabled
+    fn muted(&self) -> bool {
+        self.period < 8 || self.target_period() > 0x7FF
+    }
+
+    pub fn clock_sweep(&mut self) {
+        if self.sweep_divider == 0 && self.sweep_enabled && self.sweep_shift > 0 && !self.muted() {
+            self.period = self.target_period();
+        }
+        if self.sweep_divider == 0 || self.sweep_reload {
+            self.sweep_divider = self.sweep_period;
+            self.sweep_reload = false;
+        } else {
+            self.sweep_divider -= 1;
+        }
+    }
+
+    pub fn output(&self) -> u8 {
+        if !self.length.active() || self.muted() || DUTY_TABLE[self.duty as usize][self.step as usize] == 0 {
+            0
+        } else {
+            self.envelope.volume()
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn pulse(channel: u8, period: u16) -> Pulse {
+        let mut pulse = Pulse::new(channel);
+        pulse.length.set_enabled(true);
+        pulse.write(0, 0b1011_1111);
+        pulse.write(1, 0b1000_1001);
+        pulse.write(2, period as u8);
+        pulse.write(3, (period >> 8) as u8);
+        pulse
+    }
+
+    #[test]
+    fn test_sweep_negate_differs() {
+        let mut pulse1 = pulse(1, 0x100);
+        let mut pulse2 = pulse(2, 0x100);
+        pulse1.clock_sweep();
+        pulse2.clock_sweep();
+        assert_eq!(pulse1.period, 0x100 - 0x80 - 1);
+        assert_eq!(pulse2.period, 0x100 - 0x80);
+    }
+
+    #[test]
+    fn test_sweep_mutes() {
+        let mut pulse = pulse(2, 0x7F0);
+        pulse.write(1, 0b0000_0001);
+        // the target overflows even with the sweep off
+        assert!(pulse.muted());
+        pulse.write(2, 0x05);
+        pulse.write(3, 0x00);
+        assert!(pulse.muted());
+        pulse.write(2, 0x08);
+        assert!(!pulse.muted());
+        pulse.clock_timer();
+        assert_eq!(pulse.output(), 15);
+    }
+
+    #[test]
+    fn test_duty_sequence() {
+        let mut pulse = pulse(2, 0x10);
+        let mut out = vec![];
+        for _ in 0..8 {
+            out.push(pulse.output());
+            for _ in 0..=0x10 {
+                pulse.clock_timer();
+            }
+        }
+        // the write to $4003 restarted the sequence
+        assert_eq!(out, [0, 15, 15, 15, 15, 0, 0, 0]);
+    }
+}
@@ -0,0 +1,129 @@
+use std::collections::VecDeque;
+use std::f64::consts::PI;
+
+// Band-limited resampling from the CPU clock to the host rate.
+//
+// The APU output is a staircase that changes at most once per CPU cycle.
+// Each change is added as an impulse of its size, spread over WIDTH output
+// samples by a windowed sinc at the sub-sample position it happened at, and
+// the output is the running sum of those. That's a band-limited step, so
+// nothing above the host's Nyquist rate folds back. The steps come out
+// WIDTH / 2 samples late.
+
+const WIDTH: usize = 16;
+const PHASES: usize = 64;
+// a bit below Nyquist, the sinc's transition band needs the room
+const CUTOFF: f64 = 0.45;
+
+fn kernel() -> Vec<[f32; WIDTH]> {
+    let mut kernel = vec![[0.0; WIDTH]; PHASES];
+    for (phase, taps) in kernel.iter_mut().enumerate() {
+        let offset = phase as f64 / PHASES as f64;
+        let mut sum = 0.0;
+        for (k, tap) in taps.iter_mut().enumerate() {
+            let x = k as f64 - (WIDTH / 2) as f64 - offset;
+            let sinc = if x == 0.0 { 1.0 } else { (2.0 * PI * CUTOFF * x).sin() / (2.0 * PI * CUTOFF * x) };
+            // Blackman window over the kernel
+            let w = (x + (WIDTH / 2) as f64) / WIDTH as f64;
+            let window = 0.42 - 0.5 * (2.0 * PI * w).cos() + 0.08 * (4.0 * PI * w).cos();
+            *tap = (sinc * window) as f32;
+            sum += *tap as f64;
+        }
+        // every step has to add up to its full size
+        for tap in taps.iter_mut() {
+            *tap = (*tap as f64 / sum) as f32;
+        }
+    }
+    kernel
+}
+
+pub struct Resampler {
+    clock_rate: f64,
+    sample_rate: f64,
+    // output samples per input clock
+    step: f64,
+    // position of the current clock, in output samples from deltas[0]
+    time: f64,
+    last: f32,
+    integrator: f32,
+    deltas: VecDeque<f32>,
+    kernel: Vec<[f32; WIDTH]>,
+}
+
+impl Resampler {
+    pub fn new(clock_rate: f64, sample_rate: f64) -> Self {
+        Resampler {
+            clock_rate,
+            sample_rate,
+            step: sample_rate / clock_rate,
+            time: 0.0,
+            last: 0.0,
+            integrator: 0.0,
+            deltas: VecDeque::from(vec![0.0; WIDTH + 1]),
+            kernel: kernel(),
+        }
+    }
+
+    // scales the output rate, slightly above 1.0 makes more samples
+    pub fn set_rate_adjustment(&mut self, ratio: f64) {
+        self.step = self.sample_rate * ratio / self.clock_rate;
+    }
+
+    // one input clock, calls out with every sample that's done
+    pub fn clock(&mut self, level: f32, mut out: impl FnMut(f32)) {
+        if level != self.last {
+            let delta = level - self.last;
+            self.last = level;
+            let start = self.time as usize;
+            let phase = ((self.time - start as f64) * PHASES as f64) as usize;
+            for (k, tap) in self.kernel[phase].iter().enumerate() {
+                self.deltas[start + k] += delta * tap;
+            }
+        }
+
+        self.time += self.step;
+        // nothing lands before floor(time) anymore
+        while self.time >= 1.0 {
+            self.time -= 1.0;
+            self.integrator += self.deltas.pop_front().unwrap();
+            self.deltas.push_back(0.0);
+            out(self.integrator);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn resample(levels: impl Iterator<Item = f32>) -> Vec<f32> {
+        let mut resampler = Resampler::new(1_789_773.0, 48_000.0);
+        let mut out = vec![];
+        for level in levels {
+            resampler.clock(level, |sample| out.push(sample));
+        }
+        out
+    }
+
+    #[test]
+    fn test_sample_count() {
+        let out = resample(std::iter::repeat_n(0.0, 1_789_773));
+        assert!((47_999..=48_001).contains(&out.len()));
+    }
+
+    #[test]
+    fn test_step_settles() {
+        let out = resample(std::iter::repeat_n(1.0, 10_000));
+        // the kernel overshoots a little around the edge, then it's flat
+        assert!(out[..3].iter().all(|sample| sample.abs() < 0.01));
+        assert!(out[16..].iter().all(|sample| (sample - 1.0).abs() < 0.001));
+        assert!(out.iter().all(|sample| *sample < 1.1));
+    }
+
+    #[test]
+    fn test_ultrasonic_square_is_filtered() {
+        // a 100 kHz square way above Nyquist averages out
+        let out = resample((0..100_000).map(|i| if (i / 9) % 2 == 0 { 1.0 } else { 0.0 }));
+        assert!(out[20..].iter().all(|sample| (sample - 0.5).abs() < 0.1));
+    }
+}
@@ -0,0 +1,138 @@
+use std::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+use std::sync::Arc;
+
+// Single producer, single consumer sample queue between the emulator and the
+// host's audio callback, without locks. The samples are kept as f32 bits in
+// atomics. One slot stays free to tell full from empty.
+
+struct Ring {
+    samples: Box<[AtomicU32]>,
+    // next slot the consumer reads
+    head: AtomicUsize,
+    // next slot the producer writes
+    tail: AtomicUsize,
+}
+
+impl Ring {
+    fn len(&self) -> usize {
+        let head = self.head.load(Ordering::Acquire);
+        let tail = self.tail.load(Ordering::Acquire);
+        (tail + self.samples.len() - head) % self.samples.len()
+    }
+
+    fn capacity(&self) -> usize {
+        self.samples.len() - 1
+    }
+}
+
+pub struct AudioProducer {
+    ring: Arc<Ring>,
+}
+
+pub struct AudioConsumer {
+    ring: Arc<Ring>,
+}
+
+pub fn audio_ring(capacity: usize) -> (AudioProducer, AudioConsumer) {
+    let ring = Arc::new(Ring {
+        samples: (0..capacity + 1).map(|_| AtomicU32::new(0)).collect(),
+        head: AtomicUsize::new(0),
+        tail: AtomicUsize::new(0),
+    });
+    (AudioProducer { ring: ring.clone() }, AudioConsumer { ring })
+}
+
+impl AudioProducer {
+    // false when the consumer is behind and the sample was dropped
+    pub fn push(&mut self, sample: f32) -> bool {
+        let ring = &self.ring;
+        let tail = ring.tail.load(Ordering::Relaxed);
+        let next = (tail + 1) % ring.samples.len();
+        if next == ring.head.load(Ordering::Acquire) {
+            return false;
+        }
+        ring.samples[tail].store(sample.to_bits(), Ordering::Relaxed);
+        ring.tail.store(next, Ordering::Release);
+        true
+    }
+
+    // 0.0 empty to 1.0 full, what rate control steers by
+    pub fn fill_level(&self) -> f32 {
+        self.ring.len() as f32 / self.ring.capacity() as f32
+    }
+}
+
+impl AudioConsumer {
+    // fills as much of out as there are samples, returns how many
+    pub fn pop_into(&mut self, out: &mut [f32]) -> usize {
+        let ring = &self.ring;
+        let mut head = ring.head.load(Ordering::Relaxed);
+        let tail = ring.tail.load(Ordering::Acquire);
+        let mut count = 0;
+        while head != tail && count < out.len() {
+            out[count] = f32::from_bits(ring.samples[head].load(Ordering::Relaxed));
+            head = (head + 1) % ring.samples.len();
+            count += 1;
+        }
+        ring.head.store(head, Ordering::Release);
+        count
+    }
+
+    pub fn len(&self) -> usize {
+        self.ring.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn fill_level(&self) -> f32 {
+        self.ring.len() as f32 / self.ring.capacity() as f32
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_push_and_pop() {
+        let (mut producer, mut consumer) = audio_ring(4);
+        for i in 0..4 {
+            assert!(producer.push(i as f32));
+        }
+        assert!(!producer.push(4.0));
+        assert_eq!(producer.fill_level(), 1.0);
+
+        let mut out = [0.0; 3];
+        assert_eq!(consumer.pop_into(&mut out), 3);
+        assert_eq!(out, [0.0, 1.0, 2.0]);
+        assert!(producer.push(5.0));
+        assert_eq!(consumer.pop_into(&mut out), 2);
+        assert_eq!(out[..2], [3.0, 5.0]);
+        assert!(consumer.is_empty());
+    }
+
+    #[test]
+    fn test_across_threads() {
+        let (mut producer, mut consumer) = audio_ring(64);
+        let thread = std::thread::spawn(move || {
+            let mut i = 0;
+            while i < 10_000 {
+                if producer.push(i as f32) {
+                    i += 1;
+                }
+            }
+        });
+        let mut expected = 0;
+        let mut out = [0.0; 16];
+        while expected < 10_000 {
+            let count = consumer.pop_into(&mut out);
+            for sample in &out[..count] {
+                assert_eq!(*sample, expected as f32);
+                expected += 1;
+            }
+        }
+        thread.join().unwrap();
+    }
+}
@@ -0,0 +1,118 @@
+use crate::apu::length_counter::LengthCounter;
+
+// The triangle channel, $4008-$400B.
+//
+// CRRR RRRR   length halt / linear counter control, linear counter reload
+// TTTT TTTT   timer low ($400A)
+// LLLL LTTT   length load, timer high ($400B)
+//
+// The timer runs every CPU cycle and steps a 32 step triangle, but only while
+// both the linear counter and the length counter are non-zero. Stopping
+// leaves the output where it was instead of dropping to 0.
+const SEQUENCE: [u8; 32] = [
+    15, 14, 13, 12, 11, 10, 9, 8, 7, 6, 5, 4, 3, 2, 1, 0,
+    0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15,
+];
+
+pub struct Triangle {
+    pub length: LengthCounter,
+    control: bool,
+    linear_reload_value: u8,
+    linear_counter: u8,
+    linear_reload: bool,
+    period: u16,
+    timer: u16,
+    step: u8,
+}
+
+impl Triangle {
+    pub fn new() -> Self {
+        Triangle {
+            length: LengthCounter::new(),
+            control: false,
+            linear_reload_value: 0,
+            linear_counter: 0,
+            linear_reload: false,
+            period: 0,
+            timer: 0,
+            step: 0,
+        }
+    }
+
+    pub fn write(&mut self, reg: u16, data: u8) {
+        match reg {
+            0 => {
+                self.control = data & 0x80 != 0;
+                self.length.halt = self.control;
+                self.linear_reload_value = data & 0x7F;
+            }
+            1 => {}
+            2 => self.period = (self.period & 0x0700) | data as u16,
+            _ => {
+                self.period = (self.period & 0x00FF) | ((data & 0b111) as u16) << 8;
+                self.length.load(data);
+                self.linear_reload = true;
+            }
+        }
+    }
+
+    pub fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.period;
+            // periods below 2 are ultrasonic and just pop, hold the step instead
+            if self.linear_counter > 0 && self.length.active() && self.period >= 2 {
+                self.step = (self.step + 1) & 31;
+            }
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    // every quarter frame
+    pub fn clock_linear_counter(&mut self) {
+        if self.linear_reload {
+            self.linear_counter = self.linear_reload_value;
+        } else if self.linear_counter > 0 {
+            self.linear_counter -= 1;
+        }
+        if !self.control {
+            self.linear_reload = false;
+        }
+    }
+
+    pub fn output(&self) -> u8 {
+        SEQUENCE[self.step as usize]
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_linear_counter_gates_the_sequence() {
+        let mut triangle = Triangle::new();
+        triangle.length.set_enabled(true);
+        triangle.write(0, 2);
+        triangle.write(2, 3);
+        triangle.write(3, 0x08);
+        for _ in 0..8 {
+            triangle.clock_timer();
+        }
+        // not reloaded yet
+        assert_eq!(triangle.output(), 15);
+
+        triangle.clock_linear_counter();
+        for _ in 0..8 {
+            triangle.clock_timer();
+        }
+        assert_eq!(triangle.output(), 13);
+
+        triangle.clock_linear_counter();
+        triangle.clock_linear_counter();
+        for _ in 0..8 {
+            triangle.clock_timer();
+        }
+        assert_eq!(triangle.output(), 13);
+    }
+}
@@ -1,12 +1,39 @@
-use crate::cartridge::Rom;
+use crate::apu::audio::AudioOutput;
+use crate::apu::capture::AudioCapture;
+use crate::apu::channels::{ChannelControls, APU_CHANNELS};
+use crate::apu::nes_apu::APU;
+use crate::cartridge::{Rom, RomError};
+use crate::input::joypad::JoypadButton;
+use crate::input::{self, InputDeviceRef, InputPort};
+use crate::mapper::{self, MapperRef};
 use crate::ppu::nes_ppu::PPU;
+use crate::region::Region;
+use crate::save::SaveStorage;
+use crate::vs::{VsPpu, VsSystem};
 
 pub struct Bus{
     cpu_vram: [u8; 2048],
-    prg_rom: Vec<u8>,
+    mapper: MapperRef,
     pub ppu: PPU,
+    pub apu: APU,
+    ports: [Option<InputDeviceRef>; 2],
+    expansion_port: Option<InputDeviceRef>,
 
     pub cycles: usize,
+    frames: usize,
+    region: Region,
+    // master clocks the PPU is behind the CPU
+    master_clock: usize,
+    save_storage: Option<Box<dyn SaveStorage>>,
+    vs: Option<VsSystem>,
+    // a controller port read by the instruction being clocked
+    controller_read: Option<u16>,
+    // a $4014 write waiting for the instruction to finish
+    oam_dma_pending: bool,
+    oam_dma_active: bool,
+    audio: Option<AudioOutput>,
+    capture: Option<AudioCapture>,
+    channels: ChannelControls,
 }
 
 
@@ -15,36 +42,215 @@ const RAM_MIRRORS_END: u16 = 0x1FFF;
 const _PPU_REGISTERS: u16 = 0x2000;
 const PPU_REGISTERS_MIRRORS_END: u16 = 0x3FFF;
 
+// CPU cycles a DMC sample fetch takes away: halt, dummy, alignment and read
+const DMC_DMA_CYCLES: u8 = 4;
+// during OAM DMA the halt and alignment are already paid for
+const DMC_DMA_CYCLES_IN_OAM_DMA: u8 = 2;
+
+// 256 reads and writes plus the halt, and one more to align on an odd cycle
+const OAM_DMA_CYCLES: usize = 513;
+
+// battery RAM is also written back every 5 seconds, not only on exit
+const SAVE_FLUSH_FRAMES: usize = 300;
+
 impl Bus {
-    pub fn new(rom: Rom) -> Self{
-        let ppu = PPU::new(rom.chr_rom.clone(), rom.screen_mirroring);
+    pub fn new(rom: Rom) -> Result<Self, RomError> {
+        let vs = VsSystem::new(&rom);
+        let vs_ppu = VsPpu::of(&rom);
+        let region = Region::of(&rom);
+        let [port1, port2, expansion] = input::default_devices(rom.expansion_device);
+        let mut bus = Bus::with_mapper(mapper::new_mapper(rom)?);
+        if let Some(ppu) = vs_ppu {
+            bus.ppu.set_vs_ppu(ppu);
+        }
+        bus.vs = vs;
+        bus.set_region(region);
+        bus.connect_input(InputPort::Port1, port1);
+        bus.connect_input(InputPort::Port2, port2);
+        bus.connect_input(InputPort::Expansion, expansion);
+        Ok(bus)
+    }
+
+    pub fn with_mapper(mapper: MapperRef) -> Self{
+        let ppu = PPU::new(mapper.clone());
+        let channels = ChannelControls::new(&mapper.borrow().expansion_channels());
         Bus {
             cpu_vram: [0; 2048],
-            prg_rom: rom.prg_rom,
+            mapper,
             ppu: ppu,
+            apu: APU::new(),
+            ports: [Some(input::joypad()), Some(input::joypad())],
+            expansion_port: None,
 
             cycles: 0,
+            frames: 0,
+            region: Region::Ntsc,
+            master_clock: 0,
+            save_storage: None,
+            vs: None,
+            controller_read: None,
+            oam_dma_pending: false,
+            oam_dma_active: false,
+            audio: None,
+            capture: None,
+            channels,
+        }
+    }
+
+    // Loads the save of a battery backed cartridge (or the changes to a disk)
+    // from the storage and keeps the storage up to date from then on.
+    // Does nothing for carts without a battery.
+    pub fn set_save_storage(&mut self, mut storage: Box<dyn SaveStorage>) {
+        let mut mapper = self.mapper.borrow_mut();
+        if !mapper.has_save() {
+            return;
         }
+        match storage.load() {
+            Ok(Some(save)) => mapper.load_save(&save),
+            Ok(None) => {}
+            Err(e) => println!("Failed to load save: {}", e),
+        }
+        drop(mapper);
+        self.save_storage = Some(storage);
     }
 
-    fn read_prg_rom(&self, mut addr: u16) -> u8 {
-        addr -= 0x8000;
-        if self.prg_rom.len() == 0x4000 && addr >= 0x4000 {
-            //mirror if needed
-            addr = addr % 0x4000;
+    pub fn flush_save_ram(&mut self) {
+        let storage = match self.save_storage.as_mut() {
+            Some(storage) => storage,
+            None => return,
+        };
+        if let Some(save) = self.mapper.borrow_mut().take_save() {
+            if let Err(e) = storage.save(&save) {
+                println!("Failed to write save: {}", e);
+            }
         }
-        self.prg_rom[addr as usize]
+    }
+
+    pub fn region(&self) -> Region {
+        self.region
+    }
+
+    // overrides the region the cart asked for
+    pub fn set_region(&mut self, region: Region) {
+        self.region = region;
+        self.master_clock = 0;
+        self.ppu.set_region(region);
+        self.apu.set_region(region);
+    }
+
+    // where the APU and expansion sound go, None runs silent
+    pub fn set_audio_output(&mut self, audio: Option<AudioOutput>) {
+        self.audio = audio;
+    }
+
+    // records the audio from now on, replacing a running capture
+    pub fn start_audio_capture(&mut self, capture: AudioCapture) {
+        self.capture = Some(capture);
+    }
+
+    // the capture to finish(), None if none was running
+    pub fn stop_audio_capture(&mut self) -> Option<AudioCapture> {
+        self.capture.take()
+    }
+
+    // the APU channels and then the cartridge's, the index for the setters below
+    pub fn audio_channels(&self) -> &[&'static str] {
+        self.channels.names()
+    }
+
+    pub fn channel_volume(&self, channel: usize) -> f32 {
+        self.channels.volume(channel)
+    }
+
+    pub fn set_channel_volume(&mut self, channel: usize, volume: f32) {
+        self.channels.set_volume(channel, volume);
+        self.apply_channel_gains();
+    }
+
+    pub fn channel_muted(&self, channel: usize) -> bool {
+        self.channels.muted(channel)
+    }
+
+    pub fn set_channel_muted(&mut self, channel: usize, muted: bool) {
+        self.channels.set_muted(channel, muted);
+        self.apply_channel_gains();
+    }
+
+    pub fn channel_solo(&self, channel: usize) -> bool {
+        self.channels.solo(channel)
+    }
+
+    pub fn set_channel_solo(&mut self, channel: usize, solo: bool) {
+        self.channels.set_solo(channel, solo);
+        self.apply_channel_gains();
+    }
+
+    fn apply_channel_gains(&mut self) {
+        let gains = self.channels.gains();
+        let (apu, expansion) = gains.split_at(APU_CHANNELS.len());
+        self.apu.set_gains(apu);
+        self.mapper.borrow_mut().set_expansion_gains(expansion);
+    }
+
+    // frames completed since power on
+    pub fn frame_count(&self) -> usize {
+        self.frames
+    }
+
+    // plugs a device in, replacing what was there, None leaves the port empty
+    pub fn connect_input(&mut self, port: InputPort, device: Option<InputDeviceRef>) {
+        match port {
+            InputPort::Port1 => self.ports[0] = device,
+            InputPort::Port2 => self.ports[1] = device,
+            InputPort::Expansion => self.expansion_port = device,
+        }
+    }
+
+    pub fn input_device(&self, port: InputPort) -> Option<InputDeviceRef> {
+        match port {
+            InputPort::Port1 => self.ports[0].clone(),
+            InputPort::Port2 => self.ports[1].clone(),
+            InputPort::Expansion => self.expansion_port.clone(),
+        }
+    }
+
+    // the buttons a player holds, set before each frame. Players 0 and 1 are
+    // the pads in the two ports, 2 and 3 the ones behind a Four Score or on
+    // the expansion port.
+    pub fn set_joypad_buttons(&mut self, player: usize, buttons: JoypadButton) {
+        let device = match player {
+            0 | 1 => self.ports[player].as_ref(),
+            _ => self.expansion_port.as_ref().or_else(|| self.ports.get(player - 2).and_then(Option::as_ref)),
+        };
+        if let Some(device) = device {
+            device.borrow_mut().set_player_buttons(player, buttons);
+        }
+    }
+
+    pub fn disk_sides(&self) -> usize {
+        self.mapper.borrow().disk_sides()
+    }
+
+    pub fn insert_disk(&mut self, side: Option<usize>) {
+        self.mapper.borrow_mut().insert_disk(side);
+    }
+
+    // DIP switches and coin slots of a Vs. System cabinet
+    pub fn vs_system(&mut self) -> Option<&mut VsSystem> {
+        self.vs.as_mut()
     }
 
     pub fn read_mem(&mut self, addr: u16) -> u8 {
+        if let 0x4016 | 0x4017 = addr {
+            self.controller_read = Some(addr);
+        }
         match addr {
             RAM..=RAM_MIRRORS_END => {
                 let mirror_down_addr = addr & 0b00000111_11111111;
                 self.cpu_vram[mirror_down_addr as usize]
             }
-            0x2000 | 0x2001 | 0x2003 | 0x2005 | 0x2006 | 0x4014 => {
-                panic!("Attempt to read from write-only PPU address {:x}", addr);
-            }
+            // write-only, a CPU or OAM DMA read gets the open bus
+            0x2000 | 0x2001 | 0x2003 | 0x2005 | 0x2006 | 0x4014 => (addr >> 8) as u8,
 
             0x2002 => self.ppu.read_status(),
 
@@ -56,12 +262,32 @@ impl Bus {
                 let mirror_down_addr = addr & 0b00100000_00000111;
                 self.read_mem(mirror_down_addr)
             }
-            0x8000..=0xFFFF => self.read_prg_rom(addr),
+            0x4015 => self.apu.read_status(),
 
-            _ => {
-                println!("Ignoring mem access at {}", addr);
-                0
+            // the APU registers besides $4015 are write-only, $4018-$401F are the test mode ones
+            0x4000..=0x4013 | 0x4018..=0x401F => (addr >> 8) as u8,
+
+            0x4016 | 0x4017 => {
+                let port = (addr - 0x4016) as usize;
+                let mut data = 0;
+                let devices = [self.ports[port].clone(), self.expansion_port.clone()];
+                for device in devices.iter().flatten() {
+                    let mut device = device.borrow_mut();
+                    device.sense_light(&mut self.ppu);
+                    data |= device.read(port);
+                }
+                match self.vs.as_ref() {
+                    Some(vs) if addr == 0x4016 => vs.read_4016() | data,
+                    Some(vs) => vs.read_4017() | data,
+                    // the upper bits are whatever was last on the data bus
+                    None => (addr >> 8) as u8 & 0xE0 | data,
+                }
             }
+            0x4020..=0x5FFF => self.mapper.borrow_mut().read_expansion(addr).unwrap_or((addr >> 8) as u8),
+
+            0x6000..=0x7FFF => self.mapper.borrow_mut().read_prg_ram(addr).unwrap_or((addr >> 8) as u8),
+
+            0x8000..=0xFFFF => self.mapper.borrow_mut().read_prg(addr),
         }
     }
 
@@ -74,12 +300,14 @@ impl Bus {
 
             0x2002 => panic!("Trying to write to read only register {}", addr),
 
-            0x2000 => {
-                self.ppu.write_to_ctrl(data);
+            0x2000 | 0x2001 => {
+                if (addr == 0x2000) != self.ppu.swaps_ctrl_mask() {
+                    self.ppu.write_to_ctrl(data);
+                } else {
+                    self.ppu.mask.set_mltpl(data);
+                }
             }
 
-            0x2001 => self.ppu.mask.set_mltpl(data),
-
             0x2003 => self.ppu.set_oam_addr(data),
 
             0x2004 => self.ppu.write_oam_data(data),
@@ -98,7 +326,36 @@ impl Bus {
                 self.write_mem(mirror_down_addr, data);
             }
 
-            0x8000..=0xFFFF => panic!("Attempt to write to Cartridge ROM space: {:x}", addr),
+            0x4000..=0x4013 | 0x4015 | 0x4017 => self.apu.write_register(addr, data),
+
+            0x4014 => {
+                let page = (data as u16) << 8;
+                let mut buffer = [0; 256];
+                for (i, byte) in buffer.iter_mut().enumerate() {
+                    *byte = self.read_mem(page + i as u16);
+                }
+                self.ppu.oam_dma(&buffer);
+                self.oam_dma_pending = true;
+            }
+
+            0x4016 => {
+                // a device in both ports sees the write twice, the strobe doesn't mind
+                for device in self.ports.iter().chain([&self.expansion_port]).flatten() {
+                    device.borrow_mut().write(data);
+                }
+                self.mapper.borrow_mut().write_output_latch(data);
+            }
+
+            0x4020..=0x5FFF => {
+                if let (0x4020, Some(vs)) = (addr, self.vs.as_mut()) {
+                    vs.write_4020(data);
+                }
+                self.mapper.borrow_mut().write_expansion(addr, data);
+            }
+
+            0x6000..=0x7FFF => self.mapper.borrow_mut().write_prg_ram(addr, data),
+
+            0x8000..=0xFFFF => self.mapper.borrow_mut().write_prg(addr, data),
 
             _ => {
                 println!("Ignoring mem write-access at {}", addr);
@@ -108,7 +365,149 @@ impl Bus {
 
     pub fn tick(&mut self, cl: u8){
         self.cycles += cl as usize;
-        self.ppu.tick((cl * 3).try_into().unwrap());
+        self.master_clock += cl as usize * self.region.cpu_divider();
+        let dots = self.master_clock / self.region.ppu_divider();
+        self.master_clock %= self.region.ppu_divider();
+        let frame_done = self.ppu.tick(dots as u8);
+
+        let mut stall = 0;
+        for _ in 0..cl {
+            let mut mapper = self.mapper.borrow_mut();
+            mapper.cpu_clock();
+            self.apu.tick();
+            if let Some(audio) = &mut self.audio {
+                audio.clock(self.apu.output() + mapper.audio_output());
+            }
+            if let Some(capture) = &mut self.capture {
+                capture.clock(self.apu.channel_levels(), mapper.audio_output());
+            }
+            drop(mapper);
+            // the sample fetch is an ordinary read on the CPU bus
+            if let Some(addr) = self.apu.dmc_dma_request() {
+                let data = self.read_mem(addr);
+                self.apu.dmc_dma_fill(data);
+                stall += if self.oam_dma_active { DMC_DMA_CYCLES_IN_OAM_DMA } else { DMC_DMA_CYCLES };
+            }
+        }
+
+        let controller_read = self.controller_read.take();
+        if stall > 0 {
+            // the halted CPU repeats its read, a controller shifts out an extra bit
+            if let Some(addr) = controller_read {
+                self.read_mem(addr);
+                self.controller_read = None;
+            }
+            self.tick(stall);
+        }
+
+        // the copy already happened, the CPU sits out the cycles it takes
+        // while everything else keeps running
+        if self.oam_dma_pending {
+            self.oam_dma_pending = false;
+            self.oam_dma_active = true;
+            let mut remaining = OAM_DMA_CYCLES + self.cycles % 2;
+            while remaining > 0 {
+                // PAL runs 3.2 dots a cycle, keep the dot count within a u8
+                let cycles = remaining.min(64);
+                self.tick(cycles as u8);
+                remaining -= cycles;
+            }
+            self.oam_dma_active = false;
+        }
+
+        if frame_done {
+            if let Some(audio) = &mut self.audio {
+                audio.end_frame();
+            }
+            self.frames += 1;
+            if self.frames.is_multiple_of(SAVE_FLUSH_FRAMES) {
+                self.flush_save_ram();
+            }
+        }
+    }
+
+    pub fn poll_nmi_status(&mut self) -> Option<u8> {
+        self.ppu.poll_nmi_interrupt()
+    }
+
+    //the IRQ line is level triggered, it stays low until the source is acknowledged
+    pub fn poll_irq_status(&self) -> bool {
+        self.mapper.borrow().irq_pending() || self.apu.irq_pending()
+    }
+
+    pub fn expansion_audio(&self) -> f32 {
+        self.mapper.borrow().audio_output()
     }
-    
-}
\ No newline at end of file
+
+}
+
+impl Drop for Bus {
+    fn drop(&mut self) {
+        self.flush_save_ram();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cartridge::test::test_rom;
+
+    #[test]
+    fn test_oam_dma() {
+        let mut bus = Bus::new(test_rom()).unwrap();
+        for i in 0..256 {
+            bus.write_mem(0x0200 + i, i as u8);
+        }
+        bus.write_mem(0x2003, 0x10);
+        bus.write_mem(0x4014, 0x02);
+        // the copy starts at OAMADDR and wraps around
+        assert_eq!(bus.ppu.oam_data[0x10], 0x00);
+        assert_eq!(bus.ppu.oam_data[0x0F], 0xFF);
+
+        bus.tick(4);
+        assert_eq!(bus.cycles, 4 + 513);
+        bus.write_mem(0x4014, 0x02);
+        bus.tick(4);
+        assert_eq!(bus.cycles, 4 + 513 + 4 + 514);
+        // the PPU kept going
+        assert_eq!(bus.ppu.scanline, 1035 * 3 / 341);
+    }
+
+    #[test]
+    fn test_joypad_ports() {
+        let mut bus = Bus::new(test_rom()).unwrap();
+        bus.set_joypad_buttons(0, JoypadButton::BUTTON_B);
+        bus.set_joypad_buttons(1, JoypadButton::BUTTON_A);
+        bus.write_mem(0x4016, 1);
+        bus.write_mem(0x4016, 0);
+        assert_eq!(bus.read_mem(0x4016), 0x40);
+        assert_eq!(bus.read_mem(0x4016), 0x41);
+        assert_eq!(bus.read_mem(0x4017), 0x41);
+        assert_eq!(bus.read_mem(0x4017), 0x40);
+    }
+
+    #[test]
+    fn test_unsupported_mapper() {
+        // the fields are public, nothing stopped this rom from being built
+        let mut rom = test_rom();
+        rom.mapper = 0x115;
+        assert!(matches!(Bus::new(rom), Err(RomError::UnsupportedMapper(0x115))));
+    }
+
+    #[test]
+    fn test_oam_dma_from_write_only_registers() {
+        let mut bus = Bus::new(test_rom()).unwrap();
+        bus.write_mem(0x4014, 0x20);
+        assert_eq!(bus.ppu.oam_data[0], 0x20);
+        bus.write_mem(0x4014, 0x40);
+        assert_eq!(bus.ppu.oam_data[0x14], 0x40);
+    }
+
+    #[test]
+    fn test_apu_reads_are_open_bus() {
+        let mut bus = Bus::new(test_rom()).unwrap();
+        assert_eq!(bus.read_mem(0x4000), 0x40);
+        assert_eq!(bus.read_mem(0x4013), 0x40);
+        assert_eq!(bus.read_mem(0x401F), 0x40);
+    }
+}
@@ -1,33 +1,309 @@
-#[derive(Debug, PartialEq)]
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::mapper::DISK_SIDE_SIZE;
+use crate::patch::{self, PatchError};
+use crate::romdb::RomDb;
+use crate::unif;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+#[allow(non_camel_case_types)]
 pub enum Mirroring {
    VERTICAL,
    HORIZONTAL,
    FOUR_SCREEN,
+   ONE_SCREEN_LOWER,
+   ONE_SCREEN_UPPER,
+}
+
+// Which flavour of the iNES header a file uses
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum HeaderFormat {
+    // bytes 7-15 are unused and often filled with garbage, only the low mapper nibble is valid
+    ArchaicINes,
+    INes,
+    Nes2,
+    // not iNES at all, chunks named after the board
+    Unif,
+    // a Famicom Disk System image, the BIOS comes from a separate file
+    Fds,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Timing {
+    Ntsc,
+    Pal,
+    // runs on both without changes
+    MultiRegion,
+    Dendy,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ConsoleType {
+    Nes,
+    VsSystem,
+    Playchoice10,
+    // NES 2.0 extended console type from byte 13
+    Extended(u8),
+}
+
+#[derive(Debug)]
+pub enum RomError {
+    Io(io::Error),
+    // not even a whole 16 byte header
+    TooShort(usize),
+    BadMagic,
+    TruncatedPrg { expected: usize, found: usize },
+    TruncatedChr { expected: usize, found: usize },
+    UnsupportedMapper(u16),
+    UnsupportedBoard(String),
+    // the header asks for a PRG ROM no board can have
+    SizeMismatch { prg_rom: usize },
+    Patch(PatchError),
+    // disk images only run with the FDS BIOS, see Rom::from_fds
+    MissingBios,
+    BadBios(usize),
+}
+
+impl fmt::Display for RomError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RomError::Io(e) => write!(f, "Can't read rom: {}", e),
+            RomError::TooShort(len) => write!(f, "File is too short for an iNES header ({} bytes)", len),
+            RomError::BadMagic => write!(f, "File is not in iNES or UNIF format"),
+            RomError::TruncatedPrg { expected, found } => {
+                write!(f, "PRG ROM is truncated, expected {} bytes but found {}", expected, found)
+            }
+            RomError::TruncatedChr { expected, found } => {
+                write!(f, "CHR ROM is truncated, expected {} bytes but found {}", expected, found)
+            }
+            RomError::UnsupportedMapper(mapper) => write!(f, "Mapper {} is not supported", mapper),
+            RomError::UnsupportedBoard(board) => write!(f, "Board {} is not supported", board),
+            RomError::SizeMismatch { prg_rom } => {
+                write!(f, "PRG ROM size of {} bytes is not a whole number of 8K banks", prg_rom)
+            }
+            RomError::Patch(e) => write!(f, "Can't apply patch: {}", e),
+            RomError::MissingBios => write!(f, "Disk images need the FDS BIOS"),
+            RomError::BadBios(len) => write!(f, "FDS BIOS must be 8192 bytes, not {}", len),
+        }
+    }
+}
+
+impl std::error::Error for RomError {}
+
+impl From<io::Error> for RomError {
+    fn from(e: io::Error) -> Self {
+        RomError::Io(e)
+    }
+}
+
+impl From<PatchError> for RomError {
+    fn from(e: PatchError) -> Self {
+        RomError::Patch(e)
+    }
+}
+
+// Problems that don't stop the rom from loading
+#[derive(Debug, PartialEq, Clone)]
+pub enum RomWarning {
+    // bytes 7-15 of an old header hold something like "DiskDude!" and are ignored
+    HeaderGarbage(String),
+    // bytes after the CHR ROM that no header field accounts for
+    TrailingData(usize),
+    // a UNIF chunk that was skipped, like a PRG piece with no hex digit
+    UnknownChunk(String),
+}
+
+impl fmt::Display for RomWarning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RomWarning::HeaderGarbage(text) => write!(f, "Ignoring garbage in header bytes 7-15: {:?}", text),
+            RomWarning::TrailingData(len) => write!(f, "Ignoring {} bytes of data after the CHR ROM", len),
+            RomWarning::UnknownChunk(id) => write!(f, "Ignoring UNIF chunk {:?}", id),
+        }
+    }
 }
 
 pub struct Rom {
    pub prg_rom: Vec<u8>,
    pub chr_rom: Vec<u8>,
-   pub mapper: u8,
+   // copied to $7000-$71FF at power-on
+   pub trainer: Option<Vec<u8>>,
+   pub mapper: u16,
+   pub submapper: u8,
    pub screen_mirroring: Mirroring,
+   pub battery: bool,
+   pub header_format: HeaderFormat,
+
+   pub prg_ram_size: usize,
+   pub prg_nvram_size: usize,
+   pub chr_ram_size: usize,
+   pub chr_nvram_size: usize,
+
+   pub timing: Timing,
+   pub console_type: ConsoleType,
+   pub vs_ppu_type: u8,
+   pub vs_hardware_type: u8,
+   pub expansion_device: u8,
+   pub misc_roms: u8,
+
+   pub warnings: Vec<RomWarning>,
+   // set when the rom was found in a RomDb
+   pub title: Option<String>,
+   pub board: Option<String>,
+
+   // FDS disk sides, DISK_SIDE_SIZE bytes each
+   pub disk: Vec<u8>,
 }
 
 const PRG_ROM_PAGE_SIZE: usize = 16384;
 const CHR_ROM_PAGE_SIZE: usize = 8192;
+const PRG_RAM_PAGE_SIZE: usize = 8192;
+const TRAINER_SIZE: usize = 512;
+const HEADER_SIZE: usize = 16;
+const FDS_BIOS_SIZE: usize = 8192;
+
+// NES 2.0 rom size: either a 12 bit page count or, if the high nibble is $F,
+// an exponent-multiplier pair EEEEEEMM meaning 2^E * (MM * 2 + 1) bytes
+fn nes2_rom_size(lsb: u8, msb: u8, page_size: usize) -> usize {
+    if msb == 0xF {
+        let exponent = (lsb >> 2) as u32;
+        let multiplier = (lsb & 0b11) as usize * 2 + 1;
+        2usize.saturating_pow(exponent).saturating_mul(multiplier)
+    } else {
+        ((msb as usize) << 8 | lsb as usize) * page_size
+    }
+}
+
+// NES 2.0 RAM size: 64 << shift bytes, 0 means none
+fn nes2_ram_size(shift: u8) -> usize {
+    if shift == 0 { 0 } else { 64 << shift }
+}
 
 impl Rom {
-    pub fn new(raw: &Vec<u8>) -> Result<Rom, String> {
-        let nes_tag: Vec<u8> = vec![0x4E, 0x45, 0x53, 0x1A];
-        if &raw[0..4] != nes_tag {
-            return Err("File is not in iNES file format".to_string());
+    pub fn from_path(path: impl AsRef<Path>) -> Result<Rom, RomError> {
+        let raw = fs::read(path)?;
+        Rom::from_bytes(&raw)
+    }
+
+    // IPS, UPS or BPS patches are applied to the whole file, header included, in order
+    pub fn from_path_with_patches(path: impl AsRef<Path>, patches: &[impl AsRef<Path>]) -> Result<Rom, RomError> {
+        let raw = fs::read(path)?;
+        let patches = patches.iter().map(fs::read).collect::<Result<Vec<_>, _>>()?;
+        Rom::from_bytes_patched(&raw, &patches)
+    }
+
+    pub fn from_bytes_patched(raw: &[u8], patches: &[Vec<u8>]) -> Result<Rom, RomError> {
+        let mut raw = raw.to_vec();
+        for patch in patches {
+            raw = patch::apply(&raw, patch)?;
         }
- 
-        let mapper = (raw[7] & 0b1111_0000) | (raw[6] >> 4);
- 
-        let ines_ver = (raw[7] >> 2) & 0b11;
-        if ines_ver != 0 {
-            return Err("NES2.0 format is not supported".to_string());
+        Rom::from_bytes(&raw)
+    }
+
+    pub fn from_bytes(raw: &[u8]) -> Result<Rom, RomError> {
+        let rom = Rom::parse(raw)?;
+        rom.check_mapper()?;
+        Ok(rom)
+    }
+
+    // The database entry, if there is one, replaces the header before the
+    // mapper is checked, so a wrong mapper number in the header is fixed too.
+    pub fn from_bytes_with_db(raw: &[u8], db: &RomDb) -> Result<Rom, RomError> {
+        let mut rom = Rom::parse(raw)?;
+        db.apply(&mut rom);
+        rom.check_mapper()?;
+        Ok(rom)
+    }
+
+    pub fn from_fds_path(image: impl AsRef<Path>, bios: impl AsRef<Path>) -> Result<Rom, RomError> {
+        Rom::from_fds(&fs::read(image)?, &fs::read(bios)?)
+    }
+
+    // A .fds image, with or without the fwNES header, and the 8K disk BIOS.
+    // The BIOS takes the place of the PRG ROM.
+    pub fn from_fds(image: &[u8], bios: &[u8]) -> Result<Rom, RomError> {
+        if bios.len() != FDS_BIOS_SIZE {
+            return Err(RomError::BadBios(bios.len()));
+        }
+        let disk = if image.starts_with(b"FDS\x1A") { &image[HEADER_SIZE.min(image.len())..] } else { image };
+        if !disk.starts_with(b"\x01*NINTENDO-HVC*") {
+            return Err(RomError::BadMagic);
+        }
+        let sides = disk.len() / DISK_SIDE_SIZE;
+        if sides == 0 {
+            return Err(RomError::TooShort(image.len()));
+        }
+
+        let mut rom = Rom::empty(HeaderFormat::Fds);
+        rom.mapper = 20;
+        rom.prg_rom = bios.to_vec();
+        rom.prg_ram_size = 0x8000;
+        rom.chr_ram_size = 0x2000;
+        rom.disk = disk[..sides * DISK_SIDE_SIZE].to_vec();
+        let trailing = disk.len() - rom.disk.len();
+        if trailing > 0 {
+            rom.warnings.push(RomWarning::TrailingData(trailing));
+        }
+        Ok(rom)
+    }
+
+    fn check_mapper(&self) -> Result<(), RomError> {
+        if !crate::mapper::is_supported(self.mapper) {
+            return Err(RomError::UnsupportedMapper(self.mapper));
+        }
+        Ok(())
+    }
+
+    pub(crate) fn empty(header_format: HeaderFormat) -> Rom {
+        Rom {
+            prg_rom: vec![],
+            chr_rom: vec![],
+            trainer: None,
+            mapper: 0,
+            submapper: 0,
+            screen_mirroring: Mirroring::HORIZONTAL,
+            battery: false,
+            header_format,
+            prg_ram_size: 0,
+            prg_nvram_size: 0,
+            chr_ram_size: 0,
+            chr_nvram_size: 0,
+            timing: Timing::Ntsc,
+            console_type: ConsoleType::Nes,
+            vs_ppu_type: 0,
+            vs_hardware_type: 0,
+            expansion_device: 0,
+            misc_roms: 0,
+            warnings: vec![],
+            title: None,
+            board: None,
+            disk: vec![],
+        }
+    }
+
+    fn parse(raw: &[u8]) -> Result<Rom, RomError> {
+        if raw.starts_with(b"UNIF") {
+            return unif::parse(raw);
+        }
+        if raw.starts_with(b"FDS\x1A") || raw.starts_with(b"\x01*NINTENDO-HVC*") {
+            return Err(RomError::MissingBios);
         }
+        if raw.len() < HEADER_SIZE {
+            return Err(RomError::TooShort(raw.len()));
+        }
+        let nes_tag = [0x4E, 0x45, 0x53, 0x1A];
+        if raw[0..4] != nes_tag {
+            return Err(RomError::BadMagic);
+        }
+
+        let header_format = match (raw[7] >> 2) & 0b11 {
+            0b10 => HeaderFormat::Nes2,
+            0b00 if raw[12..16].iter().all(|b| *b == 0) => HeaderFormat::INes,
+            _ => HeaderFormat::ArchaicINes,
+        };
  
         let four_screen = raw[6] & 0b1000 != 0;
         let vertical_mirroring = raw[6] & 0b1 != 0;
@@ -36,21 +312,102 @@ impl Rom {
             (false, true) => Mirroring::VERTICAL,
             (false, false) => Mirroring::HORIZONTAL,
         };
+
+        let mut rom = Rom::empty(header_format);
+        rom.mapper = (raw[6] >> 4) as u16;
+        rom.screen_mirroring = screen_mirroring;
+        rom.battery = raw[6] & 0b10 != 0;
+
+        let prg_rom_size;
+        let chr_rom_size;
+        match header_format {
+            HeaderFormat::ArchaicINes => {
+                prg_rom_size = raw[4] as usize * PRG_ROM_PAGE_SIZE;
+                chr_rom_size = raw[5] as usize * CHR_ROM_PAGE_SIZE;
+                rom.prg_ram_size = PRG_RAM_PAGE_SIZE;
+            }
+            HeaderFormat::INes => {
+                prg_rom_size = raw[4] as usize * PRG_ROM_PAGE_SIZE;
+                chr_rom_size = raw[5] as usize * CHR_ROM_PAGE_SIZE;
+                rom.mapper |= (raw[7] & 0b1111_0000) as u16;
+                // 0 means 8K for compatibility
+                rom.prg_ram_size = raw[8].max(1) as usize * PRG_RAM_PAGE_SIZE;
+                rom.console_type = match raw[7] & 0b11 {
+                    0b01 => ConsoleType::VsSystem,
+                    0b10 => ConsoleType::Playchoice10,
+                    _ => ConsoleType::Nes,
+                };
+                if raw[9] & 1 != 0 {
+                    rom.timing = Timing::Pal;
+                }
+            }
+            HeaderFormat::Nes2 => {
+                prg_rom_size = nes2_rom_size(raw[4], raw[9] & 0x0F, PRG_ROM_PAGE_SIZE);
+                chr_rom_size = nes2_rom_size(raw[5], raw[9] >> 4, CHR_ROM_PAGE_SIZE);
+                rom.mapper |= (raw[7] & 0b1111_0000) as u16 | ((raw[8] & 0x0F) as u16) << 8;
+                rom.submapper = raw[8] >> 4;
+                rom.prg_ram_size = nes2_ram_size(raw[10] & 0x0F);
+                rom.prg_nvram_size = nes2_ram_size(raw[10] >> 4);
+                rom.chr_ram_size = nes2_ram_size(raw[11] & 0x0F);
+                rom.chr_nvram_size = nes2_ram_size(raw[11] >> 4);
+                rom.timing = match raw[12] & 0b11 {
+                    0 => Timing::Ntsc,
+                    1 => Timing::Pal,
+                    2 => Timing::MultiRegion,
+                    _ => Timing::Dendy,
+                };
+                rom.console_type = match raw[7] & 0b11 {
+                    0 => ConsoleType::Nes,
+                    1 => ConsoleType::VsSystem,
+                    2 => ConsoleType::Playchoice10,
+                    _ => ConsoleType::Extended(raw[13] & 0x0F),
+                };
+                if rom.console_type == ConsoleType::VsSystem {
+                    rom.vs_ppu_type = raw[13] & 0x0F;
+                    rom.vs_hardware_type = raw[13] >> 4;
+                }
+                rom.misc_roms = raw[14] & 0b11;
+                rom.expansion_device = raw[15] & 0b11_1111;
+            }
+            HeaderFormat::Unif | HeaderFormat::Fds => unreachable!("only iNES files have an iNES header"),
+        }
+
+        // iNES has no CHR RAM size, boards without CHR ROM get 8K
+        if header_format != HeaderFormat::Nes2 && chr_rom_size == 0 {
+            rom.chr_ram_size = CHR_ROM_PAGE_SIZE;
+        }
  
-        let prg_rom_size = raw[4] as usize * PRG_ROM_PAGE_SIZE;
-        let chr_rom_size = raw[5] as usize * CHR_ROM_PAGE_SIZE;
- 
-        let skip_trainer = raw[6] & 0b100 != 0;
- 
-        let prg_rom_start = 16 + if skip_trainer { 512 } else { 0 };
+        if header_format == HeaderFormat::ArchaicINes {
+            let text = String::from_utf8_lossy(&raw[7..HEADER_SIZE]);
+            rom.warnings.push(RomWarning::HeaderGarbage(text.trim_end_matches('\0').to_string()));
+        }
+        if prg_rom_size == 0 || !prg_rom_size.is_multiple_of(0x2000) {
+            return Err(RomError::SizeMismatch { prg_rom: prg_rom_size });
+        }
+
+        let has_trainer = raw[6] & 0b100 != 0;
+        let prg_rom_start = HEADER_SIZE + if has_trainer { TRAINER_SIZE } else { 0 };
+        // a file cut off inside the trainer has no PRG ROM at all
+        let available = raw.len().saturating_sub(prg_rom_start);
+        if available < prg_rom_size {
+            return Err(RomError::TruncatedPrg { expected: prg_rom_size, found: available });
+        }
         let chr_rom_start = prg_rom_start + prg_rom_size;
- 
-        Ok(Rom {
-            prg_rom: raw[prg_rom_start..(prg_rom_start + prg_rom_size)].to_vec(),
-            chr_rom: raw[chr_rom_start..(chr_rom_start + chr_rom_size)].to_vec(),
-            mapper: mapper,
-            screen_mirroring: screen_mirroring,
-        })
+        let available = raw.len() - chr_rom_start;
+        if available < chr_rom_size {
+            return Err(RomError::TruncatedChr { expected: chr_rom_size, found: available });
+        }
+        let trailing = available - chr_rom_size;
+        if trailing > 0 && rom.misc_roms == 0 {
+            rom.warnings.push(RomWarning::TrailingData(trailing));
+        }
+
+        if has_trainer {
+            rom.trainer = Some(raw[HEADER_SIZE..HEADER_SIZE + TRAINER_SIZE].to_vec());
+        }
+        rom.prg_rom = raw[prg_rom_start..(prg_rom_start + prg_rom_size)].to_vec();
+        rom.chr_rom = raw[chr_rom_start..(chr_rom_start + chr_rom_size)].to_vec();
+        Ok(rom)
     }
  }
 
@@ -93,7 +450,7 @@ pub mod test {
             chr_rom: vec![2; 1 * CHR_ROM_PAGE_SIZE],
         });
 
-        Rom::new(&test_rom).unwrap()
+        Rom::from_bytes(&test_rom).unwrap()
     }
 
     #[test]
@@ -107,12 +464,31 @@ pub mod test {
             chr_rom: vec![2; 1 * CHR_ROM_PAGE_SIZE],
         });
 
-        let rom: Rom = Rom::new(&test_rom).unwrap();
+        let rom: Rom = Rom::from_bytes(&test_rom).unwrap();
 
         assert_eq!(rom.chr_rom, vec!(2; 1 * CHR_ROM_PAGE_SIZE));
         assert_eq!(rom.prg_rom, vec!(1; 2 * PRG_ROM_PAGE_SIZE));
         assert_eq!(rom.mapper, 3);
         assert_eq!(rom.screen_mirroring, Mirroring::VERTICAL);
+        assert!(!rom.battery);
+    }
+
+    #[test]
+    fn test_chr_ram() {
+        let test_rom = create_rom(TestRom {
+            header: vec![
+                0x4E, 0x45, 0x53, 0x1A, 0x02, 0x00, 0x01, 00, 00, 00, 00, 00, 00, 00, 00, 00,
+            ],
+            trainer: None,
+            pgp_rom: vec![1; 2 * PRG_ROM_PAGE_SIZE],
+            chr_rom: vec![],
+        });
+
+        let rom: Rom = Rom::from_bytes(&test_rom).unwrap();
+
+        assert!(rom.chr_rom.is_empty());
+        assert_eq!(rom.chr_ram_size, 8192);
+        assert_eq!(rom.mapper, 0);
     }
 
     #[test]
@@ -136,33 +512,224 @@ pub mod test {
                 00,
                 00,
             ],
-            trainer: Some(vec![0; 512]),
+            trainer: Some(vec![3; 512]),
             pgp_rom: vec![1; 2 * PRG_ROM_PAGE_SIZE],
             chr_rom: vec![2; 1 * CHR_ROM_PAGE_SIZE],
         });
 
-        let rom: Rom = Rom::new(&test_rom).unwrap();
+        let rom: Rom = Rom::from_bytes(&test_rom).unwrap();
 
+        assert_eq!(rom.trainer, Some(vec!(3; 512)));
         assert_eq!(rom.chr_rom, vec!(2; 1 * CHR_ROM_PAGE_SIZE));
         assert_eq!(rom.prg_rom, vec!(1; 2 * PRG_ROM_PAGE_SIZE));
         assert_eq!(rom.mapper, 3);
         assert_eq!(rom.screen_mirroring, Mirroring::VERTICAL);
+
+        let mut ram = crate::mapper::PrgRam::new(&rom);
+        assert_eq!(ram.read(0x0FFF), Some(0));
+        assert_eq!(ram.read(0x1000), Some(3));
+        assert_eq!(ram.read(0x11FF), Some(3));
+        assert_eq!(ram.read(0x1200), Some(0));
+        assert!(!ram.take_dirty());
     }
 
     #[test]
-    fn test_nes2_is_not_supported() {
+    fn test_nes2_header() {
         let test_rom = create_rom(TestRom {
             header: vec![
-                0x4E, 0x45, 0x53, 0x1A, 0x01, 0x01, 0x31, 0x8, 00, 00, 00, 00, 00, 00, 00, 00,
+                0x4E, 0x45, 0x53, 0x1A, 0x01, 0x01, 0x51, 0x19, 0x30, 00, 0x70, 0x07, 0x01, 0x21, 0x01, 0x2A,
             ],
             trainer: None,
             pgp_rom: vec![1; 1 * PRG_ROM_PAGE_SIZE],
             chr_rom: vec![2; 1 * CHR_ROM_PAGE_SIZE],
         });
-        let rom = Rom::new(&test_rom);
-        match rom {
-            Result::Ok(_) => assert!(false, "should not load rom"),
-            Result::Err(str) => assert_eq!(str, "NES2.0 format is not supported"),
-        }
+        let rom = Rom::from_bytes(&test_rom).unwrap();
+
+        assert_eq!(rom.header_format, HeaderFormat::Nes2);
+        assert_eq!(rom.mapper, 21);
+        assert_eq!(rom.submapper, 3);
+        assert_eq!(rom.prg_ram_size, 0);
+        assert_eq!(rom.prg_nvram_size, 8192);
+        assert_eq!(rom.chr_ram_size, 8192);
+        assert_eq!(rom.chr_nvram_size, 0);
+        assert_eq!(rom.timing, Timing::Pal);
+        assert_eq!(rom.console_type, ConsoleType::VsSystem);
+        assert_eq!(rom.vs_ppu_type, 1);
+        assert_eq!(rom.vs_hardware_type, 2);
+        assert_eq!(rom.misc_roms, 1);
+        assert_eq!(rom.expansion_device, 0x2A);
+    }
+
+    #[test]
+    fn test_nes2_exponent_rom_size() {
+        // 2^14 * 1 bytes of PRG and 2^13 * 1 bytes of CHR through the exponent form
+        let test_rom = create_rom(TestRom {
+            header: vec![
+                0x4E, 0x45, 0x53, 0x1A, 14 << 2, 13 << 2, 0x00, 0x08, 00, 0xFF, 00, 00, 00, 00, 00, 00,
+            ],
+            trainer: None,
+            pgp_rom: vec![1; 1 * PRG_ROM_PAGE_SIZE],
+            chr_rom: vec![2; 1 * CHR_ROM_PAGE_SIZE],
+        });
+        let rom = Rom::from_bytes(&test_rom).unwrap();
+        assert_eq!(rom.prg_rom.len(), 16384);
+        assert_eq!(rom.chr_rom.len(), 8192);
+    }
+
+    #[test]
+    fn test_archaic_ines_ignores_upper_header() {
+        let test_rom = create_rom(TestRom {
+            header: vec![
+                0x4E, 0x45, 0x53, 0x1A, 0x01, 0x01, 0x31, b'D', b'i', b's', b'k', b'D', b'u', b'd', b'e', b'!',
+            ],
+            trainer: None,
+            pgp_rom: vec![1; 1 * PRG_ROM_PAGE_SIZE],
+            chr_rom: vec![2; 1 * CHR_ROM_PAGE_SIZE],
+        });
+        let rom = Rom::from_bytes(&test_rom).unwrap();
+        assert_eq!(rom.header_format, HeaderFormat::ArchaicINes);
+        assert_eq!(rom.mapper, 3);
+        assert_eq!(rom.console_type, ConsoleType::Nes);
+        assert_eq!(rom.warnings, vec![RomWarning::HeaderGarbage("DiskDude!".to_string())]);
+    }
+
+    #[test]
+    fn test_patched() {
+        let raw = create_rom(TestRom {
+            header: header(1, 1),
+            trainer: None,
+            pgp_rom: vec![1; PRG_ROM_PAGE_SIZE],
+            chr_rom: vec![2; CHR_ROM_PAGE_SIZE],
+        });
+        // mapper 0 instead of 3, then change the first PRG byte
+        let mut fixed_header = raw.clone();
+        fixed_header[6] = 0x01;
+        let mut translated = fixed_header.clone();
+        translated[16] = 0x99;
+        let patches = vec![
+            patch::create_ips(&raw, &fixed_header).unwrap(),
+            patch::create_bps(&fixed_header, &translated),
+        ];
+
+        let rom = Rom::from_bytes_patched(&raw, &patches).unwrap();
+        assert_eq!(rom.mapper, 0);
+        assert_eq!(rom.prg_rom[0], 0x99);
+
+        // applied in the wrong order the BPS checksum doesn't match
+        let patches = vec![patches[1].clone(), patches[0].clone()];
+        assert!(matches!(
+            Rom::from_bytes_patched(&raw, &patches),
+            Err(RomError::Patch(PatchError::SourceChecksum { .. }))
+        ));
+    }
+
+    #[cfg(test)]
+    fn header(prg_pages: u8, chr_pages: u8) -> Vec<u8> {
+        vec![0x4E, 0x45, 0x53, 0x1A, prg_pages, chr_pages, 0x31, 00, 00, 00, 00, 00, 00, 00, 00, 00]
+    }
+
+    #[test]
+    fn test_too_short() {
+        assert!(matches!(Rom::from_bytes(&[0x4E, 0x45, 0x53]), Err(RomError::TooShort(3))));
+    }
+
+    #[test]
+    fn test_bad_magic() {
+        let mut raw = create_rom(TestRom {
+            header: header(1, 1),
+            trainer: None,
+            pgp_rom: vec![1; PRG_ROM_PAGE_SIZE],
+            chr_rom: vec![2; CHR_ROM_PAGE_SIZE],
+        });
+        raw[3] = 0;
+        assert!(matches!(Rom::from_bytes(&raw), Err(RomError::BadMagic)));
+    }
+
+    #[test]
+    fn test_truncated_prg() {
+        let raw = create_rom(TestRom {
+            header: header(2, 1),
+            trainer: None,
+            pgp_rom: vec![1; PRG_ROM_PAGE_SIZE],
+            chr_rom: vec![],
+        });
+        assert!(matches!(
+            Rom::from_bytes(&raw),
+            Err(RomError::TruncatedPrg { expected: 32768, found: 16384 })
+        ));
+    }
+
+    #[test]
+    fn test_truncated_chr() {
+        let raw = create_rom(TestRom {
+            header: header(1, 1),
+            trainer: None,
+            pgp_rom: vec![1; PRG_ROM_PAGE_SIZE],
+            chr_rom: vec![2; 100],
+        });
+        assert!(matches!(
+            Rom::from_bytes(&raw),
+            Err(RomError::TruncatedChr { expected: 8192, found: 100 })
+        ));
+    }
+
+    #[test]
+    fn test_unsupported_mapper() {
+        // NES 2.0 mapper $115, the upper four bits come from byte 8
+        let raw = create_rom(TestRom {
+            header: vec![
+                0x4E, 0x45, 0x53, 0x1A, 0x01, 0x01, 0x51, 0x18, 0x01, 00, 00, 00, 00, 00, 00, 00,
+            ],
+            trainer: None,
+            pgp_rom: vec![1; PRG_ROM_PAGE_SIZE],
+            chr_rom: vec![2; CHR_ROM_PAGE_SIZE],
+        });
+        assert!(matches!(Rom::from_bytes(&raw), Err(RomError::UnsupportedMapper(0x115))));
+    }
+
+    #[test]
+    fn test_fds_image() {
+        let mut image = b"FDS\x1A\x02".to_vec();
+        image.resize(HEADER_SIZE, 0);
+        image.extend_from_slice(b"\x01*NINTENDO-HVC*");
+        image.resize(HEADER_SIZE + DISK_SIDE_SIZE * 2, 0);
+
+        assert!(matches!(Rom::from_bytes(&image), Err(RomError::MissingBios)));
+        assert!(matches!(Rom::from_fds(&image, &[0; 4096]), Err(RomError::BadBios(4096))));
+
+        let rom = Rom::from_fds(&image, &[0xEA; 8192]).unwrap();
+        assert_eq!(rom.header_format, HeaderFormat::Fds);
+        assert_eq!(rom.mapper, 20);
+        assert_eq!(rom.prg_rom.len(), 8192);
+        assert_eq!(rom.disk.len(), DISK_SIDE_SIZE * 2);
+        assert_eq!(rom.disk, image[HEADER_SIZE..]);
+    }
+
+    #[test]
+    fn test_size_mismatch() {
+        // NES 2.0 exponent form, 2^10 bytes of PRG
+        let raw = create_rom(TestRom {
+            header: vec![
+                0x4E, 0x45, 0x53, 0x1A, 10 << 2, 0x00, 0x00, 0x08, 00, 0x0F, 00, 00, 00, 00, 00, 00,
+            ],
+            trainer: None,
+            pgp_rom: vec![1; 1024],
+            chr_rom: vec![],
+        });
+        assert!(matches!(Rom::from_bytes(&raw), Err(RomError::SizeMismatch { prg_rom: 1024 })));
+    }
+
+    #[test]
+    fn test_trailing_data() {
+        let mut raw = create_rom(TestRom {
+            header: header(1, 1),
+            trainer: None,
+            pgp_rom: vec![1; PRG_ROM_PAGE_SIZE],
+            chr_rom: vec![2; CHR_ROM_PAGE_SIZE],
+        });
+        raw.extend_from_slice(&[0; 128]);
+        let rom = Rom::from_bytes(&raw).unwrap();
+        assert_eq!(rom.chr_rom.len(), CHR_ROM_PAGE_SIZE);
+        assert_eq!(rom.warnings, vec![RomWarning::TrailingData(128)]);
     }
 }
\ No newline at end of file
@@ -53,6 +53,23 @@ pub enum AddressingMode{
 
 const STACK_RESET:u16 = 0x1Fd;
 
+mod interrupt {
+    pub(super) struct Interrupt {
+        pub(super) vector_addr: u16,
+        pub(super) cpu_cycles: u8,
+    }
+
+    pub(super) const NMI: Interrupt = Interrupt {
+        vector_addr: 0xFFFA,
+        cpu_cycles: 7,
+    };
+
+    pub(super) const IRQ: Interrupt = Interrupt {
+        vector_addr: 0xFFFE,
+        cpu_cycles: 7,
+    };
+}
+
 impl CPU{
     pub fn new(bus: bus::Bus) -> Self{
         let mut cpu = CPU{
@@ -803,6 +820,18 @@ impl CPU{
         self.write_mem(addr, data);
     }
 
+    //pushes pc and status (with B cleared) and jumps to the interrupt vector
+    fn interrupt(&mut self, interrupt: interrupt::Interrupt){
+        self.push_stack_u16(self.program_counter);
+        let mut flags = self.status_reg;
+        flags.remove(CpuFlags::BREAK);
+        flags.insert(CpuFlags::BREAK2);
+        self.push_stack(flags.bits());
+        self.status_reg.insert(CpuFlags::INTERRUPT_DISABLE);
+        self.bus.tick(interrupt.cpu_cycles);
+        self.program_counter = self.read_mem_u16(interrupt.vector_addr);
+    }
+
     //whipes all registers and sets program counter to addr stored at 0xFFFC
     pub fn reset(&mut self){
         self.reg_a = 0;
@@ -828,8 +857,26 @@ impl CPU{
         self.run_with_callback(|_|{});
     }
 
-    pub fn run_with_callback<F>(&mut self, mut callback: F)where F: FnMut(&mut CPU){
+    pub fn run_with_callback<F>(&mut self, callback: F)where F: FnMut(&mut CPU){
+        self.run_instructions(None, callback);
+    }
+
+    //runs a single instruction, serving a pending interrupt first. false once BRK is hit
+    pub fn step(&mut self) -> bool{
+        self.run_instructions(Some(1), |_|{})
+    }
+
+    //runs count instructions, or until BRK when None. false once BRK is hit
+    fn run_instructions<F>(&mut self, mut count: Option<usize>, mut callback: F) -> bool where F: FnMut(&mut CPU){
         loop {
+            if count == Some(0) {
+                return true;
+            }
+            if self.bus.poll_nmi_status().is_some() {
+                self.interrupt(interrupt::NMI);
+            } else if self.bus.poll_irq_status() && !self.status_reg.contains(CpuFlags::INTERRUPT_DISABLE) {
+                self.interrupt(interrupt::IRQ);
+            }
             callback(self);
             let opc = self.read_mem(self.program_counter);
             self.program_counter += 1;
@@ -838,7 +885,7 @@ impl CPU{
                 //BRK
                 0x00 => {
                     self.status_reg.insert(CpuFlags::BREAK);
-                    return;
+                    return false;
                 }
                 
                 //NOP
@@ -1227,7 +1274,7 @@ impl CPU{
                 }
                 
                 //KIL
-                0x02 | 0x12 | 0x22 | 0x32 | 0x42 | 0x52 | 0x62 | 0x72 | 0x92 | 0xB2 | 0xD2 | 0xF2 => return,
+                0x02 | 0x12 | 0x22 | 0x32 | 0x42 | 0x52 | 0x62 | 0x72 | 0x92 | 0xB2 | 0xD2 | 0xF2 => return false,
 
                 //LAR
                 0xBB => {
@@ -1290,6 +1337,7 @@ impl CPU{
                 0x9B => self.xas(),
             }
             self.bus.tick(opcodes::OP_MAP.get(&opc).unwrap().cycles);
+            count = count.map(|count| count - 1);
         }
     }
 
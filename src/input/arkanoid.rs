@@ -0,0 +1,79 @@
+use crate::input::InputDevice;
+
+// The Arkanoid Vaus paddle. The strobe latches the knob position, which then
+// shifts out inverted and high bit first.
+//
+//  NES, in a controller port:  ---D B---   D data, B button
+//  Famicom, expansion port:    $4016 ---- --B-   $4017 ---- --D-
+//
+// The knob covers about $62-$F2 in the games.
+pub struct ArkanoidVaus {
+    famicom: bool,
+    position: u8,
+    button: bool,
+    shift: u8,
+}
+
+impl ArkanoidVaus {
+    pub fn new(famicom: bool) -> Self {
+        ArkanoidVaus {
+            famicom,
+            position: 0x62,
+            button: false,
+            shift: 0,
+        }
+    }
+
+    fn shift_out(&mut self) -> u8 {
+        let bit = self.shift >> 7;
+        self.shift <<= 1;
+        bit
+    }
+}
+
+impl InputDevice for ArkanoidVaus {
+    fn write(&mut self, data: u8) {
+        if data & 1 != 0 {
+            self.shift = !self.position;
+        }
+    }
+
+    fn read(&mut self, port: usize) -> u8 {
+        match (self.famicom, port) {
+            (false, _) => self.shift_out() << 4 | (self.button as u8) << 3,
+            (true, 0) => (self.button as u8) << 1,
+            (true, _) => self.shift_out() << 1,
+        }
+    }
+
+    fn set_trigger(&mut self, pressed: bool) {
+        self.button = pressed;
+    }
+
+    fn set_position(&mut self, position: u8) {
+        self.position = position;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_position_shifts_out_inverted() {
+        let mut vaus = ArkanoidVaus::new(false);
+        vaus.set_position(0xA5);
+        vaus.set_trigger(true);
+        vaus.write(1);
+        vaus.write(0);
+        let bits: Vec<u8> = (0..8).map(|_| vaus.read(1)).collect();
+        assert_eq!(bits, [0x08, 0x18, 0x08, 0x18, 0x18, 0x08, 0x18, 0x08]);
+
+        let mut vaus = ArkanoidVaus::new(true);
+        vaus.set_position(0x7F);
+        vaus.write(1);
+        assert_eq!(vaus.read(0), 0);
+        assert_eq!(vaus.read(1), 0x02);
+        assert_eq!(vaus.read(1), 0);
+    }
+}
@@ -0,0 +1,42 @@
+use crate::input::joypad::{Joypad, JoypadButton};
+use crate::input::InputDevice;
+
+// Two more standard pads on the Famicom expansion port, players 3 and 4,
+// the way the Hori 4 Players Adapter wires them in its simple mode:
+// $4016 D1 is player 3, $4017 D1 player 4.
+pub struct FamicomPads {
+    joypads: [Joypad; 2],
+}
+
+impl FamicomPads {
+    pub fn new() -> Self {
+        FamicomPads {
+            joypads: [Joypad::new(), Joypad::new()],
+        }
+    }
+}
+
+impl InputDevice for FamicomPads {
+    fn write(&mut self, data: u8) {
+        for joypad in &mut self.joypads {
+            joypad.write(data);
+        }
+    }
+
+    fn read(&mut self, port: usize) -> u8 {
+        self.joypads[port].read(port) << 1
+    }
+
+    // players 2 and 3, others aren't on this adapter
+    fn set_player_buttons(&mut self, player: usize, buttons: JoypadButton) {
+        if let Some(joypad) = player.checked_sub(2).and_then(|i| self.joypads.get_mut(i)) {
+            joypad.set_buttons(buttons);
+        }
+    }
+}
+
+impl Default for FamicomPads {
+    fn default() -> Self {
+        FamicomPads::new()
+    }
+}
@@ -0,0 +1,91 @@
+use crate::input::joypad::{Joypad, JoypadButton};
+use crate::input::InputDevice;
+
+// The NES Four Score, plugged into both ports. Each port shifts out 24 bits:
+//
+//  0-7    the pad of player 1 ($4016) or player 2 ($4017)
+//  8-15   the pad of player 3 or player 4
+//  16-23  the signature, $10 on $4016 and $20 on $4017, high bit first
+//
+// and 1s after that until the next strobe.
+
+const SIGNATURES: [u8; 2] = [0x10, 0x20];
+
+pub struct FourScore {
+    joypads: [Joypad; 4],
+    strobe: bool,
+    reads: [usize; 2],
+}
+
+impl FourScore {
+    pub fn new() -> Self {
+        FourScore {
+            joypads: [Joypad::new(), Joypad::new(), Joypad::new(), Joypad::new()],
+            strobe: false,
+            reads: [0; 2],
+        }
+    }
+}
+
+impl InputDevice for FourScore {
+    fn write(&mut self, data: u8) {
+        for joypad in &mut self.joypads {
+            joypad.write(data);
+        }
+        self.strobe = data & 1 != 0;
+        self.reads = [0; 2];
+    }
+
+    fn read(&mut self, port: usize) -> u8 {
+        if self.strobe {
+            return self.joypads[port].read(port);
+        }
+        let read = self.reads[port];
+        self.reads[port] = (read + 1).min(24);
+        match read {
+            0..=7 => self.joypads[port].read(port),
+            8..=15 => self.joypads[port + 2].read(port),
+            16..=23 => SIGNATURES[port] >> (23 - read) & 1,
+            _ => 1,
+        }
+    }
+
+    // players 0-3, odd ones are on $4017
+    fn set_player_buttons(&mut self, player: usize, buttons: JoypadButton) {
+        if let Some(joypad) = self.joypads.get_mut(player) {
+            joypad.set_buttons(buttons);
+        }
+    }
+}
+
+impl Default for FourScore {
+    fn default() -> Self {
+        FourScore::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_four_pads_and_signature() {
+        let mut four_score = FourScore::new();
+        four_score.set_player_buttons(0, JoypadButton::BUTTON_A);
+        four_score.set_player_buttons(2, JoypadButton::BUTTON_B);
+        four_score.set_player_buttons(3, JoypadButton::RIGHT);
+        four_score.write(1);
+        four_score.write(0);
+
+        let port0: Vec<u8> = (0..26).map(|_| four_score.read(0)).collect();
+        let port1: Vec<u8> = (0..26).map(|_| four_score.read(1)).collect();
+        let bits = |v: &[u8]| v.iter().fold(0u32, |acc, bit| acc << 1 | *bit as u32);
+        assert_eq!(bits(&port0[0..8]), 0b1000_0000);
+        assert_eq!(bits(&port0[8..16]), 0b0100_0000);
+        assert_eq!(bits(&port0[16..24]), 0x10);
+        assert_eq!(bits(&port1[0..8]), 0);
+        assert_eq!(bits(&port1[8..16]), 0b0000_0001);
+        assert_eq!(bits(&port1[16..24]), 0x20);
+        assert_eq!(port0[24..], [1, 1]);
+    }
+}
@@ -0,0 +1,119 @@
+use crate::input::InputDevice;
+
+// The standard controller, a 4021 shift register behind $4016/$4017.
+//
+// $4016 write: bit 0 strobe, while high the buttons are latched continuously
+// $4016/$4017 read: bit 0 the next button, A B Select Start Up Down Left Right
+//
+// After the 8 buttons the register has shifted in 1s, an official pad keeps
+// returning 1 until the next strobe. While the strobe is high every read
+// returns the state of A.
+
+bitflags::bitflags! {
+    pub struct JoypadButton: u8 {
+        const BUTTON_A = 0b0000_0001;
+        const BUTTON_B = 0b0000_0010;
+        const SELECT   = 0b0000_0100;
+        const START    = 0b0000_1000;
+        const UP       = 0b0001_0000;
+        const DOWN     = 0b0010_0000;
+        const LEFT     = 0b0100_0000;
+        const RIGHT    = 0b1000_0000;
+    }
+}
+
+pub struct Joypad {
+    strobe: bool,
+    shift: u8,
+    buttons: JoypadButton,
+}
+
+impl Joypad {
+    pub fn new() -> Self {
+        Joypad {
+            strobe: false,
+            shift: 0,
+            buttons: JoypadButton::empty(),
+        }
+    }
+
+    // what the host sees pressed, usually set once per frame
+    pub fn set_buttons(&mut self, buttons: JoypadButton) {
+        self.buttons = buttons;
+    }
+
+    pub fn set_button_pressed(&mut self, button: JoypadButton, pressed: bool) {
+        self.buttons.set(button, pressed);
+    }
+
+    pub fn buttons(&self) -> JoypadButton {
+        self.buttons
+    }
+}
+
+impl InputDevice for Joypad {
+    fn write(&mut self, data: u8) {
+        // the register keeps loading while the strobe is high, the last load
+        // is when it goes low
+        let strobe = data & 1 != 0;
+        if strobe || self.strobe {
+            self.shift = self.buttons.bits();
+        }
+        self.strobe = strobe;
+    }
+
+    // bit 0 only
+    fn read(&mut self, _port: usize) -> u8 {
+        if self.strobe {
+            return self.buttons.bits() & 1;
+        }
+        let bit = self.shift & 1;
+        self.shift = self.shift >> 1 | 0x80;
+        bit
+    }
+
+    // a pad in a controller port is player 0 or 1, the players after them
+    // only get here when there is no Four Score or expansion device for them
+    fn set_player_buttons(&mut self, player: usize, buttons: JoypadButton) {
+        if player < 2 {
+            self.buttons = buttons;
+        }
+    }
+}
+
+impl Default for Joypad {
+    fn default() -> Self {
+        Joypad::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_shift_out() {
+        let mut joypad = Joypad::new();
+        joypad.set_buttons(JoypadButton::BUTTON_A | JoypadButton::START | JoypadButton::RIGHT);
+        joypad.write(1);
+        joypad.write(0);
+        let bits: Vec<u8> = (0..10).map(|_| joypad.read(0)).collect();
+        assert_eq!(bits, [1, 0, 0, 1, 0, 0, 0, 1, 1, 1]);
+    }
+
+    #[test]
+    fn test_strobe_high_repeats_a() {
+        let mut joypad = Joypad::new();
+        joypad.write(1);
+        assert_eq!(joypad.read(0), 0);
+        joypad.set_button_pressed(JoypadButton::BUTTON_A, true);
+        assert_eq!(joypad.read(0), 1);
+        assert_eq!(joypad.read(0), 1);
+
+        // the buttons are latched when the strobe goes low
+        joypad.write(0);
+        joypad.set_buttons(JoypadButton::empty());
+        assert_eq!(joypad.read(0), 1);
+        assert_eq!(joypad.read(0), 0);
+    }
+}
@@ -0,0 +1,151 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::input::joypad::{Joypad, JoypadButton};
+use crate::ppu::nes_ppu::PPU;
+
+pub mod joypad;
+pub mod four_score;
+pub mod zapper;
+pub mod arkanoid;
+pub mod power_pad;
+pub mod famicom;
+
+// Everything plugged into the two controller ports or the Famicom expansion
+// port. Every $4016 write goes to all devices, a read of $4016 (port 0) or
+// $4017 (port 1) asks the device in that port and the expansion device which
+// data lines they pull up.
+//
+//  controller ports  D0, D3, D4 of their own register
+//  expansion port    D1 of $4016, D1-D4 of $4017
+//
+// The bits no device drives are open bus. A device that takes both ports,
+// like the Four Score, is plugged into both and told which one is read.
+pub trait InputDevice {
+    // bits 0-2 of $4016, bit 0 is the strobe of the controllers
+    fn write(&mut self, _data: u8) {}
+    fn read(&mut self, port: usize) -> u8;
+    // called right before each read with the PPU where the beam is now
+    fn sense_light(&mut self, _ppu: &mut PPU) {}
+
+    // what the player does, each device takes the parts it has
+    fn set_player_buttons(&mut self, _player: usize, _buttons: JoypadButton) {}
+    fn set_trigger(&mut self, _pressed: bool) {}
+    // where a light gun points on the screen, None is off it
+    fn set_aim(&mut self, _aim: Option<(usize, usize)>) {}
+    // the knob of a paddle
+    fn set_position(&mut self, _position: u8) {}
+    // bit n is mat button n + 1
+    fn set_mat_buttons(&mut self, _buttons: u16) {}
+}
+
+pub type InputDeviceRef = Rc<RefCell<dyn InputDevice>>;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum InputPort {
+    Port1,
+    Port2,
+    Expansion,
+}
+
+pub fn joypad() -> InputDeviceRef {
+    Rc::new(RefCell::new(Joypad::new()))
+}
+
+// The devices for port 1, port 2 and the expansion port that the NES 2.0
+// "default expansion device" (byte 15) asks for. Anything not supported
+// gets the two standard controllers.
+pub fn default_devices(expansion_device: u8) -> [Option<InputDeviceRef>; 3] {
+    match expansion_device {
+        0x02 => {
+            let four_score: InputDeviceRef = Rc::new(RefCell::new(four_score::FourScore::new()));
+            [Some(four_score.clone()), Some(four_score), None]
+        }
+        0x03 => [Some(joypad()), Some(joypad()), Some(Rc::new(RefCell::new(famicom::FamicomPads::new())))],
+        0x08 => [Some(joypad()), Some(Rc::new(RefCell::new(zapper::Zapper::new()))), None],
+        0x09 => [
+            Some(Rc::new(RefCell::new(zapper::Zapper::new()))),
+            Some(Rc::new(RefCell::new(zapper::Zapper::new()))),
+            None,
+        ],
+        // side A or B of the mat, the wiring is the same
+        0x0B | 0x0C => [Some(joypad()), Some(Rc::new(RefCell::new(power_pad::PowerPad::new()))), None],
+        0x0F => [Some(joypad()), Some(Rc::new(RefCell::new(arkanoid::ArkanoidVaus::new(false)))), None],
+        0x10 => [Some(joypad()), Some(joypad()), Some(Rc::new(RefCell::new(arkanoid::ArkanoidVaus::new(true))))],
+        _ => [Some(joypad()), Some(joypad()), None],
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bus::Bus;
+    use crate::cartridge::test::test_rom;
+
+    fn read_byte(bus: &mut Bus, addr: u16) -> u8 {
+        (0..8).fold(0, |acc, _| acc << 1 | bus.read_mem(addr) & 1)
+    }
+
+    #[test]
+    fn test_four_score_from_header() {
+        let mut rom = test_rom();
+        rom.expansion_device = 0x02;
+        let mut bus = Bus::new(rom).unwrap();
+        bus.set_joypad_buttons(3, JoypadButton::BUTTON_A);
+        bus.write_mem(0x4016, 1);
+        bus.write_mem(0x4016, 0);
+        assert_eq!(read_byte(&mut bus, 0x4017), 0);
+        assert_eq!(read_byte(&mut bus, 0x4017), 0x80);
+        assert_eq!(read_byte(&mut bus, 0x4017), 0x20);
+    }
+
+    #[test]
+    fn test_players_past_the_ports_are_ignored() {
+        let mut rom = test_rom();
+        rom.expansion_device = 0x02;
+        let mut bus = Bus::new(rom).unwrap();
+        bus.set_joypad_buttons(4, JoypadButton::BUTTON_A);
+        bus.set_joypad_buttons(5, JoypadButton::BUTTON_A);
+        bus.write_mem(0x4016, 1);
+        bus.write_mem(0x4016, 0);
+        assert_eq!(read_byte(&mut bus, 0x4016), 0);
+        assert_eq!(read_byte(&mut bus, 0x4016), 0);
+        assert_eq!(read_byte(&mut bus, 0x4017), 0);
+        assert_eq!(read_byte(&mut bus, 0x4017), 0);
+
+        // two plain pads keep their buttons when players 2 and 3 are set too
+        let mut bus = Bus::new(test_rom()).unwrap();
+        bus.set_joypad_buttons(0, JoypadButton::BUTTON_A);
+        bus.set_joypad_buttons(1, JoypadButton::BUTTON_B);
+        bus.set_joypad_buttons(2, JoypadButton::START);
+        bus.set_joypad_buttons(3, JoypadButton::SELECT);
+        bus.write_mem(0x4016, 1);
+        bus.write_mem(0x4016, 0);
+        assert_eq!(read_byte(&mut bus, 0x4016), 0x80);
+        assert_eq!(read_byte(&mut bus, 0x4017), 0x40);
+
+        bus.connect_input(InputPort::Expansion, Some(Rc::new(RefCell::new(famicom::FamicomPads::new()))));
+        bus.set_joypad_buttons(4, JoypadButton::BUTTON_A);
+        bus.set_joypad_buttons(5, JoypadButton::BUTTON_A);
+        bus.write_mem(0x4016, 1);
+        bus.write_mem(0x4016, 0);
+        assert_eq!(bus.read_mem(0x4016), 0x41);
+    }
+
+    #[test]
+    fn test_expansion_port_drives_d1() {
+        let mut bus = Bus::new(test_rom()).unwrap();
+        bus.connect_input(InputPort::Expansion, Some(Rc::new(RefCell::new(famicom::FamicomPads::new()))));
+        bus.set_joypad_buttons(0, JoypadButton::BUTTON_A);
+        bus.set_joypad_buttons(2, JoypadButton::BUTTON_A);
+        bus.write_mem(0x4016, 1);
+        bus.write_mem(0x4016, 0);
+        assert_eq!(bus.read_mem(0x4016), 0x43);
+        assert_eq!(bus.read_mem(0x4017), 0x40);
+
+        // an empty port reads as open bus
+        bus.connect_input(InputPort::Port2, None);
+        bus.connect_input(InputPort::Expansion, None);
+        assert_eq!(bus.read_mem(0x4017), 0x40);
+    }
+}
@@ -0,0 +1,87 @@
+use crate::input::InputDevice;
+
+// The Power Pad mat (Family Trainer on the NES), 12 buttons numbered
+//
+//   1  2  3  4
+//   5  6  7  8
+//   9 10 11 12
+//
+// The strobe latches them into two shift registers, read together:
+//
+//  D3  2 1 5 9 6 10 11 7
+//  D4  4 3 12 8, then 1s
+//
+// Both return 1s after 8 reads.
+
+const D3_ORDER: [usize; 8] = [2, 1, 5, 9, 6, 10, 11, 7];
+const D4_ORDER: [usize; 4] = [4, 3, 12, 8];
+
+pub struct PowerPad {
+    buttons: u16,
+    strobe: bool,
+    shift_d3: u8,
+    shift_d4: u8,
+}
+
+impl PowerPad {
+    pub fn new() -> Self {
+        PowerPad {
+            buttons: 0,
+            strobe: false,
+            shift_d3: 0,
+            shift_d4: 0,
+        }
+    }
+
+    fn latch(&mut self) {
+        let pressed = |button: &usize| (self.buttons >> (button - 1)) as u8 & 1;
+        self.shift_d3 = D3_ORDER.iter().rev().fold(0, |acc, button| acc << 1 | pressed(button));
+        self.shift_d4 = D4_ORDER.iter().rev().fold(0x0F, |acc, button| acc << 1 | pressed(button));
+    }
+}
+
+impl InputDevice for PowerPad {
+    fn write(&mut self, data: u8) {
+        let strobe = data & 1 != 0;
+        if strobe || self.strobe {
+            self.latch();
+        }
+        self.strobe = strobe;
+    }
+
+    fn read(&mut self, _port: usize) -> u8 {
+        if self.strobe {
+            self.latch();
+        }
+        let data = (self.shift_d4 & 1) << 4 | (self.shift_d3 & 1) << 3;
+        self.shift_d3 = self.shift_d3 >> 1 | 0x80;
+        self.shift_d4 = self.shift_d4 >> 1 | 0x80;
+        data
+    }
+
+    fn set_mat_buttons(&mut self, buttons: u16) {
+        self.buttons = buttons;
+    }
+}
+
+impl Default for PowerPad {
+    fn default() -> Self {
+        PowerPad::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_button_order() {
+        let mut pad = PowerPad::new();
+        // buttons 1, 3 and 7
+        pad.set_mat_buttons(0b0000_0100_0101);
+        pad.write(1);
+        pad.write(0);
+        let reads: Vec<u8> = (0..9).map(|_| pad.read(1)).collect();
+        assert_eq!(reads, [0x00, 0x18, 0x00, 0x00, 0x10, 0x10, 0x10, 0x18, 0x18]);
+    }
+}
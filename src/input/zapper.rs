@@ -0,0 +1,108 @@
+use crate::input::InputDevice;
+use crate::ppu::nes_ppu::PPU;
+
+// The NES Zapper light gun, it doesn't use the strobe.
+//
+// ---T L---   T trigger pulled, L 0 while the sensor sees light
+//
+// The sensor looks at the pixel under the aim point of the frame being drawn,
+// so games see the flash of white targets they put up after the trigger.
+pub struct Zapper {
+    trigger: bool,
+    aim: Option<(usize, usize)>,
+    light: bool,
+}
+
+impl Zapper {
+    pub fn new() -> Self {
+        Zapper {
+            trigger: false,
+            aim: None,
+            light: false,
+        }
+    }
+}
+
+impl InputDevice for Zapper {
+    fn read(&mut self, _port: usize) -> u8 {
+        (self.trigger as u8) << 4 | (!self.light as u8) << 3
+    }
+
+    fn sense_light(&mut self, ppu: &mut PPU) {
+        self.light = match self.aim {
+            Some((x, y)) => ppu.light_at(x, y),
+            None => false,
+        };
+    }
+
+    fn set_trigger(&mut self, pressed: bool) {
+        self.trigger = pressed;
+    }
+
+    fn set_aim(&mut self, aim: Option<(usize, usize)>) {
+        self.aim = aim;
+    }
+}
+
+impl Default for Zapper {
+    fn default() -> Self {
+        Zapper::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bus::Bus;
+    use crate::cartridge::test::test_rom;
+    use crate::input::InputPort;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    #[test]
+    fn test_light_at_beam_position() {
+        let mut bus = Bus::new(test_rom()).unwrap();
+        let zapper = Rc::new(RefCell::new(Zapper::new()));
+        bus.connect_input(InputPort::Port2, Some(zapper.clone()));
+        bus.ppu.frame.set_pixel(100, 50, (0xFF, 0xFF, 0xFF));
+        bus.ppu.frame.set_pixel(101, 50, (0x5C, 0x94, 0xFC));
+
+        zapper.borrow_mut().set_aim(Some((100, 50)));
+        zapper.borrow_mut().set_trigger(true);
+        // not drawn yet this frame
+        bus.ppu.scanline = 50;
+        assert_eq!(bus.read_mem(0x4017), 0x40 | 0x18);
+        bus.ppu.scanline = 60;
+        assert_eq!(bus.read_mem(0x4017), 0x40 | 0x10);
+        // the light has faded
+        bus.ppu.scanline = 80;
+        assert_eq!(bus.read_mem(0x4017), 0x40 | 0x18);
+
+        // too dark, and off the screen
+        bus.ppu.scanline = 60;
+        zapper.borrow_mut().set_aim(Some((101, 50)));
+        assert_eq!(bus.read_mem(0x4017) & 0x08, 0x08);
+        zapper.borrow_mut().set_aim(None);
+        assert_eq!(bus.read_mem(0x4017) & 0x08, 0x08);
+    }
+
+    #[test]
+    fn test_light_at_beam_dot() {
+        let mut bus = Bus::new(test_rom()).unwrap();
+        let zapper = Rc::new(RefCell::new(Zapper::new()));
+        bus.connect_input(InputPort::Port2, Some(zapper.clone()));
+        zapper.borrow_mut().set_aim(Some((100, 50)));
+        // rendering off, the line is the white backdrop
+        bus.ppu.palette_table[0] = 0x30;
+
+        bus.ppu.scanline = 50;
+        bus.ppu.cycles = 100;
+        assert_eq!(bus.read_mem(0x4017) & 0x08, 0x08);
+        bus.ppu.cycles = 102;
+        assert_eq!(bus.read_mem(0x4017) & 0x08, 0);
+        // and it stays lit on the next lines
+        bus.ppu.scanline = 51;
+        bus.ppu.cycles = 0;
+        assert_eq!(bus.read_mem(0x4017) & 0x08, 0);
+    }
+}
@@ -4,4 +4,17 @@ pub mod bus;
 pub mod cartridge;
 pub mod snake;
 pub mod log;
-pub mod ppu;
\ No newline at end of file
+pub mod ppu;
+pub mod mapper;
+pub mod save;
+pub mod hash;
+pub mod romdb;
+pub mod patch;
+pub mod unif;
+pub mod nsf;
+pub mod vs;
+pub mod region;
+pub mod apu;
+pub mod wav;
+pub mod input;
+pub mod player;
\ No newline at end of file
@@ -140,7 +140,7 @@ mod test {
 
    #[test]
    fn test_format_trace() {
-       let mut bus = Bus::new(test_rom());
+       let mut bus = Bus::new(test_rom()).unwrap();
        bus.write_mem(100, 0xa2);
        bus.write_mem(101, 0x01);
        bus.write_mem(102, 0xca);
@@ -172,7 +172,7 @@ mod test {
 
    #[test]
    fn test_format_mem_access() {
-       let mut bus = Bus::new(test_rom());
+       let mut bus = Bus::new(test_rom()).unwrap();
        // ORA ($33), Y
        bus.write_mem(100, 0x11);
        bus.write_mem(101, 0x33);
@@ -3,11 +3,24 @@ use nes_emulator::cartridge;
 use nes_emulator::cpu::CPU;
 use nes_emulator::bus;
 use nes_emulator::log;
+use nes_emulator::save::FileStorage;
+use nes_emulator::apu::capture::AudioCapture;
+use nes_emulator::wav::SampleFormat;
+use nes_emulator::input::zapper::Zapper;
+use nes_emulator::input::InputPort;
+use nes_emulator::player;
+use std::cell::RefCell;
+use std::rc::Rc;
 //use nes_emulator::snake;
 fn nes_test(){
-    let bytes: Vec<u8> = std::fs::read("./games/nestest.nes").unwrap();
-    let rom = cartridge::Rom::new(&bytes).unwrap();
-    let mut cpu = CPU::new(bus::Bus::new(rom));
+    let path = "./games/nestest.nes";
+    let rom = cartridge::Rom::from_path(path).unwrap();
+    for warning in &rom.warnings {
+        eprintln!("{}", warning);
+    }
+    let mut bus = bus::Bus::new(rom).unwrap();
+    bus.set_save_storage(Box::new(FileStorage::next_to_rom(path)));
+    let mut cpu = CPU::new(bus);
     cpu.reset();
     cpu.program_counter = 0xC000;
     cpu.run_with_callback(move|cpu|{
@@ -17,15 +30,90 @@ fn nes_test(){
 
 fn _dummy_read(){
     let bytes: Vec<u8> = std::fs::read("./games/cpu_dummy_reads.nes").unwrap();
-    let rom = cartridge::Rom::new(&bytes).unwrap();
-    let mut cpu = CPU::new(bus::Bus::new(rom));
+    let rom = cartridge::Rom::from_bytes(&bytes).unwrap();
+    let mut cpu = CPU::new(bus::Bus::new(rom).unwrap());
     cpu.reset();
     //cpu.program_counter = 0x8000;
     cpu.run_with_callback(move|cpu|{
         println!("{}",log::log(cpu));
     });
 }
+const USAGE: &str = "usage: nes_emulator <rom> [--window] [--zapper] [--frames N] [--wav PATH] [--float] [--split-channels] [--sample-rate HZ]";
+
+// runs a ROM without a window for a number of frames, optionally recording
+// the audio, or in a window to play it
+fn run(args: &[String]) -> Result<(), String> {
+    let path = &args[0];
+    let mut frames = None;
+    let mut wav = None;
+    let mut format = SampleFormat::Pcm16;
+    let mut split_channels = false;
+    let mut sample_rate = 48_000;
+    let mut window = false;
+    let mut zapper = false;
+
+    let mut options = args[1..].iter();
+    while let Some(option) = options.next() {
+        let mut value = || options.next().ok_or(format!("{} needs a value", option));
+        match option.as_str() {
+            "--frames" => frames = Some(value()?.parse().map_err(|e| format!("--frames: {}", e))?),
+            "--wav" => wav = Some(value()?.clone()),
+            "--float" => format = SampleFormat::Float32,
+            "--split-channels" => split_channels = true,
+            "--sample-rate" => sample_rate = value()?.parse().map_err(|e| format!("--sample-rate: {}", e))?,
+            "--window" => window = true,
+            // in port 2, for carts whose header doesn't ask for it
+            "--zapper" => zapper = true,
+            _ => return Err(format!("unknown option {}", option)),
+        }
+    }
+
+    // the window runs until it is closed and has no audio capture
+    if window && (frames.is_some() || wav.is_some()) {
+        return Err("--frames and --wav don't work with --window".to_string());
+    }
+
+    let rom = cartridge::Rom::from_path(path).map_err(|e| format!("{}: {}", path, e))?;
+    let mut bus = bus::Bus::new(rom).map_err(|e| format!("{}: {}", path, e))?;
+    bus.set_save_storage(Box::new(FileStorage::next_to_rom(path)));
+    if zapper {
+        bus.connect_input(InputPort::Port2, Some(Rc::new(RefCell::new(Zapper::new()))));
+    }
+    if window {
+        player::play(bus);
+        return Ok(());
+    }
+    if let Some(wav) = &wav {
+        let capture = AudioCapture::create(wav, bus.region(), sample_rate, format, split_channels)
+            .map_err(|e| format!("{}: {}", wav, e))?;
+        bus.start_audio_capture(capture);
+    }
+    let mut cpu = CPU::new(bus);
+    cpu.reset();
+    let frames = frames.unwrap_or(600);
+    while cpu.bus.frame_count() < frames && cpu.step() {}
+
+    cpu.bus.flush_save_ram();
+
+    if let Some(capture) = cpu.bus.stop_audio_capture() {
+        capture.finish().map_err(|e| format!("{}: {}", wav.unwrap(), e))?;
+    }
+    Ok(())
+}
+
 fn main() {
-    nes_test();
-    //dummy_read();
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.is_empty() {
+        nes_test();
+        //dummy_read();
+        return;
+    }
+    if args[0].starts_with("--") {
+        eprintln!("{}", USAGE);
+        std::process::exit(2);
+    }
+    if let Err(e) = run(&args) {
+        eprintln!("{}\n{}", e, USAGE);
+        std::process::exit(1);
+    }
 }
\ No newline at end of file
@@ -0,0 +1,54 @@
+use crate::cartridge::{Mirroring, Rom};
+use crate::mapper::{bank_offset, ChrMemory, Mapper, PrgRam};
+
+// Mapper 3, fixed PRG like NROM and a single 8K CHR bank selected by any write to $8000-$FFFF.
+pub struct Cnrom {
+    prg_rom: Vec<u8>,
+    chr: ChrMemory,
+    prg_ram: PrgRam,
+    mirroring: Mirroring,
+    chr_bank: u8,
+}
+
+impl Cnrom {
+    pub fn new(mut rom: Rom) -> Self {
+        let chr = ChrMemory::new(&mut rom);
+        let prg_ram = PrgRam::new(&rom);
+        Cnrom {
+            prg_rom: rom.prg_rom,
+            chr,
+            prg_ram,
+            mirroring: rom.screen_mirroring,
+            chr_bank: 0,
+        }
+    }
+}
+
+impl Mapper for Cnrom {
+    fn read_prg(&mut self, addr: u16) -> u8 {
+        let addr = (addr - 0x8000) as usize % self.prg_rom.len();
+        self.prg_rom[addr]
+    }
+
+    fn write_prg(&mut self, _addr: u16, data: u8) {
+        self.chr_bank = data;
+    }
+
+    fn read_chr(&mut self, addr: u16) -> u8 {
+        let bank = bank_offset(self.chr.len(), 0x2000, self.chr_bank as usize);
+        self.chr.read(bank + addr as usize)
+    }
+
+    fn write_chr(&mut self, addr: u16, data: u8) {
+        let bank = bank_offset(self.chr.len(), 0x2000, self.chr_bank as usize);
+        self.chr.write(bank + addr as usize, data);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn prg_ram(&mut self) -> &mut PrgRam {
+        &mut self.prg_ram
+    }
+}
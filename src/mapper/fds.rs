@@ -0,0 +1,576 @@
+use crate::cartridge::{Mirroring, Rom};
+use crate::mapper::fds_audio::{self, FdsAudio};
+use crate::mapper::{ChrMemory, Mapper, PrgRam};
+use crate::patch;
+
+// Famicom Disk System, the RAM adapter and the disk drive behind it (mapper 20).
+//
+//  $4020/$4021  timer IRQ reload value
+//  $4022        timer IRQ control: bit 0 repeat, bit 1 enabled
+//  $4023        bit 0 enables the disk registers, bit 1 the sound registers
+//  $4024        byte to write to the disk
+//  $4025        drive control:
+//               IS1B MRTD
+//               |||| |||+- motor on
+//               |||| ||+-- transfer reset, hold the head at the start
+//               |||| |+--- 1 read, 0 write
+//               |||| +---- mirroring, 1 horizontal
+//               |||+------ write the CRC
+//               |+-------- disk ready, a block follows
+//               +--------- IRQ after every byte
+//  $4030        status: timer IRQ, byte transferred, end of head (reading acknowledges)
+//  $4031        byte read from the disk
+//  $4032        drive status: not inserted, not ready, write protected
+//  $4040-$4092  sound, see fds_audio
+//
+// $6000-$DFFF is RAM and the BIOS sits at $E000-$FFFF. The pattern tables
+// are 8K of CHR RAM.
+//
+// The .fds image only holds the blocks of each side. Under the head they are
+// laid out like on the disk: a long gap of zero bits, a start mark ($80), the
+// block, its CRC and the gap before the next block.
+
+pub const DISK_SIDE_SIZE: usize = 65500;
+
+const FIRST_GAP: usize = 28300 / 8;
+const BLOCK_GAP: usize = 976 / 8;
+// the data of a full side plus the gaps and CRCs of all its blocks
+const RAW_SIDE_SIZE: usize = 76000;
+
+// CPU cycles before the head reaches the first gap after the motor starts
+const HEAD_RESET_DELAY: u32 = 50000;
+// CPU cycles per byte under the head, about 96.4 kbit/s
+const BYTE_DELAY: u32 = 150;
+// a swapped side stays out of the drive long enough for the BIOS to notice
+const INSERT_DELAY: u32 = 1_800_000;
+
+fn block_len(side: &[u8], pos: usize) -> Option<usize> {
+    match side.get(pos)? {
+        1 => Some(56),
+        2 => Some(2),
+        3 => Some(16),
+        // the size is in the file header block right before
+        4 if pos >= 3 => Some(1 + (side[pos - 3] as usize | (side[pos - 2] as usize) << 8)),
+        _ => None,
+    }
+}
+
+fn update_crc(crc: u16, data: u8) -> u16 {
+    let mut crc = crc;
+    for bit in 0..8 {
+        let carry = crc & 1 != 0;
+        crc >>= 1;
+        if carry {
+            crc ^= 0x8408;
+        }
+        if data & (1 << bit) != 0 {
+            crc ^= 0x8000;
+        }
+    }
+    crc
+}
+
+fn raw_side(side: &[u8]) -> Vec<u8> {
+    let mut raw = vec![0; FIRST_GAP];
+    let mut pos = 0;
+    while let Some(len) = block_len(side, pos) {
+        if pos + len > side.len() {
+            break;
+        }
+        let block = &side[pos..pos + len];
+        let crc = std::iter::once(&0x80).chain(block).fold(0, |crc, b| update_crc(crc, *b));
+        let crc = update_crc(update_crc(crc, 0), 0);
+        raw.push(0x80);
+        raw.extend_from_slice(block);
+        raw.extend_from_slice(&crc.to_le_bytes());
+        raw.extend(std::iter::repeat_n(0, BLOCK_GAP));
+        pos += len;
+    }
+    raw.resize(raw.len().max(RAW_SIDE_SIZE), 0);
+    raw
+}
+
+fn image_side(raw: &[u8]) -> Vec<u8> {
+    let mut side = Vec::with_capacity(DISK_SIDE_SIZE);
+    let mut pos = 0;
+    loop {
+        // skip the gap up to the start mark
+        while pos < raw.len() && raw[pos] != 0x80 {
+            pos += 1;
+        }
+        pos += 1;
+        let start = side.len();
+        let block = raw.get(pos).copied();
+        side.extend(block);
+        let len = match block_len(&side, start) {
+            Some(len) if pos + len <= raw.len() && start + len <= DISK_SIDE_SIZE => len,
+            _ => {
+                side.truncate(start);
+                break;
+            }
+        };
+        side.extend_from_slice(&raw[pos + 1..pos + len]);
+        pos += len + 2;
+    }
+    side.resize(DISK_SIDE_SIZE, 0);
+    side
+}
+
+pub struct Fds {
+    bios: Vec<u8>,
+    prg_ram: PrgRam,
+    chr: ChrMemory,
+    mirroring: Mirroring,
+
+    original: Vec<u8>,
+    sides: Vec<Vec<u8>>,
+    side: Option<usize>,
+    next_side: Option<usize>,
+    insert_delay: u32,
+    disk_dirty: bool,
+
+    disk_regs_enabled: bool,
+    sound_regs_enabled: bool,
+
+    irq_reload: u16,
+    irq_counter: u16,
+    irq_repeat: bool,
+    irq_enabled: bool,
+    timer_irq: bool,
+
+    motor_on: bool,
+    reset_transfer: bool,
+    read_mode: bool,
+    crc_control: bool,
+    disk_ready: bool,
+    disk_irq_enabled: bool,
+    disk_irq: bool,
+    transfer_complete: bool,
+    read_data: u8,
+    write_data: u8,
+
+    position: usize,
+    delay: u32,
+    end_of_head: bool,
+    scanning: bool,
+    gap_ended: bool,
+    previous_crc_control: bool,
+    crc: u16,
+
+    audio: FdsAudio,
+}
+
+impl Fds {
+    pub fn new(mut rom: Rom) -> Self {
+        let chr = ChrMemory::new(&mut rom);
+        let prg_ram = PrgRam::new(&rom);
+        let sides: Vec<Vec<u8>> = rom.disk.chunks(DISK_SIDE_SIZE).map(raw_side).collect();
+        Fds {
+            bios: rom.prg_rom,
+            prg_ram,
+            chr,
+            mirroring: Mirroring::HORIZONTAL,
+            original: rom.disk,
+            side: if sides.is_empty() { None } else { Some(0) },
+            sides,
+            next_side: None,
+            insert_delay: 0,
+            disk_dirty: false,
+            disk_regs_enabled: false,
+            sound_regs_enabled: false,
+            irq_reload: 0,
+            irq_counter: 0,
+            irq_repeat: false,
+            irq_enabled: false,
+            timer_irq: false,
+            motor_on: false,
+            reset_transfer: false,
+            read_mode: true,
+            crc_control: false,
+            disk_ready: false,
+            disk_irq_enabled: false,
+            disk_irq: false,
+            transfer_complete: false,
+            read_data: 0,
+            write_data: 0,
+            position: 0,
+            delay: 0,
+            end_of_head: true,
+            scanning: false,
+            gap_ended: false,
+            previous_crc_control: false,
+            crc: 0,
+            audio: FdsAudio::new(),
+        }
+    }
+
+    fn write_control(&mut self, data: u8) {
+        self.motor_on = data & 0x01 != 0;
+        self.reset_transfer = data & 0x02 != 0;
+        self.read_mode = data & 0x04 != 0;
+        self.mirroring = if data & 0x08 != 0 { Mirroring::HORIZONTAL } else { Mirroring::VERTICAL };
+        self.crc_control = data & 0x10 != 0;
+        self.disk_ready = data & 0x40 != 0;
+        self.disk_irq_enabled = data & 0x80 != 0;
+        self.disk_irq = false;
+    }
+
+    fn clock_timer(&mut self) {
+        if !self.irq_enabled {
+            return;
+        }
+        if self.irq_counter == 0 {
+            self.timer_irq = true;
+            self.irq_counter = self.irq_reload;
+            if !self.irq_repeat {
+                self.irq_enabled = false;
+            }
+        } else {
+            self.irq_counter -= 1;
+        }
+    }
+
+    fn clock_disk(&mut self) {
+        if self.insert_delay > 0 {
+            self.insert_delay -= 1;
+            if self.insert_delay == 0 {
+                self.side = self.next_side.take();
+            }
+        }
+        let side = match self.side {
+            Some(side) if self.motor_on => side,
+            _ => {
+                self.end_of_head = true;
+                self.scanning = false;
+                return;
+            }
+        };
+        if self.reset_transfer && !self.scanning {
+            return;
+        }
+        if self.end_of_head {
+            self.delay = HEAD_RESET_DELAY;
+            self.end_of_head = false;
+            self.position = 0;
+            self.gap_ended = false;
+            return;
+        }
+        if self.delay > 0 {
+            self.delay -= 1;
+            return;
+        }
+
+        self.scanning = true;
+        let raw = &mut self.sides[side];
+        if self.read_mode {
+            let data = raw[self.position];
+            if !self.previous_crc_control {
+                self.crc = update_crc(self.crc, data);
+            }
+            let mut raise_irq = self.disk_irq_enabled;
+            if !self.disk_ready {
+                self.gap_ended = false;
+                self.crc = 0;
+            } else if data != 0 && !self.gap_ended {
+                // the start mark itself doesn't interrupt
+                self.gap_ended = true;
+                raise_irq = false;
+            }
+            if self.gap_ended {
+                self.transfer_complete = true;
+                self.read_data = data;
+                self.disk_irq |= raise_irq;
+            }
+        } else {
+            let mut data = 0;
+            if !self.crc_control {
+                self.transfer_complete = true;
+                self.disk_irq |= self.disk_irq_enabled;
+                data = self.write_data;
+            }
+            if !self.disk_ready {
+                data = 0;
+                self.crc = 0;
+            }
+            if !self.crc_control {
+                self.crc = update_crc(self.crc, data);
+            } else {
+                if !self.previous_crc_control {
+                    self.crc = update_crc(update_crc(self.crc, 0), 0);
+                }
+                data = self.crc as u8;
+                self.crc >>= 8;
+            }
+            raw[self.position] = data;
+            self.disk_dirty = true;
+            self.gap_ended = false;
+        }
+
+        self.previous_crc_control = self.crc_control;
+        self.position += 1;
+        if self.position >= raw.len() {
+            self.motor_on = false;
+        } else {
+            self.delay = BYTE_DELAY;
+        }
+    }
+
+    fn image(&self) -> Vec<u8> {
+        self.sides.iter().flat_map(|raw| image_side(raw)).collect()
+    }
+}
+
+impl Mapper for Fds {
+    fn read_prg(&mut self, addr: u16) -> u8 {
+        match addr {
+            0x8000..=0xDFFF => self.prg_ram.read((addr - 0x6000) as usize).unwrap_or(0),
+            _ => self.bios[(addr & 0x1FFF) as usize],
+        }
+    }
+
+    fn write_prg(&mut self, addr: u16, data: u8) {
+        if addr < 0xE000 {
+            self.prg_ram.write((addr - 0x6000) as usize, data);
+        }
+    }
+
+    fn read_chr(&mut self, addr: u16) -> u8 {
+        self.chr.read(addr as usize)
+    }
+
+    fn write_chr(&mut self, addr: u16, data: u8) {
+        self.chr.write(addr as usize, data);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn prg_ram(&mut self) -> &mut PrgRam {
+        &mut self.prg_ram
+    }
+
+    fn read_expansion(&mut self, addr: u16) -> Option<u8> {
+        match addr {
+            0x4030 => {
+                let mut status = 0;
+                status |= self.timer_irq as u8;
+                status |= (self.transfer_complete as u8) << 1;
+                status |= (self.end_of_head as u8) << 6;
+                self.timer_irq = false;
+                self.transfer_complete = false;
+                self.disk_irq = false;
+                Some(status)
+            }
+            0x4031 => {
+                self.transfer_complete = false;
+                self.disk_irq = false;
+                Some(self.read_data)
+            }
+            0x4032 => {
+                let inserted = self.side.is_some();
+                let mut status = 0x40;
+                status |= !inserted as u8;
+                status |= ((!inserted || !self.scanning) as u8) << 1;
+                status |= (!inserted as u8) << 2;
+                Some(status)
+            }
+            // battery good
+            0x4033 => Some(0x80),
+            0x4040..=0x4092 if self.sound_regs_enabled => self.audio.read(addr),
+            _ => None,
+        }
+    }
+
+    fn write_expansion(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x4020 => self.irq_reload = (self.irq_reload & 0xFF00) | data as u16,
+            0x4021 => self.irq_reload = (self.irq_reload & 0x00FF) | (data as u16) << 8,
+            0x4022 => {
+                self.irq_repeat = data & 0x01 != 0;
+                self.irq_enabled = data & 0x02 != 0 && self.disk_regs_enabled;
+                if self.irq_enabled {
+                    self.irq_counter = self.irq_reload;
+                } else {
+                    self.timer_irq = false;
+                }
+            }
+            0x4023 => {
+                self.disk_regs_enabled = data & 0x01 != 0;
+                self.sound_regs_enabled = data & 0x02 != 0;
+                if !self.disk_regs_enabled {
+                    self.irq_enabled = false;
+                    self.timer_irq = false;
+                    self.disk_irq = false;
+                }
+            }
+            0x4024 if self.disk_regs_enabled => {
+                self.write_data = data;
+                self.transfer_complete = false;
+                self.disk_irq = false;
+            }
+            0x4025 if self.disk_regs_enabled => self.write_control(data),
+            0x4040..=0x4092 if self.sound_regs_enabled => self.audio.write(addr, data),
+            _ => {}
+        }
+    }
+
+    fn cpu_clock(&mut self) {
+        self.clock_timer();
+        self.clock_disk();
+        self.audio.clock();
+    }
+
+    fn irq_pending(&self) -> bool {
+        self.timer_irq || self.disk_irq
+    }
+
+    fn audio_output(&self) -> f32 {
+        self.audio.output()
+    }
+
+    fn expansion_channels(&self) -> Vec<&'static str> {
+        fds_audio::CHANNELS.to_vec()
+    }
+
+    fn set_expansion_gains(&mut self, gains: &[f32]) {
+        self.audio.set_gains(gains);
+    }
+
+    // the save is an IPS patch from the original image to the written one
+    fn has_save(&mut self) -> bool {
+        !self.sides.is_empty()
+    }
+
+    fn load_save(&mut self, data: &[u8]) {
+        match patch::apply_ips(&self.original, data) {
+            Ok(image) if image.len() == self.original.len() => {
+                self.sides = image.chunks(DISK_SIDE_SIZE).map(raw_side).collect();
+            }
+            _ => println!("Ignoring disk changes that don't fit the disk image"),
+        }
+    }
+
+    fn take_save(&mut self) -> Option<Vec<u8>> {
+        if !std::mem::take(&mut self.disk_dirty) {
+            return None;
+        }
+        patch::create_ips(&self.original, &self.image()).ok()
+    }
+
+    fn disk_sides(&self) -> usize {
+        self.sides.len()
+    }
+
+    fn insert_disk(&mut self, side: Option<usize>) {
+        self.side = None;
+        self.next_side = side.filter(|side| *side < self.sides.len());
+        self.insert_delay = if self.next_side.is_some() { INSERT_DELAY } else { 0 };
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cartridge::HeaderFormat;
+
+    fn disk() -> Vec<u8> {
+        let mut side = vec![0x01];
+        side.extend_from_slice(b"*NINTENDO-HVC*");
+        side.resize(56, 0);
+        side.extend_from_slice(&[0x02, 0x01]);
+        // file header for a 4 byte file, then the file
+        side.extend_from_slice(&[0x03, 0, 0, b'F', b'I', b'L', b'E', 0, 0, 0, 0, 0x00, 0x60, 4, 0, 0x00]);
+        side.extend_from_slice(&[0x04, 1, 2, 3, 4]);
+        side.resize(DISK_SIDE_SIZE, 0);
+        let mut disk = side.clone();
+        disk.extend_from_slice(&side);
+        disk
+    }
+
+    fn fds() -> Fds {
+        let mut rom = Rom::empty(HeaderFormat::Fds);
+        rom.prg_rom = vec![0xEA; 0x2000];
+        rom.prg_ram_size = 0x8000;
+        rom.chr_ram_size = 0x2000;
+        rom.disk = disk();
+        Fds::new(rom)
+    }
+
+    #[test]
+    fn test_raw_side_round_trip() {
+        let side = &disk()[..DISK_SIDE_SIZE];
+        let raw = raw_side(side);
+        assert_eq!(raw[FIRST_GAP], 0x80);
+        assert_eq!(raw[FIRST_GAP + 1], 0x01);
+        assert_eq!(image_side(&raw), side);
+    }
+
+    #[test]
+    fn test_timer_irq() {
+        let mut fds = fds();
+        fds.write_expansion(0x4023, 0x01);
+        fds.write_expansion(0x4020, 2);
+        fds.write_expansion(0x4021, 0);
+        fds.write_expansion(0x4022, 0x02);
+        for _ in 0..2 {
+            fds.clock_timer();
+        }
+        assert!(!fds.irq_pending());
+        fds.clock_timer();
+        assert!(fds.irq_pending());
+        assert_eq!(fds.read_expansion(0x4030).unwrap() & 1, 1);
+        assert!(!fds.irq_pending());
+    }
+
+    #[test]
+    fn test_read_disk() {
+        let mut fds = fds();
+        fds.write_expansion(0x4023, 0x01);
+        // motor on, read mode, disk ready
+        fds.write_expansion(0x4025, 0x65);
+        assert_eq!(fds.read_expansion(0x4032).unwrap() & 0b111, 0b010);
+
+        let mut bytes = vec![];
+        for _ in 0..(HEAD_RESET_DELAY as usize + (FIRST_GAP + 4) * (BYTE_DELAY as usize + 1)) {
+            fds.cpu_clock();
+            if fds.transfer_complete {
+                bytes.push(fds.read_expansion(0x4031).unwrap());
+            }
+        }
+        assert_eq!(&bytes[..4], &[0x80, 0x01, b'*', b'N']);
+        assert_eq!(fds.read_expansion(0x4032).unwrap() & 0b111, 0b000);
+    }
+
+    #[test]
+    fn test_disk_changes_are_saved_as_ips() {
+        let mut fds = fds();
+        assert!(fds.has_save());
+        assert_eq!(fds.take_save(), None);
+
+        // the first byte of the file on side B
+        let pos = FIRST_GAP + (1 + 2 + BLOCK_GAP) * 3 + 56 + 2 + 16 + 2;
+        assert_eq!(fds.sides[1][pos..pos + 4], [1, 2, 3, 4]);
+        fds.sides[1][pos] = 0x99;
+        fds.disk_dirty = true;
+        let save = fds.take_save().unwrap();
+        assert_eq!(save.len(), 5 + 3 + 2 + 1 + 3);
+
+        let mut other = self::fds();
+        other.load_save(&save);
+        assert_eq!(other.image()[DISK_SIDE_SIZE + 56 + 2 + 16 + 1], 0x99);
+    }
+
+    #[test]
+    fn test_side_switching() {
+        let mut fds = fds();
+        assert_eq!(fds.disk_sides(), 2);
+        fds.insert_disk(Some(1));
+        assert_eq!(fds.read_expansion(0x4032).unwrap() & 1, 1);
+        for _ in 0..INSERT_DELAY {
+            fds.clock_disk();
+        }
+        assert_eq!(fds.side, Some(1));
+        assert_eq!(fds.read_expansion(0x4032).unwrap() & 1, 0);
+    }
+}
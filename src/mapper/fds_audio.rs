@@ -0,0 +1,273 @@
+// FDS expansion audio, a single wavetable channel with frequency modulation.
+//
+//  $4040-$407F  64 step wavetable, 6 bits per step, writable while $4089.7 is set
+//  $4080        volume envelope: E D SSSSSS (envelope off, increase, speed or gain)
+//  $4082/$4083  wave frequency, $4083.7 halts the wave, $4083.6 the envelopes
+//  $4084        modulation envelope, same layout as $4080
+//  $4085        modulation counter (7 bit signed)
+//  $4086/$4087  modulation frequency, $4087.7 halts modulation
+//  $4088        appends an entry to the 32 step modulation table while halted
+//  $4089        wave write enable and master volume (2/2, 2/3, 2/4, 2/5)
+//  $408A        envelope speed multiplier
+//  $4090/$4092  read the volume and modulation gains
+
+// the wave at full volume is about as loud as two APU pulses
+const AUDIO_VOLUME: f32 = 0.25;
+
+const MASTER_VOLUME: [f32; 4] = [1.0, 2.0 / 3.0, 2.0 / 4.0, 2.0 / 5.0];
+
+// modulation table entries change the counter by this much, None resets it
+const MOD_STEPS: [Option<i8>; 8] = [Some(0), Some(1), Some(2), Some(4), None, Some(-4), Some(-2), Some(-1)];
+
+struct Envelope {
+    disabled: bool,
+    increase: bool,
+    speed: u8,
+    gain: u8,
+    timer: u32,
+}
+
+impl Envelope {
+    fn new() -> Self {
+        Envelope {
+            disabled: true,
+            increase: false,
+            speed: 0,
+            gain: 0,
+            timer: 0,
+        }
+    }
+
+    fn write(&mut self, data: u8) {
+        self.disabled = data & 0x80 != 0;
+        self.increase = data & 0x40 != 0;
+        self.speed = data & 0x3F;
+        if self.disabled {
+            self.gain = data & 0x3F;
+        }
+        self.timer = 0;
+    }
+
+    fn clock(&mut self, master_speed: u8) {
+        if self.disabled || master_speed == 0 {
+            return;
+        }
+        self.timer += 1;
+        if self.timer < 8 * master_speed as u32 * (self.speed as u32 + 1) {
+            return;
+        }
+        self.timer = 0;
+        if self.increase && self.gain < 32 {
+            self.gain += 1;
+        } else if !self.increase && self.gain > 0 {
+            self.gain -= 1;
+        }
+    }
+}
+
+pub struct FdsAudio {
+    wave_table: [u8; 64],
+    wave_write: bool,
+    wave_frequency: u16,
+    wave_halted: bool,
+    wave_accumulator: u32,
+    envelopes_halted: bool,
+    master_volume: u8,
+    envelope_speed: u8,
+
+    volume: Envelope,
+    modulation: Envelope,
+    mod_table: [u8; 64],
+    mod_position: usize,
+    mod_counter: i8,
+    mod_frequency: u16,
+    mod_halted: bool,
+    mod_accumulator: u32,
+
+    output: u8,
+    gain: f32,
+}
+
+pub const CHANNELS: [&str; 1] = ["FDS"];
+
+impl FdsAudio {
+    pub fn new() -> Self {
+        FdsAudio {
+            wave_table: [0; 64],
+            wave_write: false,
+            wave_frequency: 0,
+            wave_halted: true,
+            wave_accumulator: 0,
+            envelopes_halted: false,
+            master_volume: 0,
+            envelope_speed: 0xE8,
+            volume: Envelope::new(),
+            modulation: Envelope::new(),
+            mod_table: [0; 64],
+            mod_position: 0,
+            mod_counter: 0,
+            mod_frequency: 0,
+            mod_halted: true,
+            mod_accumulator: 0,
+            output: 0,
+            gain: 1.0,
+        }
+    }
+
+    // the volume of the one channel, only changes what comes out
+    pub fn set_gains(&mut self, gains: &[f32]) {
+        if let Some(gain) = gains.first() {
+            self.gain = *gain;
+        }
+    }
+
+    pub fn read(&self, addr: u16) -> Option<u8> {
+        // the upper bits come from the open bus
+        match addr {
+            0x4040..=0x407F => Some(self.wave_table[(addr - 0x4040) as usize] | 0x40),
+            0x4090 => Some(self.volume.gain | 0x40),
+            0x4092 => Some(self.modulation.gain | 0x40),
+            _ => None,
+        }
+    }
+
+    pub fn write(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x4040..=0x407F if self.wave_write => self.wave_table[(addr - 0x4040) as usize] = data & 0x3F,
+            0x4080 => self.volume.write(data),
+            0x4082 => self.wave_frequency = (self.wave_frequency & 0x0F00) | data as u16,
+            0x4083 => {
+                self.wave_frequency = (self.wave_frequency & 0x00FF) | ((data & 0x0F) as u16) << 8;
+                self.wave_halted = data & 0x80 != 0;
+                self.envelopes_halted = data & 0x40 != 0;
+                if self.wave_halted {
+                    self.wave_accumulator = 0;
+                }
+            }
+            0x4084 => self.modulation.write(data),
+            // sign extend the 7 bit value
+            0x4085 => self.mod_counter = ((data & 0x7F) << 1) as i8 >> 1,
+            0x4086 => self.mod_frequency = (self.mod_frequency & 0x0F00) | data as u16,
+            0x4087 => {
+                self.mod_frequency = (self.mod_frequency & 0x00FF) | ((data & 0x0F) as u16) << 8;
+                self.mod_halted = data & 0x80 != 0;
+                if self.mod_halted {
+                    self.mod_accumulator = 0;
+                }
+            }
+            // every entry takes two steps of the table
+            0x4088 if self.mod_halted => {
+                self.mod_table[self.mod_position] = data & 0b111;
+                self.mod_table[(self.mod_position + 1) % 64] = data & 0b111;
+                self.mod_position = (self.mod_position + 2) % 64;
+            }
+            0x4089 => {
+                self.wave_write = data & 0x80 != 0;
+                self.master_volume = data & 0b11;
+            }
+            0x408A => self.envelope_speed = data,
+            _ => {}
+        }
+    }
+
+    pub fn clock(&mut self) {
+        if !self.envelopes_halted && !self.wave_halted {
+            self.volume.clock(self.envelope_speed);
+            self.modulation.clock(self.envelope_speed);
+        }
+
+        if !self.mod_halted && self.mod_frequency != 0 {
+            self.mod_accumulator += self.mod_frequency as u32;
+            if self.mod_accumulator >= 0x10000 {
+                self.mod_accumulator -= 0x10000;
+                self.step_modulation();
+            }
+        }
+
+        if !self.wave_halted && !self.wave_write {
+            self.wave_accumulator = (self.wave_accumulator + self.pitch()) & 0x3F_FFFF;
+            self.output = self.wave_table[(self.wave_accumulator >> 16) as usize];
+        }
+    }
+
+    fn step_modulation(&mut self) {
+        let entry = self.mod_table[self.mod_position];
+        self.mod_counter = match MOD_STEPS[entry as usize] {
+            // the counter wraps around in 7 bits
+            Some(step) => ((self.mod_counter.wrapping_add(step) as u8) << 1) as i8 >> 1,
+            None => 0,
+        };
+        self.mod_position = (self.mod_position + 1) % 64;
+    }
+
+    // wave frequency bent by the modulator, the rounding follows the hardware
+    fn pitch(&self) -> u32 {
+        let frequency = self.wave_frequency as i32;
+        if self.mod_halted {
+            return frequency as u32;
+        }
+        let counter = self.mod_counter as i32;
+        let mut temp = counter * self.modulation.gain as i32;
+        let remainder = temp & 0x0F;
+        temp >>= 4;
+        if remainder > 0 && temp & 0x80 == 0 {
+            temp += if counter < 0 { -1 } else { 2 };
+        }
+        if temp >= 192 {
+            temp -= 256;
+        } else if temp < -64 {
+            temp += 256;
+        }
+        temp *= frequency;
+        let remainder = temp & 0x3F;
+        temp >>= 6;
+        if remainder >= 32 {
+            temp += 1;
+        }
+        (frequency + temp).max(0) as u32
+    }
+
+    pub fn output(&self) -> f32 {
+        let gain = self.volume.gain.min(32) as f32;
+        let level = self.output as f32 * gain / (63.0 * 32.0);
+        level * MASTER_VOLUME[self.master_volume as usize] * AUDIO_VOLUME * self.gain
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_wave_plays_table() {
+        let mut audio = FdsAudio::new();
+        audio.write(0x4089, 0x80);
+        for i in 0..64 {
+            audio.write(0x4040 + i, i as u8);
+        }
+        audio.write(0x4089, 0x00);
+        audio.write(0x4080, 0x80 | 32);
+        // one table step every 32 cycles
+        audio.write(0x4082, 0x00);
+        audio.write(0x4083, 0x08);
+        for _ in 0..32 * 10 {
+            audio.clock();
+        }
+        assert_eq!(audio.output, 10);
+        assert_eq!(audio.read(0x4090), Some(0x40 | 32));
+        assert!(audio.output() > 0.0);
+    }
+
+    #[test]
+    fn test_mod_counter_wraps_in_7_bits() {
+        let mut audio = FdsAudio::new();
+        audio.write(0x4087, 0x80);
+        audio.write(0x4088, 3);
+        audio.mod_position = 0;
+        audio.write(0x4085, 0x3E);
+        audio.step_modulation();
+        assert_eq!(audio.mod_counter, -62);
+        audio.write(0x4085, 0x7F);
+        assert_eq!(audio.mod_counter, -1);
+    }
+}
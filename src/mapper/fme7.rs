@@ -0,0 +1,199 @@
+use crate::cartridge::{Mirroring, Rom};
+use crate::mapper::sunsoft5b::{self, Sunsoft5b};
+use crate::mapper::{bank_offset, ChrMemory, Mapper, PrgRam};
+
+// Sunsoft FME-7 and 5B, mapper 69.
+//
+// $8000-$9FFF selects one of 16 internal registers, $A000-$BFFF writes it:
+//
+//  $0-$7  1K CHR banks
+//  $8     PRG bank at $6000, bit 6 selects RAM and bit 7 enables it
+//  $9-$B  8K PRG banks at $8000, $A000 and $C000
+//  $C     mirroring
+//  $D     IRQ control, writing acknowledges the IRQ
+//  $E/$F  IRQ counter low/high byte
+//
+// The IRQ counter counts down every CPU cycle and fires when it wraps
+// from $0000 to $FFFF. $C000/$E000 are the 5B audio address/data ports.
+pub struct Fme7 {
+    prg_rom: Vec<u8>,
+    chr: ChrMemory,
+    prg_ram: PrgRam,
+
+    command: u8,
+    prg_6000: u8,
+    prg_banks: [u8; 3],
+    chr_banks: [u8; 8],
+    mirroring: Mirroring,
+
+    irq_enabled: bool,
+    irq_counter_enabled: bool,
+    irq_counter: u16,
+    irq_pending: bool,
+
+    audio: Sunsoft5b,
+}
+
+// three channels at full volume in the 5B are roughly as loud as two APU pulses
+pub(super) const AUDIO_VOLUME: f32 = 0.12;
+
+impl Fme7 {
+    pub fn new(mut rom: Rom) -> Self {
+        let chr = ChrMemory::new(&mut rom);
+        let prg_ram = PrgRam::new(&rom);
+        Fme7 {
+            prg_rom: rom.prg_rom,
+            chr,
+            prg_ram,
+            command: 0,
+            prg_6000: 0,
+            prg_banks: [0; 3],
+            chr_banks: [0; 8],
+            mirroring: rom.screen_mirroring,
+            irq_enabled: false,
+            irq_counter_enabled: false,
+            irq_counter: 0,
+            irq_pending: false,
+            audio: Sunsoft5b::new(),
+        }
+    }
+
+    fn chr_offset(&self, addr: u16) -> usize {
+        let bank = self.chr_banks[(addr / 0x400) as usize];
+        bank_offset(self.chr.len(), 0x400, bank as usize) + (addr & 0x3FF) as usize
+    }
+
+    fn write_parameter(&mut self, data: u8) {
+        match self.command {
+            reg @ 0x0..=0x7 => self.chr_banks[reg as usize] = data,
+            0x8 => {
+                self.prg_6000 = data;
+                self.prg_ram.enabled = data & 0x80 != 0;
+            }
+            reg @ 0x9..=0xB => self.prg_banks[(reg - 9) as usize] = data & 0x3F,
+            0xC => {
+                self.mirroring = match data & 0b11 {
+                    0 => Mirroring::VERTICAL,
+                    1 => Mirroring::HORIZONTAL,
+                    2 => Mirroring::ONE_SCREEN_LOWER,
+                    _ => Mirroring::ONE_SCREEN_UPPER,
+                };
+            }
+            0xD => {
+                self.irq_enabled = data & 0x01 != 0;
+                self.irq_counter_enabled = data & 0x80 != 0;
+                self.irq_pending = false;
+            }
+            0xE => self.irq_counter = (self.irq_counter & 0xFF00) | data as u16,
+            0xF => self.irq_counter = (self.irq_counter & 0x00FF) | (data as u16) << 8,
+            _ => {}
+        }
+    }
+}
+
+impl Mapper for Fme7 {
+    fn read_prg(&mut self, addr: u16) -> u8 {
+        let len = self.prg_rom.len();
+        let offset = match addr {
+            0x8000..=0xDFFF => {
+                let bank = self.prg_banks[((addr - 0x8000) / 0x2000) as usize];
+                bank_offset(len, 0x2000, bank as usize)
+            }
+            _ => len - 0x2000,
+        };
+        self.prg_rom[offset + (addr & 0x1FFF) as usize]
+    }
+
+    fn write_prg(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x8000..=0x9FFF => self.command = data & 0x0F,
+            0xA000..=0xBFFF => self.write_parameter(data),
+            0xC000..=0xDFFF => self.audio.write_address(data),
+            _ => self.audio.write_data(data),
+        }
+    }
+
+    fn read_chr(&mut self, addr: u16) -> u8 {
+        self.chr.read(self.chr_offset(addr))
+    }
+
+    fn write_chr(&mut self, addr: u16, data: u8) {
+        let offset = self.chr_offset(addr);
+        self.chr.write(offset, data);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn prg_ram(&mut self) -> &mut PrgRam {
+        &mut self.prg_ram
+    }
+
+    fn read_prg_ram(&mut self, addr: u16) -> Option<u8> {
+        if self.prg_6000 & 0x40 != 0 {
+            return self.prg_ram.read((addr - 0x6000) as usize);
+        }
+        let bank = bank_offset(self.prg_rom.len(), 0x2000, (self.prg_6000 & 0x3F) as usize);
+        Some(self.prg_rom[bank + (addr & 0x1FFF) as usize])
+    }
+
+    fn write_prg_ram(&mut self, addr: u16, data: u8) {
+        if self.prg_6000 & 0x40 != 0 {
+            self.prg_ram.write((addr - 0x6000) as usize, data);
+        }
+    }
+
+    fn cpu_clock(&mut self) {
+        if self.irq_counter_enabled {
+            self.irq_counter = self.irq_counter.wrapping_sub(1);
+            if self.irq_counter == 0xFFFF && self.irq_enabled {
+                self.irq_pending = true;
+            }
+        }
+        self.audio.clock();
+    }
+
+    fn irq_pending(&self) -> bool {
+        self.irq_pending
+    }
+
+    fn audio_output(&self) -> f32 {
+        self.audio.output() * AUDIO_VOLUME
+    }
+
+    fn expansion_channels(&self) -> Vec<&'static str> {
+        sunsoft5b::CHANNELS.to_vec()
+    }
+
+    fn set_expansion_gains(&mut self, gains: &[f32]) {
+        self.audio.set_gains(gains);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cartridge::test::test_rom;
+
+    #[test]
+    fn test_irq_fires_when_counter_wraps() {
+        let mut mapper = Fme7::new(test_rom());
+        mapper.write_prg(0x8000, 0xE);
+        mapper.write_prg(0xA000, 2);
+        mapper.write_prg(0x8000, 0xF);
+        mapper.write_prg(0xA000, 0);
+        mapper.write_prg(0x8000, 0xD);
+        mapper.write_prg(0xA000, 0x81);
+
+        mapper.cpu_clock();
+        mapper.cpu_clock();
+        assert!(!mapper.irq_pending());
+        mapper.cpu_clock();
+        assert!(mapper.irq_pending());
+
+        // writing the control register acknowledges
+        mapper.write_prg(0xA000, 0x81);
+        assert!(!mapper.irq_pending());
+    }
+}
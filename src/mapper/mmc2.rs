@@ -0,0 +1,162 @@
+use crate::cartridge::{Mirroring, Rom};
+use crate::mapper::{bank_offset, ChrMemory, Mapper, PrgRam};
+
+// Nintendo MMC2 (mapper 9, Punch-Out!!) and MMC4 (mapper 10, Fire Emblem).
+//
+// Each 4K half of the pattern table has two bank registers. Which one is used
+// is decided by a latch that flips whenever the PPU fetches tile $FD or $FE:
+//
+//  PPU read    | latch
+//  ------------+-----------------
+//  $0FD8       | 0 = $FD
+//  $0FE8       | 0 = $FE
+//  $1FD8-$1FDF | 1 = $FD
+//  $1FE8-$1FEF | 1 = $FE
+//
+// The MMC4 triggers latch 0 on the whole $0FD8-$0FDF/$0FE8-$0FEF range.
+// The new bank takes effect after the triggering read.
+//
+// PRG: MMC2 has an 8K bank at $8000 and the last three 8K banks fixed,
+// MMC4 has a 16K bank at $8000 and the last 16K fixed.
+pub struct Mmc2 {
+    prg_rom: Vec<u8>,
+    chr: ChrMemory,
+    prg_ram: PrgRam,
+    mmc4: bool,
+
+    prg_bank: u8,
+    chr_banks: [[u8; 2]; 2],
+    latches: [usize; 2],
+    mirroring: Mirroring,
+}
+
+const LATCH_FD: usize = 0;
+const LATCH_FE: usize = 1;
+
+impl Mmc2 {
+    pub fn new(mut rom: Rom) -> Self {
+        let chr = ChrMemory::new(&mut rom);
+        let prg_ram = PrgRam::new(&rom);
+        Mmc2 {
+            prg_rom: rom.prg_rom,
+            chr,
+            prg_ram,
+            mmc4: rom.mapper == 10,
+            prg_bank: 0,
+            chr_banks: [[0; 2]; 2],
+            latches: [LATCH_FE; 2],
+            mirroring: rom.screen_mirroring,
+        }
+    }
+
+    fn chr_offset(&self, addr: u16) -> usize {
+        let half = (addr / 0x1000) as usize;
+        let bank = self.chr_banks[half][self.latches[half]];
+        bank_offset(self.chr.len(), 0x1000, bank as usize) + (addr & 0x0FFF) as usize
+    }
+
+    fn update_latch(&mut self, addr: u16) {
+        match addr {
+            0x0FD8 => self.latches[0] = LATCH_FD,
+            0x0FE8 => self.latches[0] = LATCH_FE,
+            0x0FD9..=0x0FDF if self.mmc4 => self.latches[0] = LATCH_FD,
+            0x0FE9..=0x0FEF if self.mmc4 => self.latches[0] = LATCH_FE,
+            0x1FD8..=0x1FDF => self.latches[1] = LATCH_FD,
+            0x1FE8..=0x1FEF => self.latches[1] = LATCH_FE,
+            _ => {}
+        }
+    }
+}
+
+impl Mapper for Mmc2 {
+    fn read_prg(&mut self, addr: u16) -> u8 {
+        let len = self.prg_rom.len();
+        let offset = if self.mmc4 {
+            match addr {
+                0x8000..=0xBFFF => bank_offset(len, 0x4000, self.prg_bank as usize) + (addr & 0x3FFF) as usize,
+                _ => len - 0x4000 + (addr & 0x3FFF) as usize,
+            }
+        } else {
+            match addr {
+                0x8000..=0x9FFF => bank_offset(len, 0x2000, self.prg_bank as usize) + (addr & 0x1FFF) as usize,
+                _ => len - 0x6000 + (addr - 0xA000) as usize,
+            }
+        };
+        self.prg_rom[offset]
+    }
+
+    fn write_prg(&mut self, addr: u16, data: u8) {
+        match addr {
+            0xA000..=0xAFFF => self.prg_bank = data & 0x0F,
+            0xB000..=0xBFFF => self.chr_banks[0][LATCH_FD] = data & 0x1F,
+            0xC000..=0xCFFF => self.chr_banks[0][LATCH_FE] = data & 0x1F,
+            0xD000..=0xDFFF => self.chr_banks[1][LATCH_FD] = data & 0x1F,
+            0xE000..=0xEFFF => self.chr_banks[1][LATCH_FE] = data & 0x1F,
+            0xF000..=0xFFFF => {
+                self.mirroring = if data & 1 == 0 { Mirroring::VERTICAL } else { Mirroring::HORIZONTAL };
+            }
+            _ => {}
+        }
+    }
+
+    fn read_chr(&mut self, addr: u16) -> u8 {
+        let data = self.chr.read(self.chr_offset(addr));
+        self.update_latch(addr);
+        data
+    }
+
+    fn write_chr(&mut self, addr: u16, data: u8) {
+        let offset = self.chr_offset(addr);
+        self.chr.write(offset, data);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn prg_ram(&mut self) -> &mut PrgRam {
+        &mut self.prg_ram
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cartridge::test::test_rom;
+
+    fn mmc2() -> Mmc2 {
+        let mut rom = test_rom();
+        rom.mapper = 9;
+        rom.chr_rom = (0..0x8000).map(|i| (i / 0x1000) as u8).collect();
+        Mmc2::new(rom)
+    }
+
+    #[test]
+    fn test_latch_switches_after_tile_fetch() {
+        let mut mapper = mmc2();
+        mapper.write_prg(0xB000, 1);
+        mapper.write_prg(0xC000, 2);
+        assert_eq!(mapper.read_chr(0x0000), 2);
+
+        // the triggering read still sees the old bank
+        assert_eq!(mapper.read_chr(0x0FD8), 2);
+        assert_eq!(mapper.read_chr(0x0000), 1);
+        // MMC2 only reacts to the exact address in the lower half
+        mapper.read_chr(0x0FE9);
+        assert_eq!(mapper.read_chr(0x0000), 1);
+        mapper.read_chr(0x0FE8);
+        assert_eq!(mapper.read_chr(0x0000), 2);
+    }
+
+    #[test]
+    fn test_upper_latch_reacts_to_whole_tile_row() {
+        let mut mapper = mmc2();
+        mapper.write_prg(0xD000, 3);
+        mapper.write_prg(0xE000, 4);
+        assert_eq!(mapper.read_chr(0x1000), 4);
+        mapper.read_chr(0x1FDB);
+        assert_eq!(mapper.read_chr(0x1000), 3);
+        mapper.read_chr(0x1FEF);
+        assert_eq!(mapper.read_chr(0x1000), 4);
+    }
+}
@@ -0,0 +1,365 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::cartridge::{Mirroring, Rom, RomError};
+use crate::nsf::Nsf;
+
+mod nrom;
+mod cnrom;
+mod mmc2;
+mod vrc_irq;
+mod vrc2_4;
+mod vrc6;
+mod vrc7;
+mod opll;
+mod fme7;
+mod sunsoft5b;
+mod namco163;
+mod vs_unisystem;
+mod fds;
+mod fds_audio;
+mod nsf;
+
+// Everything on the cartridge side of the bus goes through a mapper:
+// the CPU sees PRG space ($8000-$FFFF), the PPU sees CHR space ($0000-$1FFF)
+// and asks the mapper how the nametables are mirrored.
+// Every pattern table fetch of the PPU, including the ones made while
+// rendering, is a read_chr call in the order the hardware makes them.
+pub trait Mapper {
+    fn read_prg(&mut self, addr: u16) -> u8;
+    fn write_prg(&mut self, addr: u16, data: u8);
+    fn read_chr(&mut self, addr: u16) -> u8;
+    fn write_chr(&mut self, addr: u16, data: u8);
+    fn mirroring(&self) -> Mirroring;
+
+    // the work RAM at $6000-$7FFF, None leaves the value on the open bus
+    fn prg_ram(&mut self) -> &mut PrgRam;
+
+    fn read_prg_ram(&mut self, addr: u16) -> Option<u8> {
+        self.prg_ram().read((addr - 0x6000) as usize)
+    }
+
+    fn write_prg_ram(&mut self, addr: u16, data: u8) {
+        self.prg_ram().write((addr - 0x6000) as usize, data);
+    }
+
+    // what the cartridge keeps between sessions, the battery backed RAM by default
+    fn has_save(&mut self) -> bool {
+        self.prg_ram().battery()
+    }
+
+    fn load_save(&mut self, data: &[u8]) {
+        self.prg_ram().load(data);
+    }
+
+    // Some when it changed since the last call
+    fn take_save(&mut self) -> Option<Vec<u8>> {
+        let ram = self.prg_ram();
+        if ram.take_dirty() {
+            Some(ram.data().to_vec())
+        } else {
+            None
+        }
+    }
+
+    // disk drives, side None ejects the disk
+    fn disk_sides(&self) -> usize {
+        0
+    }
+
+    fn insert_disk(&mut self, _side: Option<usize>) {}
+
+    // the expansion area $4020-$5FFF, None leaves the value on the open bus
+    fn read_expansion(&mut self, _addr: u16) -> Option<u8> {
+        None
+    }
+
+    fn write_expansion(&mut self, _addr: u16, _data: u8) {}
+
+    // the $4016 output latch (OUT0-OUT2), Vs. System boards bank with it
+    fn write_output_latch(&mut self, _data: u8) {}
+
+    // mappers that drive the nametables themselves return Some/true here,
+    // otherwise the PPU uses its own VRAM with the mirroring above
+    fn read_nametable(&mut self, _addr: u16) -> Option<u8> {
+        None
+    }
+
+    fn write_nametable(&mut self, _addr: u16, _data: u8) -> bool {
+        false
+    }
+
+    // called once per CPU cycle, drives IRQ counters and expansion audio
+    fn cpu_clock(&mut self) {}
+
+    fn irq_pending(&self) -> bool {
+        false
+    }
+
+    // current expansion audio level, on the same scale as the APU mixer output
+    fn audio_output(&self) -> f32 {
+        0.0
+    }
+
+    // names of the expansion sound channels, in the order set_expansion_gains takes them
+    fn expansion_channels(&self) -> Vec<&'static str> {
+        vec![]
+    }
+
+    fn set_expansion_gains(&mut self, _gains: &[f32]) {}
+}
+
+pub use fds::DISK_SIDE_SIZE;
+
+pub type MapperRef = Rc<RefCell<dyn Mapper>>;
+
+// keep in sync with new_mapper
+pub fn is_supported(mapper: u16) -> bool {
+    matches!(mapper, 0 | 3 | 9 | 10 | 19..=26 | 69 | 85 | 99)
+}
+
+pub fn board_name(mapper: u16) -> Option<&'static str> {
+    let name = match mapper {
+        0 => "NROM",
+        3 => "CNROM",
+        9 => "PxROM (MMC2)",
+        10 => "FxROM (MMC4)",
+        19 => "Namco 163",
+        20 => "Famicom Disk System",
+        21 => "VRC4a/VRC4c",
+        22 => "VRC2a",
+        23 => "VRC2b/VRC4e",
+        24 => "VRC6a",
+        25 => "VRC4b/VRC4d",
+        26 => "VRC6b",
+        69 => "Sunsoft FME-7",
+        85 => "VRC7",
+        99 => "Vs. UniSystem",
+        _ => return None,
+    };
+    Some(name)
+}
+
+// UNIF board names, without the NES-/HVC-/UNL- style prefix
+pub fn unif_board_mapper(board: &str) -> Option<u16> {
+    let mapper = match board {
+        "NROM" | "NROM-128" | "NROM-256" | "RROM" | "RROM-128" => 0,
+        "CNROM" => 3,
+        "PNROM" | "PEEOROM" => 9,
+        "FJROM" | "FKROM" => 10,
+        "BTR" | "JLROM" | "JSROM" => 69,
+        _ => return None,
+    };
+    Some(mapper)
+}
+
+// Rom fields are public, so the mapper number is checked here too
+pub fn new_mapper(rom: Rom) -> Result<MapperRef, RomError> {
+    let mapper: MapperRef = match rom.mapper {
+        0 => Rc::new(RefCell::new(nrom::Nrom::new(rom))),
+        3 => Rc::new(RefCell::new(cnrom::Cnrom::new(rom))),
+        9 | 10 => Rc::new(RefCell::new(mmc2::Mmc2::new(rom))),
+        19 => Rc::new(RefCell::new(namco163::Namco163::new(rom))),
+        20 => Rc::new(RefCell::new(fds::Fds::new(rom))),
+        21 | 22 | 23 | 25 => Rc::new(RefCell::new(vrc2_4::Vrc2_4::new(rom))),
+        24 | 26 => Rc::new(RefCell::new(vrc6::Vrc6::new(rom))),
+        69 => Rc::new(RefCell::new(fme7::Fme7::new(rom))),
+        85 => Rc::new(RefCell::new(vrc7::Vrc7::new(rom))),
+        99 => Rc::new(RefCell::new(vs_unisystem::VsUnisystem::new(rom))),
+        m => return Err(RomError::UnsupportedMapper(m)),
+    };
+    Ok(mapper)
+}
+
+// the memory and sound chips an NSF rip expects around its player code
+pub fn new_nsf_mapper(nsf: &Nsf) -> MapperRef {
+    Rc::new(RefCell::new(nsf::NsfMapper::new(nsf)))
+}
+
+// Pattern table memory of a board: the CHR ROM, or CHR RAM when the
+// header has no CHR ROM. Writes to CHR ROM are ignored.
+pub struct ChrMemory {
+    data: Vec<u8>,
+    writable: bool,
+}
+
+impl ChrMemory {
+    pub fn new(rom: &mut Rom) -> Self {
+        if rom.chr_rom.is_empty() {
+            let size = (rom.chr_ram_size + rom.chr_nvram_size).max(0x2000);
+            ChrMemory {
+                data: vec![0; size],
+                writable: true,
+            }
+        } else {
+            ChrMemory {
+                data: std::mem::take(&mut rom.chr_rom),
+                writable: false,
+            }
+        }
+    }
+
+    pub fn ram(size: usize) -> Self {
+        ChrMemory {
+            data: vec![0; size],
+            writable: true,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    pub fn read(&self, offset: usize) -> u8 {
+        self.data[offset % self.data.len()]
+    }
+
+    pub fn write(&mut self, offset: usize, data: u8) {
+        if self.writable {
+            let len = self.data.len();
+            self.data[offset % len] = data;
+        }
+    }
+}
+
+// offset of a bank inside a rom, bank numbers wrap around the rom size
+fn bank_offset(len: usize, bank_size: usize, bank: usize) -> usize {
+    let banks = (len / bank_size).max(1);
+    (bank % banks) * bank_size
+}
+
+// Work RAM of a board, PRG RAM and battery backed PRG NVRAM together.
+// Mappers with an enable or write protect bit drive the two flags.
+pub struct PrgRam {
+    data: Vec<u8>,
+    battery: bool,
+    dirty: bool,
+    pub enabled: bool,
+    pub write_protected: bool,
+}
+
+impl PrgRam {
+    pub fn new(rom: &Rom) -> Self {
+        let mut data = vec![0; rom.prg_ram_size + rom.prg_nvram_size];
+        if let Some(trainer) = &rom.trainer {
+            // the trainer lives at $7000, so there has to be a full 8K
+            if data.len() < 0x2000 {
+                data.resize(0x2000, 0);
+            }
+            data[0x1000..0x1000 + trainer.len()].copy_from_slice(trainer);
+        }
+        PrgRam {
+            data,
+            battery: rom.battery,
+            dirty: false,
+            enabled: true,
+            write_protected: false,
+        }
+    }
+
+    // plain RAM without a battery, for things that aren't cartridges
+    pub fn with_size(size: usize) -> Self {
+        PrgRam {
+            data: vec![0; size],
+            battery: false,
+            dirty: false,
+            enabled: true,
+            write_protected: false,
+        }
+    }
+
+    pub fn read(&self, offset: usize) -> Option<u8> {
+        if !self.enabled || self.data.is_empty() {
+            return None;
+        }
+        Some(self.data[offset % self.data.len()])
+    }
+
+    pub fn write(&mut self, offset: usize, data: u8) {
+        if !self.enabled || self.write_protected || self.data.is_empty() {
+            return;
+        }
+        let len = self.data.len();
+        self.data[offset % len] = data;
+        self.dirty = true;
+    }
+
+    pub fn battery(&self) -> bool {
+        self.battery
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    // restores a save, a save of a different size is truncated or padded
+    pub fn load(&mut self, save: &[u8]) {
+        let len = save.len().min(self.data.len());
+        self.data[..len].copy_from_slice(&save[..len]);
+    }
+
+    // true when the RAM changed since the last call
+    pub fn take_dirty(&mut self) -> bool {
+        std::mem::take(&mut self.dirty)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cartridge::test::test_rom;
+
+    #[test]
+    fn test_prg_ram_enable_and_protect() {
+        let mut ram = PrgRam::new(&test_rom());
+        ram.write(0x10, 0xAA);
+        assert_eq!(ram.read(0x10), Some(0xAA));
+        assert!(ram.take_dirty());
+        assert!(!ram.take_dirty());
+
+        ram.write_protected = true;
+        ram.write(0x10, 0x55);
+        assert_eq!(ram.read(0x10), Some(0xAA));
+        assert!(!ram.take_dirty());
+
+        ram.enabled = false;
+        assert_eq!(ram.read(0x10), None);
+    }
+
+    #[test]
+    fn test_chr_rom_ignores_writes() {
+        let mut rom = test_rom();
+        let mut chr = ChrMemory::new(&mut rom);
+        chr.write(0x10, 0xAA);
+        assert_eq!(chr.read(0x10), 2);
+    }
+
+    #[test]
+    fn test_chr_ram_without_chr_rom() {
+        let mut rom = test_rom();
+        rom.chr_rom = vec![];
+        rom.chr_ram_size = 0x2000;
+        let mut chr = ChrMemory::new(&mut rom);
+        assert_eq!(chr.len(), 0x2000);
+        chr.write(0x1FFF, 0xAA);
+        assert_eq!(chr.read(0x1FFF), 0xAA);
+    }
+
+    #[test]
+    fn test_nrom_8k_prg() {
+        let mut rom = test_rom();
+        rom.mapper = 0;
+        rom.prg_rom = (0..0x2000).map(|i| (i >> 8) as u8).collect();
+        let mut nrom = nrom::Nrom::new(rom);
+        assert_eq!(nrom.read_prg(0x8100), 0x01);
+        assert_eq!(nrom.read_prg(0xA100), 0x01);
+        assert_eq!(nrom.read_prg(0xFFFF), 0x1F);
+        // a stray write to rom is dropped
+        nrom.write_prg(0xFFFF, 0);
+        assert_eq!(nrom.read_prg(0xFFFF), 0x1F);
+    }
+}
@@ -0,0 +1,324 @@
+use crate::cartridge::{Mirroring, Rom};
+use crate::mapper::{bank_offset, ChrMemory, Mapper, PrgRam};
+
+// Namco 163, mapper 19.
+//
+//  $4800-$4FFF  sound RAM data port (address set through $F800)
+//  $5000-$57FF  IRQ counter low 8 bits
+//  $5800-$5FFF  IRQ counter high 7 bits, bit 7 enables counting
+//  $8000-$BFFF  eight 1K CHR banks, one register every $800
+//  $C000-$DFFF  four 1K nametable banks
+//  $E000-$F7FF  8K PRG banks at $8000, $A000 and $C000
+//  $F800-$FFFF  sound RAM address, bit 7 auto-increments, also PRG RAM
+//               write protection: writes need $4x in the upper nibble and
+//               bits 0-3 protect the 2K windows of $6000-$7FFF
+//
+// Bank values $E0-$FF pick one of the two 1K pages of the console's
+// nametable RAM instead of CHR ROM, for nametables always and for the
+// pattern tables unless disabled through bits 6/7 of $E800. Because of that
+// the mapper owns the nametable RAM.
+pub struct Namco163 {
+    prg_rom: Vec<u8>,
+    chr: ChrMemory,
+    prg_ram: PrgRam,
+    ciram: [u8; 2048],
+
+    prg_banks: [u8; 3],
+    chr_banks: [u8; 8],
+    nametable_banks: [u8; 4],
+    ciram_chr_disabled: [bool; 2],
+    prg_ram_protect: u8,
+
+    irq_counter: u16,
+    irq_enabled: bool,
+    irq_pending: bool,
+
+    audio: Namco163Audio,
+}
+
+impl Namco163 {
+    pub fn new(mut rom: Rom) -> Self {
+        let chr = ChrMemory::new(&mut rom);
+        let prg_ram = PrgRam::new(&rom);
+        Namco163 {
+            prg_rom: rom.prg_rom,
+            chr,
+            prg_ram,
+            ciram: [0; 2048],
+            prg_banks: [0; 3],
+            chr_banks: [0; 8],
+            nametable_banks: [0xE0, 0xE1, 0xE0, 0xE1],
+            ciram_chr_disabled: [false; 2],
+            prg_ram_protect: 0,
+            irq_counter: 0,
+            irq_enabled: false,
+            irq_pending: false,
+            audio: Namco163Audio::new(),
+        }
+    }
+
+    fn read_bank(&self, bank: u8, use_ciram: bool, addr: u16) -> u8 {
+        if use_ciram && bank >= 0xE0 {
+            self.ciram[(bank as usize & 1) * 0x400 + (addr & 0x3FF) as usize]
+        } else {
+            self.chr.read(bank_offset(self.chr.len(), 0x400, bank as usize) + (addr & 0x3FF) as usize)
+        }
+    }
+
+    fn write_bank(&mut self, bank: u8, use_ciram: bool, addr: u16, data: u8) {
+        if use_ciram && bank >= 0xE0 {
+            self.ciram[(bank as usize & 1) * 0x400 + (addr & 0x3FF) as usize] = data;
+        } else {
+            let offset = bank_offset(self.chr.len(), 0x400, bank as usize) + (addr & 0x3FF) as usize;
+            self.chr.write(offset, data);
+        }
+    }
+}
+
+impl Mapper for Namco163 {
+    fn read_prg(&mut self, addr: u16) -> u8 {
+        let len = self.prg_rom.len();
+        let offset = match addr {
+            0x8000..=0xDFFF => {
+                let bank = self.prg_banks[((addr - 0x8000) / 0x2000) as usize];
+                bank_offset(len, 0x2000, bank as usize)
+            }
+            _ => len - 0x2000,
+        };
+        self.prg_rom[offset + (addr & 0x1FFF) as usize]
+    }
+
+    fn write_prg(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x8000..=0xBFFF => self.chr_banks[((addr - 0x8000) / 0x800) as usize] = data,
+            0xC000..=0xDFFF => self.nametable_banks[((addr - 0xC000) / 0x800) as usize] = data,
+            0xE000..=0xE7FF => {
+                self.prg_banks[0] = data & 0x3F;
+                self.audio.disabled = data & 0x40 != 0;
+            }
+            0xE800..=0xEFFF => {
+                self.prg_banks[1] = data & 0x3F;
+                self.ciram_chr_disabled = [data & 0x40 != 0, data & 0x80 != 0];
+            }
+            0xF000..=0xF7FF => self.prg_banks[2] = data & 0x3F,
+            _ => {
+                self.audio.write_address(data);
+                self.prg_ram_protect = data;
+            }
+        }
+    }
+
+    fn read_expansion(&mut self, addr: u16) -> Option<u8> {
+        match addr {
+            0x4800..=0x4FFF => Some(self.audio.read_data()),
+            0x5000..=0x57FF => Some(self.irq_counter as u8),
+            0x5800..=0x5FFF => Some((self.irq_counter >> 8) as u8 | (self.irq_enabled as u8) << 7),
+            _ => None,
+        }
+    }
+
+    fn write_expansion(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x4800..=0x4FFF => self.audio.write_data(data),
+            0x5000..=0x57FF => {
+                self.irq_counter = (self.irq_counter & 0x7F00) | data as u16;
+                self.irq_pending = false;
+            }
+            0x5800..=0x5FFF => {
+                self.irq_counter = (self.irq_counter & 0x00FF) | ((data & 0x7F) as u16) << 8;
+                self.irq_enabled = data & 0x80 != 0;
+                self.irq_pending = false;
+            }
+            _ => {}
+        }
+    }
+
+    fn read_chr(&mut self, addr: u16) -> u8 {
+        let bank = self.chr_banks[(addr / 0x400) as usize];
+        let use_ciram = !self.ciram_chr_disabled[(addr / 0x1000) as usize];
+        self.read_bank(bank, use_ciram, addr)
+    }
+
+    fn write_chr(&mut self, addr: u16, data: u8) {
+        let bank = self.chr_banks[(addr / 0x400) as usize];
+        let use_ciram = !self.ciram_chr_disabled[(addr / 0x1000) as usize];
+        self.write_bank(bank, use_ciram, addr, data);
+    }
+
+    // only used for the PPU's own VRAM, which this mapper replaces
+    fn mirroring(&self) -> Mirroring {
+        Mirroring::VERTICAL
+    }
+
+    fn prg_ram(&mut self) -> &mut PrgRam {
+        &mut self.prg_ram
+    }
+
+    fn write_prg_ram(&mut self, addr: u16, data: u8) {
+        let window = (addr - 0x6000) / 0x800;
+        if self.prg_ram_protect & 0xF0 == 0x40 && self.prg_ram_protect & (1 << window) == 0 {
+            self.prg_ram.write((addr - 0x6000) as usize, data);
+        }
+    }
+
+    fn read_nametable(&mut self, addr: u16) -> Option<u8> {
+        let bank = self.nametable_banks[((addr - 0x2000) / 0x400 % 4) as usize];
+        Some(self.read_bank(bank, true, addr))
+    }
+
+    fn write_nametable(&mut self, addr: u16, data: u8) -> bool {
+        let bank = self.nametable_banks[((addr - 0x2000) / 0x400 % 4) as usize];
+        self.write_bank(bank, true, addr, data);
+        true
+    }
+
+    fn cpu_clock(&mut self) {
+        if self.irq_enabled && self.irq_counter < 0x7FFF {
+            self.irq_counter += 1;
+            if self.irq_counter == 0x7FFF {
+                self.irq_pending = true;
+            }
+        }
+        self.audio.clock();
+    }
+
+    fn irq_pending(&self) -> bool {
+        self.irq_pending
+    }
+
+    fn audio_output(&self) -> f32 {
+        self.audio.output()
+    }
+
+    fn expansion_channels(&self) -> Vec<&'static str> {
+        CHANNELS.to_vec()
+    }
+
+    fn set_expansion_gains(&mut self, gains: &[f32]) {
+        self.audio.set_gains(gains);
+    }
+}
+
+// Wavetable sound. 128 bytes of internal RAM hold both the 4 bit samples
+// and the channel registers, 8 bytes per channel from $40 (channel 0) to $78
+// (channel 7):
+//
+//  +0  frequency low        +4  frequency high (bits 0-1), 256 - length (bits 2-7)
+//  +1  phase low            +5  phase high
+//  +2  frequency mid        +6  wave address in nibbles
+//  +3  phase mid            +7  volume (bits 0-3)
+//
+// Bits 4-6 of $7F hold the number of enabled channels minus one, counted
+// down from channel 7. The chip updates one channel every 15 CPU cycles and
+// outputs them in turns, which is approximated by averaging.
+pub(super) struct Namco163Audio {
+    ram: [u8; 128],
+    address: u8,
+    auto_increment: bool,
+    disabled: bool,
+    divider: u8,
+    current: usize,
+    outputs: [f32; 8],
+    gains: [f32; 8],
+}
+
+pub(super) const CHANNELS: [&str; 8] = ["N163 1", "N163 2", "N163 3", "N163 4", "N163 5", "N163 6", "N163 7", "N163 8"];
+
+// a single channel at full volume is a lot louder than an APU pulse
+const AUDIO_VOLUME: f32 = 0.005;
+
+impl Namco163Audio {
+    pub(super) fn new() -> Self {
+        Namco163Audio {
+            ram: [0; 128],
+            address: 0,
+            auto_increment: false,
+            disabled: false,
+            divider: 0,
+            current: 7,
+            outputs: [0.0; 8],
+            gains: [1.0; 8],
+        }
+    }
+
+    // per channel volume, only changes what comes out
+    pub(super) fn set_gains(&mut self, gains: &[f32]) {
+        for (gain, value) in self.gains.iter_mut().zip(gains) {
+            *gain = *value;
+        }
+    }
+
+    pub(super) fn write_address(&mut self, data: u8) {
+        self.address = data & 0x7F;
+        self.auto_increment = data & 0x80 != 0;
+    }
+
+    fn step_address(&mut self) {
+        if self.auto_increment {
+            self.address = (self.address + 1) & 0x7F;
+        }
+    }
+
+    pub(super) fn read_data(&mut self) -> u8 {
+        let data = self.ram[self.address as usize];
+        self.step_address();
+        data
+    }
+
+    pub(super) fn write_data(&mut self, data: u8) {
+        self.ram[self.address as usize] = data;
+        self.step_address();
+    }
+
+    fn enabled_channels(&self) -> usize {
+        ((self.ram[0x7F] >> 4) & 0b111) as usize + 1
+    }
+
+    pub(super) fn clock(&mut self) {
+        if self.disabled {
+            return;
+        }
+        self.divider += 1;
+        if self.divider < 15 {
+            return;
+        }
+        self.divider = 0;
+
+        self.update_channel(self.current);
+        self.current = if self.current == 0 || self.current <= 8 - self.enabled_channels() {
+            7
+        } else {
+            self.current - 1
+        };
+    }
+
+    fn update_channel(&mut self, channel: usize) {
+        let base = 0x40 + channel * 8;
+        let reg = |i: usize| self.ram[base + i] as u32;
+
+        let frequency = reg(0) | reg(2) << 8 | (reg(4) & 0b11) << 16;
+        let length = 256 - (reg(4) & 0xFC);
+        let mut phase = reg(1) | reg(3) << 8 | reg(5) << 16;
+        let offset = reg(6);
+        let volume = reg(7) & 0x0F;
+
+        phase = (phase + frequency) % (length << 16);
+        self.ram[base + 1] = phase as u8;
+        self.ram[base + 3] = (phase >> 8) as u8;
+        self.ram[base + 5] = (phase >> 16) as u8;
+
+        let nibble = ((phase >> 16) + offset) & 0xFF;
+        let byte = self.ram[(nibble / 2) as usize];
+        let sample = if nibble & 1 == 0 { byte & 0x0F } else { byte >> 4 };
+        self.outputs[channel] = (sample as f32 - 8.0) * volume as f32;
+    }
+
+    pub(super) fn output(&self) -> f32 {
+        if self.disabled {
+            return 0.0;
+        }
+        let enabled = self.enabled_channels();
+        let sum: f32 = self.outputs.iter().zip(self.gains).skip(8 - enabled).map(|(output, gain)| output * gain).sum();
+        sum / enabled as f32 * AUDIO_VOLUME
+    }
+}
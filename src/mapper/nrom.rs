@@ -0,0 +1,48 @@
+use crate::cartridge::{Mirroring, Rom};
+use crate::mapper::{ChrMemory, Mapper, PrgRam};
+
+// Mapper 0, no bank switching. 8K and 16K roms are mirrored up to $FFFF.
+pub struct Nrom {
+    prg_rom: Vec<u8>,
+    chr: ChrMemory,
+    prg_ram: PrgRam,
+    mirroring: Mirroring,
+}
+
+impl Nrom {
+    pub fn new(mut rom: Rom) -> Self {
+        let chr = ChrMemory::new(&mut rom);
+        let prg_ram = PrgRam::new(&rom);
+        Nrom {
+            prg_rom: rom.prg_rom,
+            chr,
+            prg_ram,
+            mirroring: rom.screen_mirroring,
+        }
+    }
+}
+
+impl Mapper for Nrom {
+    fn read_prg(&mut self, addr: u16) -> u8 {
+        self.prg_rom[(addr - 0x8000) as usize % self.prg_rom.len()]
+    }
+
+    // no registers, the write goes nowhere
+    fn write_prg(&mut self, _addr: u16, _data: u8) {}
+
+    fn read_chr(&mut self, addr: u16) -> u8 {
+        self.chr.read(addr as usize)
+    }
+
+    fn write_chr(&mut self, addr: u16, data: u8) {
+        self.chr.write(addr as usize, data);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn prg_ram(&mut self) -> &mut PrgRam {
+        &mut self.prg_ram
+    }
+}
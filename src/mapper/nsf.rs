@@ -0,0 +1,301 @@
+use crate::cartridge::Mirroring;
+use crate::mapper::fds_audio::{self, FdsAudio};
+use crate::mapper::fme7;
+use crate::mapper::namco163::{self, Namco163Audio};
+use crate::mapper::opll::Opll;
+use crate::mapper::sunsoft5b::{self, Sunsoft5b};
+use crate::mapper::vrc6::{self, Vrc6Audio};
+use crate::mapper::vrc7;
+use crate::mapper::{bank_offset, ChrMemory, Mapper, PrgRam};
+use crate::nsf::{ExpansionChips, Nsf};
+
+// The hardware an NSF player provides around the rip.
+//
+//  $5FF8-$5FFF  4K PRG banks at $8000-$FFFF
+//  $5FF6/$5FF7  with FDS, 4K banks at $6000/$7000
+//  $6000-$7FFF  8K RAM
+//
+// With the FDS chip everything from $6000 up is RAM and bank writes copy the
+// bank into it. The program is laid out in banks even when the file isn't
+// bankswitched, the player then selects them in order.
+//
+// The sound chips sit at their usual registers:
+//
+//  VRC6  $9000-$9003, $A000-$A002, $B000-$B002
+//  VRC7  $9010 address, $9030 data
+//  FDS   $4040-$4092
+//  MMC5  $5205/$5206 multiplier, $5C00-$5FF5 RAM (the sound isn't emulated)
+//  N163  $4800 data, $F800 address
+//  5B    $C000-$DFFF address, $E000-$FFFF data
+
+const BANK_SIZE: usize = 0x1000;
+
+pub struct NsfMapper {
+    prg_rom: Vec<u8>,
+    banks: [u8; 8],
+    fds: bool,
+    prg_ram: PrgRam,
+    chr: ChrMemory,
+
+    vrc6: Option<Vrc6Audio>,
+    vrc7: Option<Opll>,
+    fds_audio: Option<FdsAudio>,
+    n163: Option<Namco163Audio>,
+    s5b: Option<Sunsoft5b>,
+    multiplier: [u8; 2],
+    exram: [u8; 0x400],
+}
+
+impl NsfMapper {
+    pub fn new(nsf: &Nsf) -> Self {
+        let fds = nsf.chips.contains(ExpansionChips::FDS);
+        let mut prg_rom = match nsf.banks {
+            Some(_) => vec![0; nsf.load_addr as usize & 0x0FFF],
+            // FDS rips can load below $8000
+            None if fds => vec![0; (nsf.load_addr as usize).saturating_sub(0x6000)],
+            None => vec![0; (nsf.load_addr as usize).saturating_sub(0x8000)],
+        };
+        prg_rom.extend_from_slice(&nsf.data);
+        prg_rom.resize(prg_rom.len().div_ceil(BANK_SIZE).max(1) * BANK_SIZE, 0);
+
+        let chips = nsf.chips;
+        NsfMapper {
+            prg_rom,
+            banks: [0, 1, 2, 3, 4, 5, 6, 7],
+            fds,
+            prg_ram: PrgRam::with_size(if fds { 0xA000 } else { 0x2000 }),
+            chr: ChrMemory::ram(0x2000),
+            vrc6: chips.contains(ExpansionChips::VRC6).then(Vrc6Audio::new),
+            vrc7: chips.contains(ExpansionChips::VRC7).then(Opll::new),
+            fds_audio: fds.then(FdsAudio::new),
+            n163: chips.contains(ExpansionChips::N163).then(Namco163Audio::new),
+            s5b: chips.contains(ExpansionChips::S5B).then(Sunsoft5b::new),
+            multiplier: [0; 2],
+            exram: [0; 0x400],
+        }
+    }
+
+    fn write_bank(&mut self, slot: usize, bank: u8) {
+        if self.fds {
+            // slot 0 is $6000
+            let offset = bank_offset(self.prg_rom.len(), BANK_SIZE, bank as usize);
+            for i in 0..BANK_SIZE {
+                self.prg_ram.write(slot * BANK_SIZE + i, self.prg_rom[offset + i]);
+            }
+        } else if slot >= 2 {
+            self.banks[slot - 2] = bank;
+        }
+    }
+}
+
+impl Mapper for NsfMapper {
+    fn read_prg(&mut self, addr: u16) -> u8 {
+        if self.fds {
+            return self.prg_ram.read((addr - 0x6000) as usize).unwrap_or(0);
+        }
+        let slot = ((addr - 0x8000) as usize) / BANK_SIZE;
+        let offset = bank_offset(self.prg_rom.len(), BANK_SIZE, self.banks[slot] as usize);
+        self.prg_rom[offset + (addr as usize & 0x0FFF)]
+    }
+
+    fn write_prg(&mut self, addr: u16, data: u8) {
+        if self.fds {
+            self.prg_ram.write((addr - 0x6000) as usize, data);
+        }
+        if let Some(vrc6) = &mut self.vrc6 {
+            if let 0x9000..=0x9003 | 0xA000..=0xA002 | 0xB000..=0xB002 = addr {
+                vrc6.write(addr, data);
+            }
+        }
+        if let Some(opll) = &mut self.vrc7 {
+            match addr {
+                0x9010 => opll.write_address(data),
+                0x9030 => opll.write_data(data),
+                _ => {}
+            }
+        }
+        if let Some(s5b) = &mut self.s5b {
+            match addr {
+                0xC000..=0xDFFF => s5b.write_address(data),
+                0xE000..=0xFFFF => s5b.write_data(data),
+                _ => {}
+            }
+        }
+        if let Some(n163) = &mut self.n163 {
+            if addr >= 0xF800 {
+                n163.write_address(data);
+            }
+        }
+    }
+
+    fn read_chr(&mut self, addr: u16) -> u8 {
+        self.chr.read(addr as usize)
+    }
+
+    fn write_chr(&mut self, addr: u16, data: u8) {
+        self.chr.write(addr as usize, data);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        Mirroring::HORIZONTAL
+    }
+
+    fn prg_ram(&mut self) -> &mut PrgRam {
+        &mut self.prg_ram
+    }
+
+    fn read_expansion(&mut self, addr: u16) -> Option<u8> {
+        match addr {
+            0x4040..=0x4092 => self.fds_audio.as_ref().and_then(|fds| fds.read(addr)),
+            0x4800 => self.n163.as_mut().map(|n163| n163.read_data()),
+            0x5205 => Some((self.multiplier[0] as u16 * self.multiplier[1] as u16) as u8),
+            0x5206 => Some(((self.multiplier[0] as u16 * self.multiplier[1] as u16) >> 8) as u8),
+            0x5C00..=0x5FF5 => Some(self.exram[(addr - 0x5C00) as usize]),
+            _ => None,
+        }
+    }
+
+    fn write_expansion(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x4040..=0x408A => {
+                if let Some(fds) = &mut self.fds_audio {
+                    fds.write(addr, data);
+                }
+            }
+            0x4800 => {
+                if let Some(n163) = &mut self.n163 {
+                    n163.write_data(data);
+                }
+            }
+            0x5205 => self.multiplier[0] = data,
+            0x5206 => self.multiplier[1] = data,
+            0x5C00..=0x5FF5 => self.exram[(addr - 0x5C00) as usize] = data,
+            0x5FF6..=0x5FFF => self.write_bank((addr - 0x5FF6) as usize, data),
+            _ => {}
+        }
+    }
+
+    fn cpu_clock(&mut self) {
+        if let Some(vrc6) = &mut self.vrc6 {
+            vrc6.clock();
+        }
+        if let Some(opll) = &mut self.vrc7 {
+            opll.clock();
+        }
+        if let Some(fds) = &mut self.fds_audio {
+            fds.clock();
+        }
+        if let Some(n163) = &mut self.n163 {
+            n163.clock();
+        }
+        if let Some(s5b) = &mut self.s5b {
+            s5b.clock();
+        }
+    }
+
+    fn audio_output(&self) -> f32 {
+        let mut output = 0.0;
+        output += self.vrc6.as_ref().map_or(0.0, |vrc6| vrc6.output());
+        output += self.vrc7.as_ref().map_or(0.0, |opll| opll.output() * vrc7::OPLL_VOLUME);
+        output += self.fds_audio.as_ref().map_or(0.0, |fds| fds.output());
+        output += self.n163.as_ref().map_or(0.0, |n163| n163.output());
+        output += self.s5b.as_ref().map_or(0.0, |s5b| s5b.output() * fme7::AUDIO_VOLUME);
+        output
+    }
+
+    // the channels of every chip the rip uses, in the order of audio_output
+    fn expansion_channels(&self) -> Vec<&'static str> {
+        let mut channels = vec![];
+        if self.vrc6.is_some() {
+            channels.extend(vrc6::CHANNELS);
+        }
+        if self.vrc7.is_some() {
+            channels.extend(vrc7::CHANNELS);
+        }
+        if self.fds_audio.is_some() {
+            channels.extend(fds_audio::CHANNELS);
+        }
+        if self.n163.is_some() {
+            channels.extend(namco163::CHANNELS);
+        }
+        if self.s5b.is_some() {
+            channels.extend(sunsoft5b::CHANNELS);
+        }
+        channels
+    }
+
+    fn set_expansion_gains(&mut self, mut gains: &[f32]) {
+        if let Some(vrc6) = &mut self.vrc6 {
+            vrc6.set_gains(gains);
+            gains = gains.get(vrc6::CHANNELS.len()..).unwrap_or(&[]);
+        }
+        if let Some(opll) = &mut self.vrc7 {
+            opll.set_gains(gains);
+            gains = gains.get(vrc7::CHANNELS.len()..).unwrap_or(&[]);
+        }
+        if let Some(fds) = &mut self.fds_audio {
+            fds.set_gains(gains);
+            gains = gains.get(fds_audio::CHANNELS.len()..).unwrap_or(&[]);
+        }
+        if let Some(n163) = &mut self.n163 {
+            n163.set_gains(gains);
+            gains = gains.get(namco163::CHANNELS.len()..).unwrap_or(&[]);
+        }
+        if let Some(s5b) = &mut self.s5b {
+            s5b.set_gains(gains);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn nsf(banks: Option<[u8; 8]>, chips: ExpansionChips) -> Nsf {
+        let mut nsf = Nsf::from_bytes(&crate::nsf::test::test_nsf()).unwrap();
+        nsf.load_addr = 0x8100;
+        nsf.banks = banks;
+        nsf.chips = chips;
+        nsf.data = (0..0x3000).map(|i| (i / BANK_SIZE) as u8 + 1).collect();
+        nsf
+    }
+
+    #[test]
+    fn test_unbanked_load() {
+        let mut mapper = NsfMapper::new(&nsf(None, ExpansionChips::empty()));
+        assert_eq!(mapper.read_prg(0x80FF), 0);
+        assert_eq!(mapper.read_prg(0x8100), 1);
+        assert_eq!(mapper.read_prg(0xB0FF), 3);
+    }
+
+    #[test]
+    fn test_bankswitching() {
+        let mut mapper = NsfMapper::new(&nsf(Some([0, 1, 2, 3, 0, 0, 0, 0]), ExpansionChips::empty()));
+        // the padding is the low 12 bits of the load address
+        assert_eq!(mapper.read_prg(0x8100), 1);
+        mapper.write_expansion(0x5FF8, 2);
+        assert_eq!(mapper.read_prg(0x8000), 2);
+        assert_eq!(mapper.read_prg(0x80FF), 2);
+        assert_eq!(mapper.read_prg(0x8100), 3);
+    }
+
+    #[test]
+    fn test_fds_banks_copy_to_ram() {
+        let mut mapper = NsfMapper::new(&nsf(Some([0, 1, 2, 3, 0, 0, 0, 0]), ExpansionChips::FDS));
+        mapper.write_expansion(0x5FF6, 2);
+        assert_eq!(mapper.read_prg(0x6100), 3);
+        mapper.write_prg(0x6100, 0x55);
+        assert_eq!(mapper.read_prg(0x6100), 0x55);
+        mapper.write_expansion(0x5FFF, 1);
+        assert_eq!(mapper.read_prg(0xF100), 2);
+    }
+
+    #[test]
+    fn test_mmc5_multiplier() {
+        let mut mapper = NsfMapper::new(&nsf(None, ExpansionChips::MMC5));
+        mapper.write_expansion(0x5205, 200);
+        mapper.write_expansion(0x5206, 100);
+        assert_eq!(mapper.read_expansion(0x5205), Some((20000 & 0xFF) as u8));
+        assert_eq!(mapper.read_expansion(0x5206), Some((20000 >> 8) as u8));
+    }
+}
@@ -0,0 +1,368 @@
+use std::f32::consts::PI;
+
+// Approximation of the YM2413 (OPLL) derived FM core inside the VRC7.
+//
+// The chip has six two-operator channels. Each channel plays one of 15
+// built-in instruments or the user defined instrument in registers $00-$07.
+// The real chip works with log-sin and exp lookup tables, here the operators
+// are computed in floating point with the envelopes tracked in decibels.
+// It produces one sample every 36 CPU cycles (49716 Hz on NTSC).
+
+const CYCLES_PER_SAMPLE: u8 = 36;
+const SAMPLE_RATE: f32 = 49716.0;
+
+// built-in instruments 1-15
+const INSTRUMENTS: [[u8; 8]; 15] = [
+    [0x03, 0x21, 0x05, 0x06, 0xE8, 0x81, 0x42, 0x27], // buzzy bell
+    [0x13, 0x41, 0x14, 0x0D, 0xD8, 0xF6, 0x23, 0x12], // guitar
+    [0x11, 0x11, 0x08, 0x08, 0xFA, 0xB2, 0x20, 0x12], // wurly
+    [0x31, 0x61, 0x0C, 0x07, 0xA8, 0x64, 0x61, 0x27], // flute
+    [0x32, 0x21, 0x1E, 0x06, 0xE1, 0x76, 0x01, 0x28], // clarinet
+    [0x02, 0x01, 0x06, 0x00, 0xA3, 0xE2, 0xF4, 0xF4], // synth
+    [0x21, 0x61, 0x1D, 0x07, 0x82, 0x81, 0x11, 0x07], // trumpet
+    [0x23, 0x21, 0x22, 0x17, 0xA2, 0x72, 0x01, 0x17], // organ
+    [0x35, 0x11, 0x25, 0x00, 0x40, 0x73, 0x72, 0x01], // bells
+    [0xB5, 0x01, 0x0F, 0x0F, 0xA8, 0xA5, 0x51, 0x02], // vibes
+    [0x17, 0xC1, 0x24, 0x07, 0xF8, 0xF8, 0x22, 0x12], // vibraphone
+    [0x71, 0x23, 0x11, 0x06, 0x65, 0x74, 0x18, 0x16], // tutti
+    [0x01, 0x02, 0xD3, 0x05, 0xC9, 0x95, 0x03, 0x02], // fretless
+    [0x61, 0x63, 0x0C, 0x00, 0x94, 0xC0, 0x33, 0xF6], // synth bass
+    [0x21, 0x72, 0x0D, 0x00, 0xC1, 0xD5, 0x56, 0x06], // sweep
+];
+
+const MULTIPLIER: [f32; 16] = [
+    0.5, 1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0, 9.0, 10.0, 10.0, 12.0, 12.0, 15.0, 15.0,
+];
+
+// key scale attenuation at 3 dB/octave for block 7, indexed by the top 4 bits of fnum
+const KSL: [f32; 16] = [
+    0.0, 9.0, 12.0, 13.875, 15.0, 16.125, 16.875, 17.625, 18.0, 18.75, 19.125, 19.5, 19.875, 20.25,
+    20.625, 21.0,
+];
+
+// attenuation at which an operator is considered silent
+const MAX_ATTENUATION: f32 = 48.0;
+
+#[derive(Clone, Copy, PartialEq)]
+enum EnvelopeState {
+    Attack,
+    Decay,
+    Sustain,
+    Release,
+    Off,
+}
+
+// one half of an instrument, modulator and carrier are described the same way
+struct OperatorPatch {
+    tremolo: bool,
+    vibrato: bool,
+    sustained: bool,
+    key_scale_rate: bool,
+    multiplier: f32,
+    key_scale_level: u8,
+    rectified: bool,
+    attack: u8,
+    decay: u8,
+    sustain_level: u8,
+    release: u8,
+}
+
+impl OperatorPatch {
+    fn new(patch: &[u8; 8], carrier: bool) -> Self {
+        let i = carrier as usize;
+        OperatorPatch {
+            tremolo: patch[i] & 0x80 != 0,
+            vibrato: patch[i] & 0x40 != 0,
+            sustained: patch[i] & 0x20 != 0,
+            key_scale_rate: patch[i] & 0x10 != 0,
+            multiplier: MULTIPLIER[(patch[i] & 0x0F) as usize],
+            key_scale_level: patch[2 + i] >> 6,
+            rectified: patch[3] & (0x08 << i) != 0,
+            attack: patch[4 + i] >> 4,
+            decay: patch[4 + i] & 0x0F,
+            sustain_level: patch[6 + i] >> 4,
+            release: patch[6 + i] & 0x0F,
+        }
+    }
+}
+
+struct Operator {
+    phase: f32,
+    state: EnvelopeState,
+    envelope: f32,
+    output: [f32; 2],
+}
+
+impl Operator {
+    fn new() -> Self {
+        Operator {
+            phase: 0.0,
+            state: EnvelopeState::Off,
+            envelope: MAX_ATTENUATION,
+            output: [0.0; 2],
+        }
+    }
+
+    fn key_on(&mut self) {
+        self.phase = 0.0;
+        self.state = EnvelopeState::Attack;
+    }
+
+    fn key_off(&mut self) {
+        if self.state != EnvelopeState::Off {
+            self.state = EnvelopeState::Release;
+        }
+    }
+
+    // attenuation change per sample for a 4 bit rate, faster rates halve the time
+    fn rate_step(rate: u8, key_scale: u8) -> f32 {
+        if rate == 0 {
+            return 0.0;
+        }
+        let effective = (rate as u32 * 4 + key_scale as u32).min(63) as f32;
+        let seconds = 0.0025 * 2f32.powf((60.0 - effective) / 4.0);
+        MAX_ATTENUATION / (seconds * SAMPLE_RATE)
+    }
+
+    fn update_envelope(&mut self, patch: &OperatorPatch, key_scale: u8, channel_sustain: bool) {
+        match self.state {
+            EnvelopeState::Attack => {
+                if patch.attack == 15 {
+                    self.envelope = 0.0;
+                } else {
+                    // attack is exponential, it speeds up the closer it gets to full volume
+                    self.envelope -= Self::rate_step(patch.attack, key_scale) * 8.0 * (self.envelope / MAX_ATTENUATION + 0.05);
+                }
+                if self.envelope <= 0.0 {
+                    self.envelope = 0.0;
+                    self.state = EnvelopeState::Decay;
+                }
+            }
+            EnvelopeState::Decay => {
+                self.envelope += Self::rate_step(patch.decay, key_scale);
+                let sustain_level = patch.sustain_level as f32 * 3.0;
+                if self.envelope >= sustain_level {
+                    self.envelope = sustain_level;
+                    self.state = EnvelopeState::Sustain;
+                }
+            }
+            EnvelopeState::Sustain => {
+                // percussive tones keep decaying with the release rate while the key is held
+                if !patch.sustained {
+                    self.envelope += Self::rate_step(patch.release, key_scale);
+                }
+            }
+            EnvelopeState::Release => {
+                let rate = if channel_sustain {
+                    5
+                } else if patch.sustained {
+                    patch.release
+                } else {
+                    7
+                };
+                self.envelope += Self::rate_step(rate, key_scale);
+            }
+            EnvelopeState::Off => {}
+        }
+        if self.envelope >= MAX_ATTENUATION {
+            self.envelope = MAX_ATTENUATION;
+            if self.state != EnvelopeState::Attack {
+                self.state = EnvelopeState::Off;
+            }
+        }
+    }
+
+    fn calc(&mut self, phase_step: f32, modulation: f32, attenuation: f32, rectified: bool) -> f32 {
+        self.phase = (self.phase + phase_step).fract();
+        let mut wave = (2.0 * PI * self.phase + modulation).sin();
+        if rectified && wave < 0.0 {
+            wave = 0.0;
+        }
+        let total = self.envelope + attenuation;
+        let out = if total >= MAX_ATTENUATION {
+            0.0
+        } else {
+            wave * 10f32.powf(-total / 20.0)
+        };
+        self.output = [out, self.output[0]];
+        out
+    }
+}
+
+struct Channel {
+    fnum: u16,
+    block: u8,
+    sustain: bool,
+    key_on: bool,
+    instrument: u8,
+    volume: u8,
+    modulator: Operator,
+    carrier: Operator,
+}
+
+impl Channel {
+    fn new() -> Self {
+        Channel {
+            fnum: 0,
+            block: 0,
+            sustain: false,
+            key_on: false,
+            instrument: 0,
+            volume: 0,
+            modulator: Operator::new(),
+            carrier: Operator::new(),
+        }
+    }
+
+    fn set_key(&mut self, on: bool) {
+        if on && !self.key_on {
+            self.modulator.key_on();
+            self.carrier.key_on();
+        } else if !on && self.key_on {
+            self.modulator.key_off();
+            self.carrier.key_off();
+        }
+        self.key_on = on;
+    }
+
+    fn key_scale_level(&self, ksl: u8) -> f32 {
+        if ksl == 0 {
+            return 0.0;
+        }
+        let base = (KSL[(self.fnum >> 5) as usize] - 3.0 * (7 - self.block) as f32).max(0.0);
+        base * [0.0, 0.5, 1.0, 2.0][ksl as usize]
+    }
+
+    fn sample(&mut self, patch: &[u8; 8], tremolo: f32, vibrato: f32) -> f32 {
+        let modulator = OperatorPatch::new(patch, false);
+        let carrier = OperatorPatch::new(patch, true);
+        let total_level = (patch[2] & 0x3F) as f32 * 0.75;
+        let feedback = patch[3] & 0b111;
+
+        let key_scale = (self.block << 1) | (self.fnum >> 8) as u8;
+        let base_step = self.fnum as f32 * 2f32.powi(self.block as i32) / 524288.0;
+
+        let mod_ks = if modulator.key_scale_rate { key_scale } else { key_scale >> 2 };
+        self.modulator.update_envelope(&modulator, mod_ks, self.sustain);
+        let car_ks = if carrier.key_scale_rate { key_scale } else { key_scale >> 2 };
+        self.carrier.update_envelope(&carrier, car_ks, self.sustain);
+
+        let fb = if feedback == 0 {
+            0.0
+        } else {
+            (self.modulator.output[0] + self.modulator.output[1]) / 2.0 * PI * 2f32.powi(feedback as i32 - 7)
+        };
+        let mod_step = base_step * modulator.multiplier * if modulator.vibrato { vibrato } else { 1.0 };
+        let mod_att = total_level
+            + self.key_scale_level(modulator.key_scale_level)
+            + if modulator.tremolo { tremolo } else { 0.0 };
+        let m = self.modulator.calc(mod_step, fb, mod_att, modulator.rectified);
+
+        let car_step = base_step * carrier.multiplier * if carrier.vibrato { vibrato } else { 1.0 };
+        let car_att = self.volume as f32 * 3.0
+            + self.key_scale_level(carrier.key_scale_level)
+            + if carrier.tremolo { tremolo } else { 0.0 };
+        self.carrier.calc(car_step, m * 2.0 * PI, car_att, carrier.rectified)
+    }
+}
+
+pub struct Opll {
+    address: u8,
+    custom: [u8; 8],
+    channels: [Channel; 6],
+    divider: u8,
+    tremolo_phase: f32,
+    vibrato_phase: f32,
+    output: f32,
+    gains: [f32; 6],
+}
+
+impl Opll {
+    pub fn new() -> Self {
+        Opll {
+            address: 0,
+            custom: [0; 8],
+            channels: [Channel::new(), Channel::new(), Channel::new(), Channel::new(), Channel::new(), Channel::new()],
+            divider: 0,
+            tremolo_phase: 0.0,
+            vibrato_phase: 0.0,
+            output: 0.0,
+            gains: [1.0; 6],
+        }
+    }
+
+    // per channel volume, only changes what comes out
+    pub fn set_gains(&mut self, gains: &[f32]) {
+        for (gain, value) in self.gains.iter_mut().zip(gains) {
+            *gain = *value;
+        }
+    }
+
+    pub fn write_address(&mut self, data: u8) {
+        self.address = data;
+    }
+
+    pub fn write_data(&mut self, data: u8) {
+        let reg = self.address;
+        match reg {
+            0x00..=0x07 => self.custom[reg as usize] = data,
+            0x10..=0x15 => {
+                let ch = &mut self.channels[(reg & 0x0F) as usize];
+                ch.fnum = (ch.fnum & 0x100) | data as u16;
+            }
+            //  ..ST BBBH
+            //    || ||||
+            //    || |||+- fnum bit 8
+            //    || +++-- block (octave)
+            //    |+------ key on
+            //    +------- sustain
+            0x20..=0x25 => {
+                let ch = &mut self.channels[(reg & 0x0F) as usize];
+                ch.fnum = (ch.fnum & 0xFF) | ((data & 1) as u16) << 8;
+                ch.block = (data >> 1) & 0b111;
+                ch.sustain = data & 0x20 != 0;
+                ch.set_key(data & 0x10 != 0);
+            }
+            // IIII VVVV instrument and volume
+            0x30..=0x35 => {
+                let ch = &mut self.channels[(reg & 0x0F) as usize];
+                ch.instrument = data >> 4;
+                ch.volume = data & 0x0F;
+            }
+            _ => {}
+        }
+    }
+
+    pub fn reset(&mut self) {
+        *self = Opll::new();
+    }
+
+    pub fn clock(&mut self) {
+        self.divider += 1;
+        if self.divider < CYCLES_PER_SAMPLE {
+            return;
+        }
+        self.divider = 0;
+
+        // tremolo is 3.7 Hz with 4.8 dB depth, vibrato 6.4 Hz with about 14 cents
+        self.tremolo_phase = (self.tremolo_phase + 3.7 / SAMPLE_RATE).fract();
+        self.vibrato_phase = (self.vibrato_phase + 6.4 / SAMPLE_RATE).fract();
+        let tremolo = 2.4 * (1.0 - (2.0 * PI * self.tremolo_phase).cos());
+        let vibrato = 2f32.powf((2.0 * PI * self.vibrato_phase).sin() * 14.0 / 1200.0);
+
+        let mut sum = 0.0;
+        for (ch, gain) in self.channels.iter_mut().zip(self.gains) {
+            let patch = if ch.instrument == 0 {
+                &self.custom
+            } else {
+                &INSTRUMENTS[ch.instrument as usize - 1]
+            };
+            sum += ch.sample(patch, tremolo, vibrato) * gain;
+        }
+        self.output = sum;
+    }
+
+    pub fn output(&self) -> f32 {
+        self.output
+    }
+}
@@ -0,0 +1,202 @@
+// Sunsoft 5B expansion audio, a YM2149F (AY-3-8910 compatible) inside the FME-7.
+//
+// Three square wave channels that can each be mixed with a shared noise
+// generator and a shared envelope. Tone and noise advance every 16 CPU
+// cycles, the envelope every 8, so a tone with period P plays at
+// CPU / (32 * P) Hz.
+
+// volume of the 32 DAC levels, 1.5 dB per step
+fn level(value: u8) -> f32 {
+    if value == 0 {
+        0.0
+    } else {
+        10f32.powf((value as f32 - 31.0) * 1.5 / 20.0)
+    }
+}
+
+struct Tone {
+    period: u16,
+    timer: u16,
+    high: bool,
+}
+
+impl Tone {
+    fn clock(&mut self) {
+        self.timer += 1;
+        if self.timer >= self.period.max(1) {
+            self.timer = 0;
+            self.high = !self.high;
+        }
+    }
+}
+
+pub struct Sunsoft5b {
+    address: u8,
+    tones: [Tone; 3],
+    volumes: [u8; 3],
+    mixer: u8,
+
+    noise_period: u8,
+    noise_timer: u8,
+    noise_lfsr: u32,
+
+    envelope_period: u16,
+    envelope_timer: u16,
+    envelope_shape: u8,
+    envelope_step: u8,
+    envelope_holding: bool,
+    envelope_inverted: bool,
+
+    divider: u8,
+    gains: [f32; 3],
+}
+
+pub const CHANNELS: [&str; 3] = ["5B A", "5B B", "5B C"];
+
+impl Sunsoft5b {
+    pub fn new() -> Self {
+        let tone = || Tone { period: 0, timer: 0, high: false };
+        Sunsoft5b {
+            address: 0,
+            tones: [tone(), tone(), tone()],
+            volumes: [0; 3],
+            mixer: 0xFF,
+            noise_period: 0,
+            noise_timer: 0,
+            noise_lfsr: 1,
+            envelope_period: 0,
+            envelope_timer: 0,
+            envelope_shape: 0,
+            envelope_step: 0,
+            envelope_holding: true,
+            envelope_inverted: false,
+            divider: 0,
+            gains: [1.0; 3],
+        }
+    }
+
+    // per channel volume, only changes what comes out
+    pub fn set_gains(&mut self, gains: &[f32]) {
+        for (gain, value) in self.gains.iter_mut().zip(gains) {
+            *gain = *value;
+        }
+    }
+
+    pub fn write_address(&mut self, data: u8) {
+        self.address = data & 0x0F;
+    }
+
+    pub fn write_data(&mut self, data: u8) {
+        match self.address {
+            reg @ (0 | 2 | 4) => {
+                let tone = &mut self.tones[(reg / 2) as usize];
+                tone.period = (tone.period & 0xF00) | data as u16;
+            }
+            reg @ (1 | 3 | 5) => {
+                let tone = &mut self.tones[(reg / 2) as usize];
+                tone.period = (tone.period & 0x0FF) | ((data & 0x0F) as u16) << 8;
+            }
+            6 => self.noise_period = data & 0x1F,
+            // ..NN NTTT, a set bit disables noise/tone for a channel
+            7 => self.mixer = data,
+            // ...E VVVV
+            reg @ 8..=10 => self.volumes[(reg - 8) as usize] = data & 0x1F,
+            11 => self.envelope_period = (self.envelope_period & 0xFF00) | data as u16,
+            12 => self.envelope_period = (self.envelope_period & 0x00FF) | (data as u16) << 8,
+            //  .... CAAH
+            //       |||+- hold
+            //       ||+-- alternate
+            //       |+--- attack (count up)
+            //       +---- continue
+            13 => {
+                self.envelope_shape = data & 0x0F;
+                self.envelope_step = 0;
+                self.envelope_timer = 0;
+                self.envelope_holding = false;
+                self.envelope_inverted = false;
+            }
+            _ => {}
+        }
+    }
+
+    pub fn clock(&mut self) {
+        self.divider = (self.divider + 1) % 16;
+        if self.divider.is_multiple_of(8) {
+            self.clock_envelope();
+        }
+        if self.divider == 0 {
+            for tone in self.tones.iter_mut() {
+                tone.clock();
+            }
+            self.noise_timer += 1;
+            if self.noise_timer >= self.noise_period.max(1) * 2 {
+                self.noise_timer = 0;
+                let feedback = (self.noise_lfsr ^ (self.noise_lfsr >> 3)) & 1;
+                self.noise_lfsr = (self.noise_lfsr >> 1) | (feedback << 16);
+            }
+        }
+    }
+
+    fn clock_envelope(&mut self) {
+        if self.envelope_holding {
+            return;
+        }
+        self.envelope_timer += 1;
+        if self.envelope_timer < self.envelope_period.max(1) {
+            return;
+        }
+        self.envelope_timer = 0;
+        self.envelope_step += 1;
+        if self.envelope_step < 32 {
+            return;
+        }
+        let shape = self.envelope_shape;
+        if shape & 0b1000 == 0 || shape & 0b0001 != 0 {
+            self.envelope_holding = true;
+            self.envelope_step = 31;
+        } else {
+            self.envelope_step = 0;
+            if shape & 0b0010 != 0 {
+                self.envelope_inverted = !self.envelope_inverted;
+            }
+        }
+    }
+
+    fn envelope_level(&self) -> u8 {
+        let shape = self.envelope_shape;
+        let attack = shape & 0b0100 != 0;
+        let continues = shape & 0b1000 != 0;
+        if self.envelope_holding {
+            // shapes 0-7 end silent, the others hold the last (possibly alternated) level
+            let end_high = continues && (attack != (shape & 0b0010 != 0));
+            return if end_high { 31 } else { 0 };
+        }
+        if attack != self.envelope_inverted {
+            self.envelope_step
+        } else {
+            31 - self.envelope_step
+        }
+    }
+
+    pub fn output(&self) -> f32 {
+        let noise = self.noise_lfsr & 1 != 0;
+        let mut sum = 0.0;
+        for (i, tone) in self.tones.iter().enumerate() {
+            let tone_on = tone.high || self.mixer & (1 << i) != 0;
+            let noise_on = noise || self.mixer & (8 << i) != 0;
+            if !(tone_on && noise_on) {
+                continue;
+            }
+            let volume = self.volumes[i];
+            let dac = if volume & 0x10 != 0 {
+                self.envelope_level()
+            } else if volume & 0x0F == 0 {
+                0
+            } else {
+                (volume & 0x0F) * 2 + 1
+            };
+            sum += level(dac) * self.gains[i];
+        }
+        sum
+    }
+}
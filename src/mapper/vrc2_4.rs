@@ -0,0 +1,177 @@
+use crate::cartridge::{Mirroring, Rom};
+use crate::mapper::vrc_irq::VrcIrq;
+use crate::mapper::{bank_offset, ChrMemory, Mapper, PrgRam};
+
+// Konami VRC2 and VRC4, mappers 21, 22, 23 and 25.
+//
+// The boards only differ in which CPU address lines are wired to the
+// chip's register select pins A0/A1:
+//
+//  mapper | submapper | board | A0    | A1
+//  -------+-----------+-------+-------+------
+//    21   |     1     | VRC4a | A1    | A2
+//    21   |     2     | VRC4c | A6    | A7
+//    22   |     0     | VRC2a | A1    | A0
+//    23   |     1     | VRC4f | A0    | A1
+//    23   |     2     | VRC4e | A2    | A3
+//    23   |     3     | VRC2b | A0    | A1
+//    25   |     1     | VRC4b | A1    | A0
+//    25   |     2     | VRC4d | A3    | A2
+//    25   |     3     | VRC2c | A1    | A0
+//
+// Without a submapper both wirings of a mapper number are decoded at once,
+// which works because games only ever touch one of them.
+pub struct Vrc2_4 {
+    prg_rom: Vec<u8>,
+    chr: ChrMemory,
+    prg_ram: PrgRam,
+    mapper: u16,
+    submapper: u8,
+    vrc2: bool,
+
+    prg_banks: [u8; 2],
+    prg_swap: bool,
+    chr_banks: [u16; 8],
+    mirroring: Mirroring,
+    irq: VrcIrq,
+}
+
+impl Vrc2_4 {
+    pub fn new(mut rom: Rom) -> Self {
+        let chr = ChrMemory::new(&mut rom);
+        let prg_ram = PrgRam::new(&rom);
+        let vrc2 = matches!((rom.mapper, rom.submapper), (22, _) | (23, 3) | (25, 3));
+        Vrc2_4 {
+            prg_rom: rom.prg_rom,
+            chr,
+            prg_ram,
+            mapper: rom.mapper,
+            submapper: rom.submapper,
+            vrc2,
+            prg_banks: [0, 1],
+            prg_swap: false,
+            chr_banks: [0; 8],
+            mirroring: rom.screen_mirroring,
+            irq: VrcIrq::new(),
+        }
+    }
+
+    // turns a CPU address into the canonical $x000-$x003 register address
+    fn translate(&self, addr: u16) -> u16 {
+        let bit = |n: u16| (addr >> n) & 1;
+        let (a0, a1) = match (self.mapper, self.submapper) {
+            (21, 1) => (bit(1), bit(2)),
+            (21, 2) => (bit(6), bit(7)),
+            (21, _) => (bit(1) | bit(6), bit(2) | bit(7)),
+            (22, _) => (bit(1), bit(0)),
+            (23, 1) | (23, 3) => (bit(0), bit(1)),
+            (23, 2) => (bit(2), bit(3)),
+            (23, _) => (bit(0) | bit(2), bit(1) | bit(3)),
+            (25, 1) | (25, 3) => (bit(1), bit(0)),
+            (25, 2) => (bit(3), bit(2)),
+            (_, _) => (bit(1) | bit(3), bit(0) | bit(2)),
+        };
+        (addr & 0xF000) | (a1 << 1) | a0
+    }
+
+    fn chr_offset(&self, addr: u16) -> usize {
+        let mut bank = self.chr_banks[(addr / 0x400) as usize] as usize;
+        if self.mapper == 22 {
+            // VRC2a ignores the lowest bit of the bank number
+            bank >>= 1;
+        }
+        bank_offset(self.chr.len(), 0x400, bank) + (addr & 0x3FF) as usize
+    }
+
+    fn write_chr_bank(&mut self, reg: u16, data: u8) {
+        let index = (((reg - 0xB000) >> 12) * 2 + ((reg >> 1) & 1)) as usize;
+        let bank = self.chr_banks[index];
+        self.chr_banks[index] = if reg & 1 == 0 {
+            (bank & 0x1F0) | (data & 0x0F) as u16
+        } else {
+            (bank & 0x00F) | ((data & 0x1F) as u16) << 4
+        };
+    }
+}
+
+impl Mapper for Vrc2_4 {
+    fn read_prg(&mut self, addr: u16) -> u8 {
+        // an 8K rom is its own second to last bank
+        let last = (self.prg_rom.len() / 0x2000).saturating_sub(1);
+        let bank = match (addr - 0x8000) / 0x2000 {
+            0 if self.prg_swap => last.saturating_sub(1),
+            0 => self.prg_banks[0] as usize,
+            1 => self.prg_banks[1] as usize,
+            2 if self.prg_swap => self.prg_banks[0] as usize,
+            2 => last.saturating_sub(1),
+            _ => last,
+        };
+        self.prg_rom[bank_offset(self.prg_rom.len(), 0x2000, bank) + (addr & 0x1FFF) as usize]
+    }
+
+    fn write_prg(&mut self, addr: u16, data: u8) {
+        match self.translate(addr) {
+            0x8000..=0x8003 => self.prg_banks[0] = data & 0x1F,
+            0x9000..=0x9003 if self.vrc2 => {
+                self.mirroring = if data & 1 == 0 { Mirroring::VERTICAL } else { Mirroring::HORIZONTAL };
+            }
+            0x9000 => {
+                self.mirroring = match data & 0b11 {
+                    0 => Mirroring::VERTICAL,
+                    1 => Mirroring::HORIZONTAL,
+                    2 => Mirroring::ONE_SCREEN_LOWER,
+                    _ => Mirroring::ONE_SCREEN_UPPER,
+                };
+            }
+            0x9002 => self.prg_swap = data & 0b10 != 0,
+            0xA000..=0xA003 => self.prg_banks[1] = data & 0x1F,
+            reg @ 0xB000..=0xE003 => self.write_chr_bank(reg, data),
+            0xF000 if !self.vrc2 => self.irq.write_latch_lo(data),
+            0xF001 if !self.vrc2 => self.irq.write_latch_hi(data),
+            0xF002 if !self.vrc2 => self.irq.write_control(data),
+            0xF003 if !self.vrc2 => self.irq.acknowledge(),
+            _ => {}
+        }
+    }
+
+    fn read_chr(&mut self, addr: u16) -> u8 {
+        self.chr.read(self.chr_offset(addr))
+    }
+
+    fn write_chr(&mut self, addr: u16, data: u8) {
+        let offset = self.chr_offset(addr);
+        self.chr.write(offset, data);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn prg_ram(&mut self) -> &mut PrgRam {
+        &mut self.prg_ram
+    }
+
+    fn cpu_clock(&mut self) {
+        self.irq.clock();
+    }
+
+    fn irq_pending(&self) -> bool {
+        self.irq.pending()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cartridge::test::test_rom;
+
+    #[test]
+    fn test_8k_prg() {
+        let mut rom = test_rom();
+        rom.mapper = 23;
+        rom.prg_rom = vec![7; 0x2000];
+        let mut mapper = Vrc2_4::new(rom);
+        assert_eq!(mapper.read_prg(0xC000), 7);
+        assert_eq!(mapper.read_prg(0xE000), 7);
+    }
+}
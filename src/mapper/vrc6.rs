@@ -0,0 +1,328 @@
+use crate::cartridge::{Mirroring, Rom};
+use crate::mapper::vrc_irq::VrcIrq;
+use crate::mapper::{bank_offset, ChrMemory, Mapper, PrgRam};
+
+// Konami VRC6, mapper 24 (VRC6a) and 26 (VRC6b, register lines A0 and A1 swapped).
+//
+// $6000-$7FFF   8K PRG RAM, enabled by bit 7 of $B003
+// $8000-$BFFF  16K switchable PRG
+// $C000-$DFFF   8K switchable PRG
+// $E000-$FFFF   8K fixed to the last bank
+//
+// Besides the banking the chip has two pulse channels and a sawtooth channel.
+pub struct Vrc6 {
+    prg_rom: Vec<u8>,
+    chr: ChrMemory,
+    prg_ram: PrgRam,
+    swap_lines: bool,
+
+    prg_16k: u8,
+    prg_8k: u8,
+    chr_banks: [u8; 8],
+    banking_mode: u8,
+    irq: VrcIrq,
+    audio: Vrc6Audio,
+}
+
+impl Vrc6 {
+    pub fn new(mut rom: Rom) -> Self {
+        let chr = ChrMemory::new(&mut rom);
+        let mut prg_ram = PrgRam::new(&rom);
+        prg_ram.enabled = false;
+        Vrc6 {
+            prg_rom: rom.prg_rom,
+            chr,
+            prg_ram,
+            swap_lines: rom.mapper == 26,
+            prg_16k: 0,
+            prg_8k: 0,
+            chr_banks: [0; 8],
+            banking_mode: 0,
+            irq: VrcIrq::new(),
+            audio: Vrc6Audio::new(),
+        }
+    }
+
+    fn chr_offset(&self, addr: u16) -> usize {
+        let slot = (addr / 0x400) as usize;
+        let a10 = (slot & 1) as u8;
+        // mode 0: eight 1K banks, mode 1: four 2K banks, mode 2/3: four 1K banks followed by two 2K banks
+        let bank = match self.banking_mode & 0b11 {
+            0 => self.chr_banks[slot],
+            1 => (self.chr_banks[slot / 2] & 0xFE) | a10,
+            _ if slot < 4 => self.chr_banks[slot],
+            _ => (self.chr_banks[4 + (slot - 4) / 2] & 0xFE) | a10,
+        };
+        bank_offset(self.chr.len(), 0x400, bank as usize) + (addr & 0x3FF) as usize
+    }
+
+    fn translate(&self, addr: u16) -> u16 {
+        if self.swap_lines {
+            (addr & 0xF000) | ((addr & 1) << 1) | ((addr >> 1) & 1)
+        } else {
+            addr & 0xF003
+        }
+    }
+}
+
+impl Mapper for Vrc6 {
+    fn read_prg(&mut self, addr: u16) -> u8 {
+        let len = self.prg_rom.len();
+        let offset = match addr {
+            0x8000..=0xBFFF => bank_offset(len, 0x4000, self.prg_16k as usize) + (addr & 0x3FFF) as usize,
+            0xC000..=0xDFFF => bank_offset(len, 0x2000, self.prg_8k as usize) + (addr & 0x1FFF) as usize,
+            _ => len - 0x2000 + (addr & 0x1FFF) as usize,
+        };
+        self.prg_rom[offset]
+    }
+
+    fn write_prg(&mut self, addr: u16, data: u8) {
+        match self.translate(addr) {
+            0x8000..=0x8003 => self.prg_16k = data & 0x0F,
+            reg @ (0x9000..=0x9003 | 0xA000..=0xA002 | 0xB000..=0xB002) => self.audio.write(reg, data),
+            0xB003 => {
+                self.banking_mode = data;
+                self.prg_ram.enabled = data & 0x80 != 0;
+            }
+            0xC000..=0xC003 => self.prg_8k = data & 0x1F,
+            reg @ 0xD000..=0xD003 => self.chr_banks[(reg & 3) as usize] = data,
+            reg @ 0xE000..=0xE003 => self.chr_banks[4 + (reg & 3) as usize] = data,
+            0xF000 => self.irq.write_latch(data),
+            0xF001 => self.irq.write_control(data),
+            0xF002 => self.irq.acknowledge(),
+            _ => {}
+        }
+    }
+
+    fn read_chr(&mut self, addr: u16) -> u8 {
+        self.chr.read(self.chr_offset(addr))
+    }
+
+    fn write_chr(&mut self, addr: u16, data: u8) {
+        let offset = self.chr_offset(addr);
+        self.chr.write(offset, data);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        match (self.banking_mode >> 2) & 0b11 {
+            0 => Mirroring::VERTICAL,
+            1 => Mirroring::HORIZONTAL,
+            2 => Mirroring::ONE_SCREEN_LOWER,
+            _ => Mirroring::ONE_SCREEN_UPPER,
+        }
+    }
+
+    fn prg_ram(&mut self) -> &mut PrgRam {
+        &mut self.prg_ram
+    }
+
+    fn cpu_clock(&mut self) {
+        self.irq.clock();
+        self.audio.clock();
+    }
+
+    fn irq_pending(&self) -> bool {
+        self.irq.pending()
+    }
+
+    fn audio_output(&self) -> f32 {
+        self.audio.output()
+    }
+
+    fn expansion_channels(&self) -> Vec<&'static str> {
+        CHANNELS.to_vec()
+    }
+
+    fn set_expansion_gains(&mut self, gains: &[f32]) {
+        self.audio.set_gains(gains);
+    }
+}
+
+struct Vrc6Pulse {
+    volume: u8,
+    duty: u8,
+    ignore_duty: bool,
+    period: u16,
+    enabled: bool,
+    timer: u16,
+    step: u8,
+}
+
+impl Vrc6Pulse {
+    fn new() -> Self {
+        Vrc6Pulse {
+            volume: 0,
+            duty: 0,
+            ignore_duty: false,
+            period: 0,
+            enabled: false,
+            timer: 0,
+            step: 15,
+        }
+    }
+
+    fn write(&mut self, reg: u16, data: u8) {
+        match reg {
+            // MDDD VVVV
+            0 => {
+                self.ignore_duty = data & 0x80 != 0;
+                self.duty = (data >> 4) & 0b111;
+                self.volume = data & 0x0F;
+            }
+            1 => self.period = (self.period & 0x0F00) | data as u16,
+            // E... PPPP
+            _ => {
+                self.period = (self.period & 0x00FF) | ((data & 0x0F) as u16) << 8;
+                self.enabled = data & 0x80 != 0;
+                if !self.enabled {
+                    self.step = 15;
+                }
+            }
+        }
+    }
+
+    fn clock(&mut self, shift: u8) {
+        if !self.enabled {
+            return;
+        }
+        if self.timer == 0 {
+            self.timer = self.period >> shift;
+            self.step = if self.step == 0 { 15 } else { self.step - 1 };
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    fn output(&self) -> u8 {
+        if self.enabled && (self.ignore_duty || self.step <= self.duty) {
+            self.volume
+        } else {
+            0
+        }
+    }
+}
+
+struct Vrc6Saw {
+    rate: u8,
+    period: u16,
+    enabled: bool,
+    timer: u16,
+    step: u8,
+    accumulator: u8,
+}
+
+impl Vrc6Saw {
+    fn new() -> Self {
+        Vrc6Saw {
+            rate: 0,
+            period: 0,
+            enabled: false,
+            timer: 0,
+            step: 0,
+            accumulator: 0,
+        }
+    }
+
+    fn write(&mut self, reg: u16, data: u8) {
+        match reg {
+            0 => self.rate = data & 0x3F,
+            1 => self.period = (self.period & 0x0F00) | data as u16,
+            _ => {
+                self.period = (self.period & 0x00FF) | ((data & 0x0F) as u16) << 8;
+                self.enabled = data & 0x80 != 0;
+                if !self.enabled {
+                    self.step = 0;
+                    self.accumulator = 0;
+                }
+            }
+        }
+    }
+
+    // the accumulator is increased on every second step and reset after the 7th increase
+    fn clock(&mut self, shift: u8) {
+        if !self.enabled {
+            return;
+        }
+        if self.timer == 0 {
+            self.timer = self.period >> shift;
+            self.step += 1;
+            if self.step == 14 {
+                self.step = 0;
+                self.accumulator = 0;
+            } else if self.step.is_multiple_of(2) {
+                self.accumulator = self.accumulator.wrapping_add(self.rate);
+            }
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    fn output(&self) -> u8 {
+        self.accumulator >> 3
+    }
+}
+
+pub(super) struct Vrc6Audio {
+    pulse1: Vrc6Pulse,
+    pulse2: Vrc6Pulse,
+    saw: Vrc6Saw,
+    halt: bool,
+    shift: u8,
+    gains: [f32; 3],
+}
+
+pub(super) const CHANNELS: [&str; 3] = ["VRC6 Pulse 1", "VRC6 Pulse 2", "VRC6 Saw"];
+
+impl Vrc6Audio {
+    pub(super) fn new() -> Self {
+        Vrc6Audio {
+            pulse1: Vrc6Pulse::new(),
+            pulse2: Vrc6Pulse::new(),
+            saw: Vrc6Saw::new(),
+            halt: false,
+            shift: 0,
+            gains: [1.0; 3],
+        }
+    }
+
+    // per channel volume, only changes what comes out
+    pub(super) fn set_gains(&mut self, gains: &[f32]) {
+        for (gain, value) in self.gains.iter_mut().zip(gains) {
+            *gain = *value;
+        }
+    }
+
+    pub(super) fn write(&mut self, reg: u16, data: u8) {
+        match reg {
+            0x9003 => {
+                self.halt = data & 0b001 != 0;
+                self.shift = if data & 0b100 != 0 {
+                    8
+                } else if data & 0b010 != 0 {
+                    4
+                } else {
+                    0
+                };
+            }
+            0x9000..=0x9002 => self.pulse1.write(reg & 3, data),
+            0xA000..=0xA002 => self.pulse2.write(reg & 3, data),
+            _ => self.saw.write(reg & 3, data),
+        }
+    }
+
+    pub(super) fn clock(&mut self) {
+        if self.halt {
+            return;
+        }
+        self.pulse1.clock(self.shift);
+        self.pulse2.clock(self.shift);
+        self.saw.clock(self.shift);
+    }
+
+    // a VRC6 pulse at full volume is about as loud as an APU pulse at full volume
+    pub(super) fn output(&self) -> f32 {
+        let [pulse1, pulse2, saw] = self.gains;
+        let sum = self.pulse1.output() as f32 * pulse1 + self.pulse2.output() as f32 * pulse2 + self.saw.output() as f32 * saw;
+        sum * 0.00752
+    }
+}
@@ -0,0 +1,150 @@
+use crate::cartridge::{Mirroring, Rom};
+use crate::mapper::opll::Opll;
+use crate::mapper::vrc_irq::VrcIrq;
+use crate::mapper::{bank_offset, ChrMemory, Mapper, PrgRam};
+
+// Konami VRC7, mapper 85.
+//
+// Three switchable 8K PRG banks at $8000, $A000 and $C000, the last 8K is fixed,
+// eight 1K CHR banks and the OPLL FM sound chip. VRC7a selects the second
+// register of a pair with A4 ($x010), VRC7b with A3 ($x008), both are decoded.
+// Bit 7 of $E000 enables the PRG RAM at $6000.
+pub struct Vrc7 {
+    prg_rom: Vec<u8>,
+    chr: ChrMemory,
+    prg_ram: PrgRam,
+
+    prg_banks: [u8; 3],
+    chr_banks: [u8; 8],
+    control: u8,
+    irq: VrcIrq,
+    opll: Opll,
+}
+
+// the OPLL output is a sum of six carriers in -1.0..=1.0
+pub(super) const OPLL_VOLUME: f32 = 0.06;
+
+pub(super) const CHANNELS: [&str; 6] = ["VRC7 1", "VRC7 2", "VRC7 3", "VRC7 4", "VRC7 5", "VRC7 6"];
+
+impl Vrc7 {
+    pub fn new(mut rom: Rom) -> Self {
+        let chr = ChrMemory::new(&mut rom);
+        let mut prg_ram = PrgRam::new(&rom);
+        prg_ram.enabled = false;
+        Vrc7 {
+            prg_rom: rom.prg_rom,
+            chr,
+            prg_ram,
+            prg_banks: [0; 3],
+            chr_banks: [0; 8],
+            control: 0,
+            irq: VrcIrq::new(),
+            opll: Opll::new(),
+        }
+    }
+
+    fn chr_offset(&self, addr: u16) -> usize {
+        let bank = self.chr_banks[(addr / 0x400) as usize];
+        bank_offset(self.chr.len(), 0x400, bank as usize) + (addr & 0x3FF) as usize
+    }
+
+    fn silenced(&self) -> bool {
+        self.control & 0x40 != 0
+    }
+}
+
+impl Mapper for Vrc7 {
+    fn read_prg(&mut self, addr: u16) -> u8 {
+        let len = self.prg_rom.len();
+        let offset = match addr {
+            0x8000..=0xDFFF => {
+                let bank = self.prg_banks[((addr - 0x8000) / 0x2000) as usize];
+                bank_offset(len, 0x2000, bank as usize)
+            }
+            _ => len - 0x2000,
+        };
+        self.prg_rom[offset + (addr & 0x1FFF) as usize]
+    }
+
+    fn write_prg(&mut self, addr: u16, data: u8) {
+        let odd = addr & 0x18 != 0;
+        match (addr & 0xF000, odd) {
+            (0x8000, false) => self.prg_banks[0] = data & 0x3F,
+            (0x8000, true) => self.prg_banks[1] = data & 0x3F,
+            (0x9000, false) => self.prg_banks[2] = data & 0x3F,
+            (0x9000, true) => match addr & 0x30 {
+                0x10 => self.opll.write_address(data),
+                0x30 => self.opll.write_data(data),
+                _ => {}
+            },
+            (reg @ 0xA000..=0xD000, _) => {
+                let index = ((reg - 0xA000) >> 12) as usize * 2 + odd as usize;
+                self.chr_banks[index] = data;
+            }
+            //  RS.. ..MM
+            //  ||     ++- mirroring
+            //  |+-------- silence expansion sound
+            //  +--------- WRAM enable
+            (0xE000, false) => {
+                if data & 0x40 != 0 && !self.silenced() {
+                    self.opll.reset();
+                }
+                self.control = data;
+                self.prg_ram.enabled = data & 0x80 != 0;
+            }
+            (0xE000, true) => self.irq.write_latch(data),
+            (0xF000, false) => self.irq.write_control(data),
+            (0xF000, true) => self.irq.acknowledge(),
+            _ => {}
+        }
+    }
+
+    fn read_chr(&mut self, addr: u16) -> u8 {
+        self.chr.read(self.chr_offset(addr))
+    }
+
+    fn write_chr(&mut self, addr: u16, data: u8) {
+        let offset = self.chr_offset(addr);
+        self.chr.write(offset, data);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        match self.control & 0b11 {
+            0 => Mirroring::VERTICAL,
+            1 => Mirroring::HORIZONTAL,
+            2 => Mirroring::ONE_SCREEN_LOWER,
+            _ => Mirroring::ONE_SCREEN_UPPER,
+        }
+    }
+
+    fn prg_ram(&mut self) -> &mut PrgRam {
+        &mut self.prg_ram
+    }
+
+    fn cpu_clock(&mut self) {
+        self.irq.clock();
+        if !self.silenced() {
+            self.opll.clock();
+        }
+    }
+
+    fn irq_pending(&self) -> bool {
+        self.irq.pending()
+    }
+
+    fn audio_output(&self) -> f32 {
+        if self.silenced() {
+            0.0
+        } else {
+            self.opll.output() * OPLL_VOLUME
+        }
+    }
+
+    fn expansion_channels(&self) -> Vec<&'static str> {
+        CHANNELS.to_vec()
+    }
+
+    fn set_expansion_gains(&mut self, gains: &[f32]) {
+        self.opll.set_gains(gains);
+    }
+}
@@ -0,0 +1,127 @@
+use crate::cartridge::{Mirroring, Rom};
+use crate::mapper::{bank_offset, ChrMemory, Mapper, PrgRam};
+
+// Vs. UniSystem, mapper 99.
+//
+// 32K of fixed PRG and an 8K CHR bank picked by bit 2 of the $4016 output
+// latch. Carts with 40K of PRG (Vs. Gumshoe) swap the extra 8K in at $8000
+// with the same bit. The cabinet has 2K of work RAM at $6000-$7FFF and 4K of
+// nametable RAM for four screen games.
+pub struct VsUnisystem {
+    prg_rom: Vec<u8>,
+    chr: ChrMemory,
+    prg_ram: PrgRam,
+    mirroring: Mirroring,
+    nametables: Option<[u8; 0x1000]>,
+    bank: u8,
+}
+
+impl VsUnisystem {
+    pub fn new(mut rom: Rom) -> Self {
+        let chr = ChrMemory::new(&mut rom);
+        let mut prg_ram = PrgRam::new(&rom);
+        if prg_ram.data().is_empty() {
+            prg_ram = PrgRam::with_size(0x800);
+        }
+        VsUnisystem {
+            prg_rom: rom.prg_rom,
+            chr,
+            prg_ram,
+            mirroring: rom.screen_mirroring,
+            nametables: (rom.screen_mirroring == Mirroring::FOUR_SCREEN).then_some([0; 0x1000]),
+            bank: 0,
+        }
+    }
+}
+
+impl Mapper for VsUnisystem {
+    fn read_prg(&mut self, addr: u16) -> u8 {
+        let offset = match addr {
+            0x8000..=0x9FFF if self.prg_rom.len() > 0x8000 => self.bank as usize * 0x8000,
+            _ => 0,
+        };
+        self.prg_rom[(offset + (addr - 0x8000) as usize) % self.prg_rom.len()]
+    }
+
+    fn write_prg(&mut self, _addr: u16, _data: u8) {}
+
+    fn read_chr(&mut self, addr: u16) -> u8 {
+        let bank = bank_offset(self.chr.len(), 0x2000, self.bank as usize);
+        self.chr.read(bank + addr as usize)
+    }
+
+    fn write_chr(&mut self, addr: u16, data: u8) {
+        let bank = bank_offset(self.chr.len(), 0x2000, self.bank as usize);
+        self.chr.write(bank + addr as usize, data);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn prg_ram(&mut self) -> &mut PrgRam {
+        &mut self.prg_ram
+    }
+
+    fn write_output_latch(&mut self, data: u8) {
+        self.bank = (data >> 2) & 1;
+    }
+
+    fn read_nametable(&mut self, addr: u16) -> Option<u8> {
+        self.nametables.map(|nametables| nametables[(addr & 0x0FFF) as usize])
+    }
+
+    fn write_nametable(&mut self, addr: u16, data: u8) -> bool {
+        match &mut self.nametables {
+            Some(nametables) => {
+                nametables[(addr & 0x0FFF) as usize] = data;
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cartridge::HeaderFormat;
+
+    fn rom(prg_banks: usize) -> Rom {
+        let mut rom = Rom::empty(HeaderFormat::INes);
+        rom.mapper = 99;
+        rom.prg_rom = (0..prg_banks * 0x2000).map(|i| (i / 0x2000) as u8).collect();
+        rom.chr_rom = (0..0x4000).map(|i| (i / 0x2000) as u8).collect();
+        rom.screen_mirroring = Mirroring::FOUR_SCREEN;
+        rom
+    }
+
+    #[test]
+    fn test_output_latch_switches_chr() {
+        let mut mapper = VsUnisystem::new(rom(4));
+        assert_eq!(mapper.read_chr(0x0000), 0);
+        mapper.write_output_latch(0b100);
+        assert_eq!(mapper.read_chr(0x0000), 1);
+        // 32K of PRG doesn't move
+        assert_eq!(mapper.read_prg(0x8000), 0);
+        assert_eq!(mapper.read_prg(0xE000), 3);
+    }
+
+    #[test]
+    fn test_40k_prg() {
+        let mut mapper = VsUnisystem::new(rom(5));
+        mapper.write_output_latch(0b100);
+        assert_eq!(mapper.read_prg(0x8000), 4);
+        assert_eq!(mapper.read_prg(0xA000), 1);
+    }
+
+    #[test]
+    fn test_four_screen_and_work_ram() {
+        let mut mapper = VsUnisystem::new(rom(4));
+        assert!(mapper.write_nametable(0x2C00, 0x55));
+        assert_eq!(mapper.read_nametable(0x2C00), Some(0x55));
+        assert_eq!(mapper.read_nametable(0x2000), Some(0));
+        mapper.write_prg_ram(0x6000, 0xAA);
+        assert_eq!(mapper.read_prg_ram(0x6800), Some(0xAA));
+    }
+}
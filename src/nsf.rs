@@ -0,0 +1,542 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::bus::Bus;
+use crate::cartridge::Timing;
+use crate::cpu::{CpuFlags, CPU};
+use crate::mapper;
+use crate::region::Region;
+
+// NSF and NSFE music rips: the sound code and data of a game, called
+// through an init and a play routine instead of running the game itself.
+//
+// NSF has a 128 byte header:
+//
+//  $00  "NESM\x1A", version
+//  $06  number of songs, first song (1 based)
+//  $08  load, init and play address
+//  $0E  title, artist and copyright, 32 bytes each
+//  $6E  play speed on NTSC in microseconds
+//  $70  initial $5FF8-$5FFF banks, all zero when not bankswitched
+//  $78  play speed on PAL
+//  $7A  bit 0 PAL, bit 1 both
+//  $7B  expansion chips
+//
+// NSFE is "NSFE" followed by chunks of a 32 bit little endian length, a 4
+// character id and the data:
+//
+//  INFO  addresses, region, chips, song count and first song (0 based)
+//  DATA  the program
+//  BANK  initial banks
+//  RATE  play speed on NTSC, PAL and Dendy
+//  auth  title, artist, copyright and ripper, zero terminated
+//  tlbl  track titles, zero terminated
+//  time  track lengths in milliseconds, 32 bit signed, negative when unknown
+//  fade  fade out lengths, the same
+//  NEND  end of file
+//
+// Chunks starting with a lowercase letter can be skipped, unknown uppercase
+// ones can't.
+
+const HEADER_SIZE: usize = 128;
+
+// the play routine runs once per frame unless the file says otherwise
+const NTSC_SPEED: u16 = 16639;
+const PAL_SPEED: u16 = 19997;
+
+bitflags::bitflags! {
+    pub struct ExpansionChips: u8 {
+        const VRC6 = 0b0000_0001;
+        const VRC7 = 0b0000_0010;
+        const FDS  = 0b0000_0100;
+        const MMC5 = 0b0000_1000;
+        const N163 = 0b0001_0000;
+        const S5B  = 0b0010_0000;
+    }
+}
+
+#[derive(Debug)]
+pub enum NsfError {
+    Io(io::Error),
+    TooShort(usize),
+    BadMagic,
+    MissingChunk(&'static str),
+    UnknownChunk(String),
+    TruncatedChunk(String),
+}
+
+impl fmt::Display for NsfError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            NsfError::Io(e) => write!(f, "Can't read NSF: {}", e),
+            NsfError::TooShort(len) => write!(f, "File is too short for an NSF header ({} bytes)", len),
+            NsfError::BadMagic => write!(f, "File is not in NSF or NSFE format"),
+            NsfError::MissingChunk(id) => write!(f, "NSFE file has no {} chunk", id),
+            NsfError::UnknownChunk(id) => write!(f, "NSFE chunk {} is required but not supported", id),
+            NsfError::TruncatedChunk(id) => write!(f, "NSFE chunk {} is truncated", id),
+        }
+    }
+}
+
+impl std::error::Error for NsfError {}
+
+impl From<io::Error> for NsfError {
+    fn from(e: io::Error) -> Self {
+        NsfError::Io(e)
+    }
+}
+
+#[derive(Debug, Default, PartialEq, Clone)]
+pub struct TrackInfo {
+    pub title: Option<String>,
+    pub length_ms: Option<u32>,
+    pub fade_ms: Option<u32>,
+}
+
+pub struct Nsf {
+    pub load_addr: u16,
+    pub init_addr: u16,
+    pub play_addr: u16,
+    // None when the program is loaded in one piece at load_addr
+    pub banks: Option<[u8; 8]>,
+    pub timing: Timing,
+    pub chips: ExpansionChips,
+    // microseconds between two calls of the play routine
+    pub ntsc_speed: u16,
+    pub pal_speed: u16,
+
+    pub first_track: usize,
+    pub tracks: Vec<TrackInfo>,
+    pub title: String,
+    pub artist: String,
+    pub copyright: String,
+    pub ripper: Option<String>,
+
+    pub data: Vec<u8>,
+}
+
+fn c_string(data: &[u8]) -> String {
+    let end = data.iter().position(|b| *b == 0).unwrap_or(data.len());
+    String::from_utf8_lossy(&data[..end]).to_string()
+}
+
+fn c_strings(data: &[u8]) -> Vec<String> {
+    let data = data.strip_suffix(&[0]).unwrap_or(data);
+    data.split(|b| *b == 0).map(c_string).collect()
+}
+
+fn u16_at(data: &[u8], pos: usize) -> u16 {
+    u16::from_le_bytes([data[pos], data[pos + 1]])
+}
+
+// a zero speed means the default
+fn speed_or(speed: u16, default: u16) -> u16 {
+    if speed == 0 { default } else { speed }
+}
+
+fn timing(flags: u8) -> Timing {
+    if flags & 0b10 != 0 {
+        Timing::MultiRegion
+    } else if flags & 0b01 != 0 {
+        Timing::Pal
+    } else {
+        Timing::Ntsc
+    }
+}
+
+fn banks(data: &[u8]) -> Option<[u8; 8]> {
+    let mut banks = [0; 8];
+    banks[..data.len().min(8)].copy_from_slice(&data[..data.len().min(8)]);
+    if banks.iter().all(|b| *b == 0) { None } else { Some(banks) }
+}
+
+impl Nsf {
+    pub fn from_path(path: impl AsRef<Path>) -> Result<Nsf, NsfError> {
+        Nsf::from_bytes(&fs::read(path)?)
+    }
+
+    pub fn from_bytes(raw: &[u8]) -> Result<Nsf, NsfError> {
+        if raw.starts_with(b"NSFE") {
+            Nsf::parse_nsfe(raw)
+        } else if raw.starts_with(b"NESM\x1A") {
+            Nsf::parse_nsf(raw)
+        } else {
+            Err(NsfError::BadMagic)
+        }
+    }
+
+    fn parse_nsf(raw: &[u8]) -> Result<Nsf, NsfError> {
+        if raw.len() < HEADER_SIZE {
+            return Err(NsfError::TooShort(raw.len()));
+        }
+        let songs = raw[6].max(1) as usize;
+        Ok(Nsf {
+            load_addr: u16_at(raw, 0x08),
+            init_addr: u16_at(raw, 0x0A),
+            play_addr: u16_at(raw, 0x0C),
+            banks: banks(&raw[0x70..0x78]),
+            timing: timing(raw[0x7A]),
+            chips: ExpansionChips::from_bits_truncate(raw[0x7B]),
+            ntsc_speed: speed_or(u16_at(raw, 0x6E), NTSC_SPEED),
+            pal_speed: speed_or(u16_at(raw, 0x78), PAL_SPEED),
+            first_track: (raw[7].max(1) as usize - 1).min(songs - 1),
+            tracks: vec![TrackInfo::default(); songs],
+            title: c_string(&raw[0x0E..0x2E]),
+            artist: c_string(&raw[0x2E..0x4E]),
+            copyright: c_string(&raw[0x4E..0x6E]),
+            ripper: None,
+            data: raw[HEADER_SIZE..].to_vec(),
+        })
+    }
+
+    fn parse_nsfe(raw: &[u8]) -> Result<Nsf, NsfError> {
+        let mut nsf = None;
+        let mut data = None;
+        let mut bank_chunk = None;
+        let mut rate = None;
+        let mut auth = vec![];
+        let mut titles = vec![];
+        let mut times = vec![];
+        let mut fades = vec![];
+
+        let mut pos = 4;
+        while raw.len() - pos >= 8 {
+            let len = u32::from_le_bytes([raw[pos], raw[pos + 1], raw[pos + 2], raw[pos + 3]]) as usize;
+            let id = &raw[pos + 4..pos + 8];
+            let name = String::from_utf8_lossy(id).to_string();
+            let start = pos + 8;
+            if raw.len() - start < len {
+                return Err(NsfError::TruncatedChunk(name));
+            }
+            let chunk = &raw[start..start + len];
+            pos = start + len;
+
+            match id {
+                b"INFO" => {
+                    if chunk.len() < 9 {
+                        return Err(NsfError::TruncatedChunk(name));
+                    }
+                    let songs = chunk[8].max(1) as usize;
+                    nsf = Some(Nsf {
+                        load_addr: u16_at(chunk, 0),
+                        init_addr: u16_at(chunk, 2),
+                        play_addr: u16_at(chunk, 4),
+                        banks: None,
+                        timing: timing(chunk[6]),
+                        chips: ExpansionChips::from_bits_truncate(chunk[7]),
+                        ntsc_speed: NTSC_SPEED,
+                        pal_speed: PAL_SPEED,
+                        first_track: chunk.get(9).map_or(0, |first| (*first as usize).min(songs - 1)),
+                        tracks: vec![TrackInfo::default(); songs],
+                        title: String::new(),
+                        artist: String::new(),
+                        copyright: String::new(),
+                        ripper: None,
+                        data: vec![],
+                    });
+                }
+                b"DATA" => data = Some(chunk.to_vec()),
+                b"BANK" => bank_chunk = Some(chunk),
+                b"RATE" => {
+                    if chunk.len() < 4 {
+                        return Err(NsfError::TruncatedChunk(name));
+                    }
+                    rate = Some((u16_at(chunk, 0), u16_at(chunk, 2)));
+                }
+                b"auth" => auth = c_strings(chunk),
+                b"tlbl" => titles = c_strings(chunk),
+                b"time" => times = chunk.chunks_exact(4).map(|t| i32::from_le_bytes([t[0], t[1], t[2], t[3]])).collect(),
+                b"fade" => fades = chunk.chunks_exact(4).map(|t| i32::from_le_bytes([t[0], t[1], t[2], t[3]])).collect(),
+                b"NEND" => break,
+                _ if id[0].is_ascii_uppercase() => return Err(NsfError::UnknownChunk(name)),
+                _ => {}
+            }
+        }
+
+        let mut nsf = nsf.ok_or(NsfError::MissingChunk("INFO"))?;
+        nsf.data = data.ok_or(NsfError::MissingChunk("DATA"))?;
+        nsf.banks = bank_chunk.and_then(banks);
+        if let Some((ntsc, pal)) = rate {
+            nsf.ntsc_speed = speed_or(ntsc, NTSC_SPEED);
+            nsf.pal_speed = speed_or(pal, PAL_SPEED);
+        }
+        let mut auth = auth.into_iter();
+        nsf.title = auth.next().unwrap_or_default();
+        nsf.artist = auth.next().unwrap_or_default();
+        nsf.copyright = auth.next().unwrap_or_default();
+        nsf.ripper = auth.next();
+        for (i, track) in nsf.tracks.iter_mut().enumerate() {
+            track.title = titles.get(i).filter(|title| !title.is_empty()).cloned();
+            track.length_ms = times.get(i).and_then(|ms| u32::try_from(*ms).ok());
+            track.fade_ms = fades.get(i).and_then(|ms| u32::try_from(*ms).ok());
+        }
+        Ok(nsf)
+    }
+}
+
+// init and play return here, the CPU idles while its PC points at it
+const RETURN_ADDR: u16 = 0x4100;
+
+// for tracks that don't say how long they are
+const DEFAULT_TRACK_MS: u32 = 150_000;
+const DEFAULT_FADE_MS: u32 = 3_000;
+
+// Runs an NSF without a PPU frame driving it: init is called once per track,
+// play every ntsc_speed (or pal_speed) microseconds once the previous call
+// returned. Rendering steps the CPU along with the samples.
+pub struct NsfPlayer {
+    nsf: Nsf,
+    cpu: CPU,
+    region: Region,
+    track: usize,
+    play_period: usize,
+    next_play: usize,
+    sample_clock: f64,
+}
+
+impl NsfPlayer {
+    pub fn new(nsf: Nsf) -> Self {
+        let mut bus = Bus::with_mapper(mapper::new_nsf_mapper(&nsf));
+        let region = Region::from_timing(nsf.timing);
+        bus.set_region(region);
+        let speed = if region == Region::Pal { nsf.pal_speed } else { nsf.ntsc_speed };
+        let first_track = nsf.first_track;
+        let mut player = NsfPlayer {
+            nsf,
+            cpu: CPU::new(bus),
+            region,
+            track: 0,
+            play_period: (region.cpu_clock_rate() * speed as f64 / 1_000_000.0) as usize,
+            next_play: 0,
+            sample_clock: 0.0,
+        };
+        player.start_track(first_track);
+        player
+    }
+
+    pub fn nsf(&self) -> &Nsf {
+        &self.nsf
+    }
+
+    pub fn track(&self) -> usize {
+        self.track
+    }
+
+    pub fn track_count(&self) -> usize {
+        self.nsf.tracks.len()
+    }
+
+    // resets RAM, sound and banks the way the NSF spec asks and calls init
+    pub fn start_track(&mut self, track: usize) {
+        self.track = track.min(self.track_count() - 1);
+        let fds = self.nsf.chips.contains(ExpansionChips::FDS);
+
+        for addr in (0x0000..0x0800).chain(0x6000..0x8000) {
+            self.cpu.write_mem(addr, 0);
+        }
+        for addr in 0x4000..=0x4013 {
+            self.cpu.write_mem(addr, 0);
+        }
+        self.cpu.write_mem(0x4015, 0x00);
+        self.cpu.write_mem(0x4015, 0x0F);
+        self.cpu.write_mem(0x4017, 0x40);
+        if fds {
+            self.cpu.write_mem(0x4089, 0x80);
+            self.cpu.write_mem(0x408A, 0xE8);
+        }
+
+        // unbanked rips are laid out in order, from $6000 with FDS
+        let banks: Vec<u8> = match self.nsf.banks {
+            Some(banks) if fds => [banks[6], banks[7]].iter().chain(&banks).copied().collect(),
+            Some(banks) => banks.to_vec(),
+            None if fds => (0..10).collect(),
+            None => (0..8).collect(),
+        };
+        let first_bank_reg = if fds { 0x5FF6 } else { 0x5FF8 };
+        for (i, bank) in banks.into_iter().enumerate() {
+            self.cpu.write_mem(first_bank_reg + i as u16, bank);
+        }
+
+        self.cpu.reg_a = self.track as u8;
+        self.cpu.reg_x = (self.region == Region::Pal) as u8;
+        self.cpu.reg_y = 0;
+        self.cpu.stack_ptr = 0xFD;
+        self.cpu.status_reg = CpuFlags::from_bits_truncate(0b100100);
+        self.call(self.nsf.init_addr);
+        self.next_play = self.cpu.bus.cycles + self.play_period;
+        self.sample_clock = self.cpu.bus.cycles as f64;
+    }
+
+    // like a JSR from RETURN_ADDR
+    fn call(&mut self, addr: u16) {
+        let [lo, hi] = (RETURN_ADDR - 1).to_le_bytes();
+        for data in [hi, lo] {
+            self.cpu.write_mem(0x0100 + self.cpu.stack_ptr as u16, data);
+            self.cpu.stack_ptr = self.cpu.stack_ptr.wrapping_sub(1);
+        }
+        self.cpu.program_counter = addr;
+    }
+
+    fn run_until(&mut self, cycles: usize) {
+        while self.cpu.bus.cycles < cycles {
+            if self.cpu.program_counter != RETURN_ADDR {
+                if !self.cpu.step() {
+                    // BRK or a jam, give up on this call
+                    self.cpu.program_counter = RETURN_ADDR;
+                    self.cpu.stack_ptr = 0xFD;
+                }
+            } else if self.cpu.bus.cycles >= self.next_play {
+                self.next_play += self.play_period;
+                if self.next_play <= self.cpu.bus.cycles {
+                    // init or the last play ran long, don't try to catch up
+                    self.next_play = self.cpu.bus.cycles + self.play_period;
+                }
+                self.call(self.nsf.play_addr);
+            } else {
+                self.cpu.bus.tick(1);
+            }
+        }
+    }
+
+    pub fn render(&mut self, sample_rate: u32, samples: usize) -> Vec<f32> {
+        let cycles_per_sample = self.region.cpu_clock_rate() / sample_rate as f64;
+        let mut pcm = Vec::with_capacity(samples);
+        for _ in 0..samples {
+            self.sample_clock += cycles_per_sample;
+            self.run_until(self.sample_clock as usize);
+            pcm.push(self.cpu.bus.apu.output() + self.cpu.bus.expansion_audio());
+        }
+        pcm
+    }
+
+    // the whole track as given by its length, faded out at the end
+    pub fn render_track(&mut self, track: usize, sample_rate: u32) -> Vec<f32> {
+        self.start_track(track);
+        let info = &self.nsf.tracks[self.track];
+        let length_ms = info.length_ms.unwrap_or(DEFAULT_TRACK_MS);
+        let fade_ms = info.fade_ms.unwrap_or(DEFAULT_FADE_MS);
+        let to_samples = |ms: u32| (ms as u64 * sample_rate as u64 / 1000) as usize;
+        let fade = to_samples(fade_ms);
+
+        let mut pcm = self.render(sample_rate, to_samples(length_ms) + fade);
+        let start = pcm.len() - fade;
+        for (i, sample) in pcm[start..].iter_mut().enumerate() {
+            *sample *= 1.0 - (i + 1) as f32 / fade as f32;
+        }
+        pcm
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+
+    // a single song: init stores A at $6000, play counts its calls in $6001
+    pub fn test_nsf() -> Vec<u8> {
+        let mut raw = b"NESM\x1A\x01".to_vec();
+        raw.extend_from_slice(&[1, 1]);
+        raw.extend_from_slice(&[0x00, 0x80, 0x00, 0x80, 0x04, 0x80]);
+        let mut title = b"Test".to_vec();
+        title.resize(32, 0);
+        raw.extend_from_slice(&title);
+        raw.extend_from_slice(&[0; 64]);
+        raw.extend_from_slice(&NTSC_SPEED.to_le_bytes());
+        raw.extend_from_slice(&[0; 8]);
+        raw.extend_from_slice(&PAL_SPEED.to_le_bytes());
+        raw.extend_from_slice(&[0, 0, 0, 0, 0, 0]);
+        assert_eq!(raw.len(), HEADER_SIZE);
+        // $8000: STA $6000, RTS  $8004: INC $6001, RTS
+        raw.extend_from_slice(&[0x8D, 0x00, 0x60, 0x60, 0xEE, 0x01, 0x60, 0x60]);
+        raw
+    }
+
+    #[test]
+    fn test_parse_nsf() {
+        let nsf = Nsf::from_bytes(&test_nsf()).unwrap();
+        assert_eq!(nsf.load_addr, 0x8000);
+        assert_eq!(nsf.init_addr, 0x8000);
+        assert_eq!(nsf.play_addr, 0x8004);
+        assert_eq!(nsf.banks, None);
+        assert_eq!(nsf.timing, Timing::Ntsc);
+        assert!(nsf.chips.is_empty());
+        assert_eq!(nsf.title, "Test");
+        assert_eq!(nsf.tracks.len(), 1);
+        assert_eq!(nsf.first_track, 0);
+        assert_eq!(nsf.data.len(), 8);
+    }
+
+    fn chunk(id: &[u8], data: &[u8]) -> Vec<u8> {
+        let mut chunk = (data.len() as u32).to_le_bytes().to_vec();
+        chunk.extend_from_slice(id);
+        chunk.extend_from_slice(data);
+        chunk
+    }
+
+    #[test]
+    fn test_parse_nsfe() {
+        let mut raw = b"NSFE".to_vec();
+        raw.extend(chunk(b"INFO", &[0x00, 0x80, 0x00, 0x80, 0x04, 0x80, 0x01, 0x01, 2, 1]));
+        raw.extend(chunk(b"BANK", &[0, 1, 2, 3, 4, 5, 6, 7]));
+        raw.extend(chunk(b"DATA", &[0x60; 16]));
+        raw.extend(chunk(b"auth", b"Title\0Artist\0Copyright\0Ripper\0"));
+        raw.extend(chunk(b"tlbl", b"Intro\0Theme\0"));
+        raw.extend(chunk(b"time", &[&90_000i32.to_le_bytes()[..], &(-1i32).to_le_bytes()].concat()));
+        raw.extend(chunk(b"fade", &[&1_000i32.to_le_bytes()[..], &1_000i32.to_le_bytes()].concat()));
+        raw.extend(chunk(b"text", b"skipped"));
+        raw.extend(chunk(b"NEND", &[]));
+
+        let nsf = Nsf::from_bytes(&raw).unwrap();
+        assert_eq!(nsf.timing, Timing::Pal);
+        assert_eq!(nsf.chips, ExpansionChips::VRC6);
+        assert_eq!(nsf.banks, Some([0, 1, 2, 3, 4, 5, 6, 7]));
+        assert_eq!(nsf.first_track, 1);
+        assert_eq!((nsf.title.as_str(), nsf.artist.as_str()), ("Title", "Artist"));
+        assert_eq!(nsf.ripper.as_deref(), Some("Ripper"));
+        assert_eq!(
+            nsf.tracks,
+            vec![
+                TrackInfo { title: Some("Intro".to_string()), length_ms: Some(90_000), fade_ms: Some(1_000) },
+                TrackInfo { title: Some("Theme".to_string()), length_ms: None, fade_ms: Some(1_000) },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_play_is_called_at_the_nsf_rate() {
+        let mut player = NsfPlayer::new(Nsf::from_bytes(&test_nsf()).unwrap());
+        player.render(44100, 44100);
+        let calls = player.cpu.read_mem(0x6001);
+        assert!((59..=61).contains(&calls), "{} calls", calls);
+        assert_eq!(player.cpu.read_mem(0x6000), 0);
+    }
+
+    #[test]
+    fn test_render_expansion_audio() {
+        let mut nsf = Nsf::from_bytes(&test_nsf()).unwrap();
+        nsf.chips = ExpansionChips::VRC6;
+        nsf.play_addr = 0x8010;
+        nsf.tracks[0].length_ms = Some(100);
+        nsf.tracks[0].fade_ms = Some(50);
+        // init starts a VRC6 pulse at full volume, play does nothing
+        nsf.data = vec![
+            0xA9, 0x8F, 0x8D, 0x00, 0x90, 0xA9, 0x00, 0x8D, 0x01, 0x90, 0xA9, 0x81, 0x8D, 0x02, 0x90, 0x60, 0x60,
+        ];
+        let mut player = NsfPlayer::new(nsf);
+        let pcm = player.render_track(0, 48000);
+        assert_eq!(pcm.len(), 7200);
+        assert!(pcm[..4800].iter().any(|sample| *sample > 0.0));
+        assert_eq!(pcm[7199], 0.0);
+    }
+
+    #[test]
+    fn test_nsfe_errors() {
+        let mut raw = b"NSFE".to_vec();
+        raw.extend(chunk(b"DATA", &[0x60]));
+        assert!(matches!(Nsf::from_bytes(&raw), Err(NsfError::MissingChunk("INFO"))));
+        raw.extend(chunk(b"VRC8", &[]));
+        assert!(matches!(Nsf::from_bytes(&raw), Err(NsfError::UnknownChunk(_))));
+        assert!(matches!(Nsf::from_bytes(b"NESM"), Err(NsfError::BadMagic)));
+    }
+}
@@ -0,0 +1,520 @@
+use std::fmt;
+
+use crate::hash::{crc32, Crc32};
+
+// Soft patches applied to a rom image before it is parsed.
+//
+// IPS  "PATCH", records of a 24 bit offset and 16 bit size followed by the
+//      data (size 0 means a run of one repeated byte), "EOF" and an optional
+//      24 bit length to truncate the result to.
+// UPS  "UPS1", the source and target sizes, then runs of bytes XORed into
+//      the source, each after a skip count. Ends with three CRC-32s: source,
+//      target and the patch itself.
+// BPS  "BPS1", the source and target sizes and metadata, then a list of
+//      copy actions from the source, the patch or already written target.
+//      Same checksums at the end as UPS.
+//
+// UPS and BPS store numbers in a variable length encoding where every byte
+// holds 7 bits and a set high bit ends the number.
+
+#[derive(Debug, PartialEq)]
+pub enum PatchError {
+    UnknownFormat,
+    // the patch ends in the middle of a record
+    Truncated,
+    SourceSize { expected: usize, found: usize },
+    SourceChecksum { expected: u32, found: u32 },
+    TargetChecksum { expected: u32, found: u32 },
+    PatchChecksum { expected: u32, found: u32 },
+    // a copy reaches outside the source or target
+    BadOffset(usize),
+    // IPS can only address the first 16M
+    TooLarge(usize),
+}
+
+impl fmt::Display for PatchError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PatchError::UnknownFormat => write!(f, "Patch is not in IPS, UPS or BPS format"),
+            PatchError::Truncated => write!(f, "Patch is truncated"),
+            PatchError::SourceSize { expected, found } => {
+                write!(f, "Patch is for a {} byte rom, this one has {} bytes", expected, found)
+            }
+            PatchError::SourceChecksum { expected, found } => {
+                write!(f, "Patch is for a rom with CRC-32 {:08X}, this one has {:08X}", expected, found)
+            }
+            PatchError::TargetChecksum { expected, found } => {
+                write!(f, "Patched rom has CRC-32 {:08X} instead of {:08X}", found, expected)
+            }
+            PatchError::PatchChecksum { expected, found } => {
+                write!(f, "Patch is corrupted, CRC-32 {:08X} instead of {:08X}", found, expected)
+            }
+            PatchError::BadOffset(offset) => write!(f, "Patch refers to offset {} outside of the rom", offset),
+            PatchError::TooLarge(len) => write!(f, "{} bytes is too large for an IPS patch", len),
+        }
+    }
+}
+
+impl std::error::Error for PatchError {}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum PatchFormat {
+    Ips,
+    Ups,
+    Bps,
+}
+
+impl PatchFormat {
+    pub fn detect(patch: &[u8]) -> Option<PatchFormat> {
+        if patch.starts_with(b"PATCH") {
+            Some(PatchFormat::Ips)
+        } else if patch.starts_with(b"UPS1") {
+            Some(PatchFormat::Ups)
+        } else if patch.starts_with(b"BPS1") {
+            Some(PatchFormat::Bps)
+        } else {
+            None
+        }
+    }
+}
+
+pub fn apply(source: &[u8], patch: &[u8]) -> Result<Vec<u8>, PatchError> {
+    match PatchFormat::detect(patch) {
+        Some(PatchFormat::Ips) => apply_ips(source, patch),
+        Some(PatchFormat::Ups) => apply_ups(source, patch),
+        Some(PatchFormat::Bps) => apply_bps(source, patch),
+        None => Err(PatchError::UnknownFormat),
+    }
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8], pos: usize) -> Self {
+        Reader { data, pos }
+    }
+
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], PatchError> {
+        let end = self.pos.checked_add(len).filter(|end| *end <= self.data.len());
+        let end = end.ok_or(PatchError::Truncated)?;
+        let bytes = &self.data[self.pos..end];
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn byte(&mut self) -> Result<u8, PatchError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn be(&mut self, len: usize) -> Result<usize, PatchError> {
+        Ok(self.bytes(len)?.iter().fold(0, |value, b| value << 8 | *b as usize))
+    }
+
+    fn varint(&mut self) -> Result<usize, PatchError> {
+        let mut value = 0usize;
+        let mut shift = 1usize;
+        loop {
+            let x = self.byte()?;
+            value = value.wrapping_add((x & 0x7F) as usize * shift);
+            if x & 0x80 != 0 {
+                return Ok(value);
+            }
+            shift <<= 7;
+            value = value.wrapping_add(shift);
+        }
+    }
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: usize) {
+    loop {
+        let x = (value & 0x7F) as u8;
+        value >>= 7;
+        if value == 0 {
+            out.push(0x80 | x);
+            return;
+        }
+        out.push(x);
+        value -= 1;
+    }
+}
+
+fn read_u32_le(data: &[u8]) -> u32 {
+    u32::from_le_bytes([data[0], data[1], data[2], data[3]])
+}
+
+// UPS and BPS end with the CRC-32 of the source, the target and the patch
+struct Footer {
+    source: u32,
+    target: u32,
+}
+
+fn check_footer(source: &[u8], patch: &[u8]) -> Result<Footer, PatchError> {
+    if patch.len() < 16 {
+        return Err(PatchError::Truncated);
+    }
+    let footer = &patch[patch.len() - 12..];
+    let expected = read_u32_le(&footer[8..]);
+    let found = crc32(&patch[..patch.len() - 4]);
+    if expected != found {
+        return Err(PatchError::PatchChecksum { expected, found });
+    }
+    let footer = Footer {
+        source: read_u32_le(&footer[0..]),
+        target: read_u32_le(&footer[4..]),
+    };
+    let found = crc32(source);
+    if footer.source != found {
+        return Err(PatchError::SourceChecksum { expected: footer.source, found });
+    }
+    Ok(footer)
+}
+
+fn check_target(footer: &Footer, target: &[u8]) -> Result<(), PatchError> {
+    let found = crc32(target);
+    if footer.target != found {
+        return Err(PatchError::TargetChecksum { expected: footer.target, found });
+    }
+    Ok(())
+}
+
+pub fn apply_ips(source: &[u8], patch: &[u8]) -> Result<Vec<u8>, PatchError> {
+    if !patch.starts_with(b"PATCH") {
+        return Err(PatchError::UnknownFormat);
+    }
+    let mut target = source.to_vec();
+    let mut reader = Reader::new(patch, 5);
+    loop {
+        if reader.bytes(3)? == b"EOF" {
+            break;
+        }
+        reader.pos -= 3;
+        let offset = reader.be(3)?;
+        let size = reader.be(2)?;
+        let (len, data) = if size == 0 {
+            let len = reader.be(2)?;
+            (len, vec![reader.byte()?; len])
+        } else {
+            (size, reader.bytes(size)?.to_vec())
+        };
+        if target.len() < offset + len {
+            target.resize(offset + len, 0);
+        }
+        target[offset..offset + len].copy_from_slice(&data);
+    }
+    // Lunar IPS extension
+    if let Ok(len) = reader.be(3) {
+        target.truncate(len);
+    }
+    Ok(target)
+}
+
+pub fn apply_ups(source: &[u8], patch: &[u8]) -> Result<Vec<u8>, PatchError> {
+    if !patch.starts_with(b"UPS1") {
+        return Err(PatchError::UnknownFormat);
+    }
+    let footer = check_footer(source, patch)?;
+    let body = &patch[..patch.len() - 12];
+    let mut reader = Reader::new(body, 4);
+    let source_size = reader.varint()?;
+    let target_size = reader.varint()?;
+    if source_size != source.len() {
+        return Err(PatchError::SourceSize { expected: source_size, found: source.len() });
+    }
+
+    let mut target = source.to_vec();
+    target.resize(target_size, 0);
+    let mut pos = 0usize;
+    while reader.pos < body.len() {
+        pos = pos.checked_add(reader.varint()?).ok_or(PatchError::BadOffset(pos))?;
+        loop {
+            let x = reader.byte()?;
+            if x == 0 {
+                pos = pos.checked_add(1).ok_or(PatchError::BadOffset(pos))?;
+                break;
+            }
+            if pos >= target.len() {
+                return Err(PatchError::BadOffset(pos));
+            }
+            target[pos] ^= x;
+            pos += 1;
+        }
+    }
+    check_target(&footer, &target)?;
+    Ok(target)
+}
+
+pub fn apply_bps(source: &[u8], patch: &[u8]) -> Result<Vec<u8>, PatchError> {
+    if !patch.starts_with(b"BPS1") {
+        return Err(PatchError::UnknownFormat);
+    }
+    let footer = check_footer(source, patch)?;
+    let body = &patch[..patch.len() - 12];
+    let mut reader = Reader::new(body, 4);
+    let source_size = reader.varint()?;
+    let target_size = reader.varint()?;
+    if source_size != source.len() {
+        return Err(PatchError::SourceSize { expected: source_size, found: source.len() });
+    }
+    let metadata_size = reader.varint()?;
+    reader.bytes(metadata_size)?;
+
+    // the size comes from the patch, only reserve what is plausible
+    let mut target: Vec<u8> = Vec::with_capacity(target_size.min(source.len() + patch.len()));
+    let mut source_offset = 0usize;
+    let mut target_offset = 0usize;
+    while reader.pos < body.len() {
+        let data = reader.varint()?;
+        let len = (data >> 2) + 1;
+        let start = target.len();
+        let end = start.checked_add(len).ok_or(PatchError::BadOffset(start))?;
+        if end > target_size {
+            return Err(PatchError::BadOffset(end));
+        }
+        match data & 0b11 {
+            // source read, the source at the current target position
+            0 => {
+                let bytes = source.get(start..end).ok_or(PatchError::BadOffset(end))?;
+                target.extend_from_slice(bytes);
+            }
+            // target read, bytes stored in the patch
+            1 => target.extend_from_slice(reader.bytes(len)?),
+            // source copy, anywhere in the source
+            2 => {
+                source_offset = relative_offset(source_offset, reader.varint()?)?;
+                let end = source_offset.checked_add(len).ok_or(PatchError::BadOffset(source_offset))?;
+                let bytes = source.get(source_offset..end).ok_or(PatchError::BadOffset(end))?;
+                target.extend_from_slice(bytes);
+                source_offset = end;
+            }
+            // target copy, from what was written so far, may overlap
+            _ => {
+                target_offset = relative_offset(target_offset, reader.varint()?)?;
+                for _ in 0..len {
+                    let byte = *target.get(target_offset).ok_or(PatchError::BadOffset(target_offset))?;
+                    target.push(byte);
+                    target_offset += 1;
+                }
+            }
+        }
+    }
+    if target.len() != target_size {
+        return Err(PatchError::Truncated);
+    }
+    check_target(&footer, &target)?;
+    Ok(target)
+}
+
+// bit 0 is the sign, the rest the distance
+fn relative_offset(offset: usize, data: usize) -> Result<usize, PatchError> {
+    let distance = data >> 1;
+    let result = if data & 1 != 0 { offset.checked_sub(distance) } else { offset.checked_add(distance) };
+    result.ok_or(PatchError::BadOffset(offset))
+}
+
+pub fn create_ips(source: &[u8], target: &[u8]) -> Result<Vec<u8>, PatchError> {
+    if target.len() > 0xFF_FFFF {
+        return Err(PatchError::TooLarge(target.len()));
+    }
+    let mut patch = b"PATCH".to_vec();
+    let differs = |i: usize| source.get(i) != Some(&target[i]);
+    let mut i = 0;
+    while i < target.len() {
+        if !differs(i) {
+            i += 1;
+            continue;
+        }
+        // an offset that spells "EOF" would end the patch, start a byte earlier
+        let start = if i == 0x454F46 { i - 1 } else { i };
+        let mut end = i;
+        while end < target.len() && end - start < 0xFFFF && differs(end) {
+            end += 1;
+        }
+        patch.extend_from_slice(&(start as u32).to_be_bytes()[1..]);
+        patch.extend_from_slice(&((end - start) as u16).to_be_bytes());
+        patch.extend_from_slice(&target[start..end]);
+        i = end;
+    }
+    patch.extend_from_slice(b"EOF");
+    if target.len() < source.len() {
+        patch.extend_from_slice(&(target.len() as u32).to_be_bytes()[1..]);
+    }
+    Ok(patch)
+}
+
+// Only uses source reads for unchanged bytes and target reads for changed
+// ones, which is enough for translations that leave most of the rom alone.
+pub fn create_bps(source: &[u8], target: &[u8]) -> Vec<u8> {
+    let mut patch = b"BPS1".to_vec();
+    write_varint(&mut patch, source.len());
+    write_varint(&mut patch, target.len());
+    write_varint(&mut patch, 0);
+
+    let same = |i: usize| source.get(i) == Some(&target[i]);
+    let mut i = 0;
+    while i < target.len() {
+        let unchanged = same(i);
+        let mut end = i;
+        while end < target.len() && same(end) == unchanged {
+            end += 1;
+        }
+        let len = end - i;
+        write_varint(&mut patch, (len - 1) << 2 | if unchanged { 0 } else { 1 });
+        if !unchanged {
+            patch.extend_from_slice(&target[i..end]);
+        }
+        i = end;
+    }
+
+    patch.extend_from_slice(&crc32(source).to_le_bytes());
+    patch.extend_from_slice(&crc32(target).to_le_bytes());
+    let mut crc = Crc32::new();
+    crc.update(&patch);
+    patch.extend_from_slice(&crc.finish().to_le_bytes());
+    patch
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn images() -> (Vec<u8>, Vec<u8>) {
+        let source: Vec<u8> = (0..4096).map(|i| (i * 7) as u8).collect();
+        let mut target = source.clone();
+        target[10] = 0xAA;
+        target[11] = 0xBB;
+        target[2000..2100].fill(0x42);
+        target.extend_from_slice(&[1, 2, 3]);
+        (source, target)
+    }
+
+    fn ups_patch(source: &[u8], target: &[u8]) -> Vec<u8> {
+        let mut patch = b"UPS1".to_vec();
+        write_varint(&mut patch, source.len());
+        write_varint(&mut patch, target.len());
+        let mut last = 0;
+        let mut i = 0;
+        while i < target.len() {
+            let old = |i: usize| *source.get(i).unwrap_or(&0);
+            if old(i) == target[i] {
+                i += 1;
+                continue;
+            }
+            write_varint(&mut patch, i - last);
+            while i < target.len() && old(i) != target[i] {
+                patch.push(old(i) ^ target[i]);
+                i += 1;
+            }
+            patch.push(0);
+            i += 1;
+            last = i;
+        }
+        patch.extend_from_slice(&crc32(source).to_le_bytes());
+        patch.extend_from_slice(&crc32(target).to_le_bytes());
+        let crc = crc32(&patch);
+        patch.extend_from_slice(&crc.to_le_bytes());
+        patch
+    }
+
+    #[test]
+    fn test_varint_round_trip() {
+        for value in [0, 1, 127, 128, 255, 16511, 16512, 1 << 30, usize::MAX] {
+            let mut data = vec![];
+            write_varint(&mut data, value);
+            assert_eq!(Reader::new(&data, 0).varint().unwrap(), value);
+        }
+    }
+
+    #[test]
+    fn test_ips_round_trip() {
+        let (source, target) = images();
+        let patch = create_ips(&source, &target).unwrap();
+        assert_eq!(PatchFormat::detect(&patch), Some(PatchFormat::Ips));
+        assert_eq!(apply(&source, &patch).unwrap(), target);
+
+        // shrinking uses the truncate extension
+        let patch = create_ips(&source, &source[..1000]).unwrap();
+        assert_eq!(apply(&source, &patch).unwrap(), &source[..1000]);
+    }
+
+    #[test]
+    fn test_ips_rle_record() {
+        let mut patch = b"PATCH".to_vec();
+        patch.extend_from_slice(&[0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0x03, 0xEE]);
+        patch.extend_from_slice(b"EOF");
+        assert_eq!(apply(&[0; 6], &patch).unwrap(), vec![0, 0, 0xEE, 0xEE, 0xEE, 0]);
+    }
+
+    #[test]
+    fn test_bps_round_trip() {
+        let (source, target) = images();
+        let patch = create_bps(&source, &target);
+        assert_eq!(apply(&source, &patch).unwrap(), target);
+    }
+
+    #[test]
+    fn test_ups() {
+        let (source, target) = images();
+        let patch = ups_patch(&source, &target);
+        assert_eq!(apply(&source, &patch).unwrap(), target);
+    }
+
+    #[test]
+    fn test_checksums_are_validated() {
+        let (source, target) = images();
+        let mut other = source.clone();
+        other[0] ^= 1;
+
+        let patch = create_bps(&source, &target);
+        assert!(matches!(apply(&other, &patch), Err(PatchError::SourceChecksum { .. })));
+        let patch = ups_patch(&source, &target);
+        assert!(matches!(apply(&other, &patch), Err(PatchError::SourceChecksum { .. })));
+
+        let mut corrupted = create_bps(&source, &target);
+        corrupted[20] ^= 0xFF;
+        assert!(matches!(apply(&source, &corrupted), Err(PatchError::PatchChecksum { .. })));
+    }
+
+    #[test]
+    fn test_bad_patches() {
+        assert_eq!(apply(&[0; 4], b"NOT A PATCH"), Err(PatchError::UnknownFormat));
+        assert_eq!(apply(&[0; 4], b"PATCH\x00\x00\x01\x00\x05\x01"), Err(PatchError::Truncated));
+    }
+
+    // the checksums of a hand made patch body, the target one is never reached
+    fn with_footer(mut patch: Vec<u8>, source: &[u8]) -> Vec<u8> {
+        patch.extend_from_slice(&crc32(source).to_le_bytes());
+        patch.extend_from_slice(&0u32.to_le_bytes());
+        let crc = crc32(&patch);
+        patch.extend_from_slice(&crc.to_le_bytes());
+        patch
+    }
+
+    #[test]
+    fn test_oversized_varints() {
+        let source = [0; 4];
+
+        // a UPS skip to the end of the address space
+        let mut patch = b"UPS1".to_vec();
+        write_varint(&mut patch, 4);
+        write_varint(&mut patch, 4);
+        write_varint(&mut patch, usize::MAX);
+        patch.push(0);
+        assert!(matches!(apply(&source, &with_footer(patch, &source)), Err(PatchError::BadOffset(_))));
+
+        // BPS actions longer than any target or source
+        for (target_size, action, offset) in [(4, usize::MAX, None), (usize::MAX, usize::MAX - 1, Some(usize::MAX - 1))] {
+            let mut patch = b"BPS1".to_vec();
+            write_varint(&mut patch, 4);
+            write_varint(&mut patch, target_size);
+            write_varint(&mut patch, 0);
+            write_varint(&mut patch, action);
+            if let Some(offset) = offset {
+                write_varint(&mut patch, offset);
+            }
+            assert!(matches!(apply(&source, &with_footer(patch, &source)), Err(PatchError::BadOffset(_))));
+        }
+    }
+}
@@ -0,0 +1,172 @@
+use sdl2::audio::{AudioCallback, AudioSpecDesired};
+use sdl2::event::Event;
+use sdl2::keyboard::{Keycode, Mod, Scancode};
+use sdl2::mouse::MouseButton;
+use sdl2::pixels::PixelFormatEnum;
+use sdl2::EventPump;
+
+use crate::apu::audio::{dynamic_rate_control, AudioOutput};
+use crate::apu::ring_buffer::{audio_ring, AudioConsumer};
+use crate::bus::Bus;
+use crate::cpu::CPU;
+use crate::input::joypad::JoypadButton;
+use crate::input::InputPort;
+use crate::ppu::frame::Frame;
+
+// A window to play in. Player 1 is on the keyboard, the mouse aims and
+// fires a light gun in either port.
+//
+//  arrows  D-pad      X  A      Z  B      Enter  Start      Right Shift  Select
+
+const SCALE: u32 = 3;
+
+const SAMPLE_RATE: i32 = 48_000;
+// samples the callback is asked for at a time
+const AUDIO_CHUNK: u16 = 1024;
+// about 85ms at 48kHz, rate control keeps it around half full
+const AUDIO_BUFFER: usize = 4096;
+
+const KEYMAP: [(Scancode, JoypadButton); 8] = [
+    (Scancode::Up, JoypadButton::UP),
+    (Scancode::Down, JoypadButton::DOWN),
+    (Scancode::Left, JoypadButton::LEFT),
+    (Scancode::Right, JoypadButton::RIGHT),
+    (Scancode::X, JoypadButton::BUTTON_A),
+    (Scancode::Z, JoypadButton::BUTTON_B),
+    (Scancode::Return, JoypadButton::START),
+    (Scancode::RShift, JoypadButton::SELECT),
+];
+
+const CHANNEL_KEYS: [Keycode; 12] = [
+    Keycode::F1, Keycode::F2, Keycode::F3, Keycode::F4, Keycode::F5, Keycode::F6,
+    Keycode::F7, Keycode::F8, Keycode::F9, Keycode::F10, Keycode::F11, Keycode::F12,
+];
+
+// F1-F12 mute a sound channel, with shift they solo it instead. [ and ]
+// turn the volume of the channel last picked down and up.
+pub(crate) fn handle_channel_keys(bus: &mut Bus, key: Keycode, keymod: Mod, selected: &mut usize) {
+    if let Some(channel) = CHANNEL_KEYS.iter().position(|k| *k == key) {
+        if channel >= bus.audio_channels().len() {
+            return;
+        }
+        *selected = channel;
+        if keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD) {
+            bus.set_channel_solo(channel, !bus.channel_solo(channel));
+        } else {
+            bus.set_channel_muted(channel, !bus.channel_muted(channel));
+        }
+        return;
+    }
+    let volume = bus.channel_volume(*selected);
+    match key {
+        Keycode::LeftBracket => bus.set_channel_volume(*selected, volume - 0.1),
+        Keycode::RightBracket => bus.set_channel_volume(*selected, volume + 0.1),
+        _ => {}
+    }
+}
+
+// the SDL audio thread's end of the ring, short reads play silence
+struct RingPlayback {
+    consumer: AudioConsumer,
+}
+
+impl AudioCallback for RingPlayback {
+    type Channel = f32;
+
+    fn callback(&mut self, out: &mut [f32]) {
+        let count = self.consumer.pop_into(out);
+        out[count..].fill(0.0);
+    }
+}
+
+// false once the window is closed
+fn handle_input(bus: &mut Bus, event_pump: &mut EventPump, selected_channel: &mut usize) -> bool {
+    for event in event_pump.poll_iter() {
+        match event {
+            Event::Quit { .. } | Event::KeyDown { keycode: Some(Keycode::Escape), .. } => return false,
+            Event::KeyDown { keycode: Some(key), keymod, .. } => {
+                handle_channel_keys(bus, key, keymod, selected_channel);
+            }
+            _ => {}
+        }
+    }
+
+    let keyboard = event_pump.keyboard_state();
+    let buttons = KEYMAP
+        .iter()
+        .filter(|(scancode, _)| keyboard.is_scancode_pressed(*scancode))
+        .fold(JoypadButton::empty(), |buttons, (_, button)| buttons | *button);
+    bus.set_joypad_buttons(0, buttons);
+
+    let mouse = event_pump.mouse_state();
+    let (x, y) = (mouse.x() / SCALE as i32, mouse.y() / SCALE as i32);
+    let aim = if (0..Frame::WIDTH as i32).contains(&x) && (0..Frame::HEIGHT as i32).contains(&y) {
+        Some((x as usize, y as usize))
+    } else {
+        None
+    };
+    for port in [InputPort::Port1, InputPort::Port2] {
+        if let Some(device) = bus.input_device(port) {
+            let mut device = device.borrow_mut();
+            device.set_aim(aim);
+            device.set_trigger(mouse.is_mouse_button_pressed(MouseButton::Left));
+        }
+    }
+    true
+}
+
+pub fn play(mut bus: Bus) {
+    let sdl_context = sdl2::init().unwrap();
+    let audio_subsystem = sdl_context.audio().unwrap();
+    let spec = AudioSpecDesired {
+        freq: Some(SAMPLE_RATE),
+        channels: Some(1),
+        samples: Some(AUDIO_CHUNK),
+    };
+    let (producer, consumer) = audio_ring(AUDIO_BUFFER);
+    // the game still runs without a sound device, only silent
+    let audio_device = match audio_subsystem.open_playback(None, &spec, |_| RingPlayback { consumer }) {
+        Ok(device) => {
+            let mut output = AudioOutput::new(bus.region(), device.spec().freq as u32, producer);
+            output.set_rate_control(Some(dynamic_rate_control(0.005)));
+            bus.set_audio_output(Some(output));
+            device.resume();
+            Some(device)
+        }
+        Err(e) => {
+            println!("No sound: {}", e);
+            None
+        }
+    };
+    let video_subsystem = sdl_context.video().unwrap();
+    let window = video_subsystem
+        .window("NES", Frame::WIDTH as u32 * SCALE, Frame::HEIGHT as u32 * SCALE)
+        .position_centered()
+        .build().unwrap();
+
+    let mut canvas = window.into_canvas().present_vsync().build().unwrap();
+    let mut event_pump = sdl_context.event_pump().unwrap();
+    canvas.set_scale(SCALE as f32, SCALE as f32).unwrap();
+
+    let creator = canvas.texture_creator();
+    let mut texture = creator
+        .create_texture_target(PixelFormatEnum::RGB24, Frame::WIDTH as u32, Frame::HEIGHT as u32).unwrap();
+
+    let mut cpu = CPU::new(bus);
+    cpu.reset();
+    let mut selected_channel = 0;
+
+    'running: while handle_input(&mut cpu.bus, &mut event_pump, &mut selected_channel) {
+        let frame = cpu.bus.frame_count();
+        while cpu.bus.frame_count() == frame {
+            if !cpu.step() {
+                break 'running;
+            }
+        }
+        texture.update(None, &cpu.bus.ppu.frame.data, Frame::WIDTH * 3).unwrap();
+        canvas.copy(&texture, None, None).unwrap();
+        canvas.present();
+    }
+    cpu.bus.flush_save_ram();
+    drop(audio_device);
+}
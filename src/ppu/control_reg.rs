@@ -46,6 +46,27 @@ bitflags::bitflags! {
     pub fn generate_vblank_nmi(&self) -> bool {
         return self.contains(ControlRegister::GENERATE_NMI);
     }
+
+    pub fn nametable_addr(&self) -> u16 {
+        match self.bits & 0b11 {
+            0 => 0x2000,
+            1 => 0x2400,
+            2 => 0x2800,
+            _ => 0x2C00,
+        }
+    }
+
+    pub fn bknd_pattern_addr(&self) -> u16 {
+        if self.contains(ControlRegister::BACKROUND_PATTERN_ADDR) { 0x1000 } else { 0 }
+    }
+
+    pub fn sprt_pattern_addr(&self) -> u16 {
+        if self.contains(ControlRegister::SPRITE_PATTERN_ADDR) { 0x1000 } else { 0 }
+    }
+
+    pub fn sprite_size(&self) -> u8 {
+        if self.contains(ControlRegister::SPRITE_SIZE) { 16 } else { 8 }
+    }
  
     pub fn update(&mut self, data: u8) {
         self.bits = data;
@@ -22,7 +22,7 @@ bitflags::bitflags! {
         const SHOW_BACKGROUND = 0b0000_1000;
         const SHOW_SPRITES = 0b0001_0000;
         const EMPH_RED = 0b0010_0000;
-        const EMPH_GREEN = 0b010_0000;
+        const EMPH_GREEN = 0b0100_0000;
         const EMPH_BLUE = 0b1000_0000;
     }
 }
@@ -54,4 +54,7 @@ impl Mask{
     pub fn set_mltpl(&mut self, m: u8){
         self.bits = m;
     }
+    pub fn rendering_enabled(&self) -> bool{
+        self.intersects(Mask::SHOW_BACKGROUND | Mask::SHOW_SPRITES)
+    }
 }
\ No newline at end of file
@@ -1,6 +1,9 @@
 pub mod nes_ppu;
+pub mod frame;
+pub mod palette;
 mod addr_reg;
 mod control_reg;
 mod mask;
 mod status;
-mod scroll;
\ No newline at end of file
+mod scroll;
+mod render;
\ No newline at end of file
@@ -1,26 +1,38 @@
 use crate::cartridge::Mirroring;
+use crate::mapper::MapperRef;
 use crate::ppu::addr_reg::AddrRegister;
 use crate::ppu::control_reg::ControlRegister;
 use crate::ppu::mask::Mask;
 use crate::ppu::status::Status;
 use crate::ppu::scroll::ScrollReg;
+use crate::ppu::frame::Frame;
+use crate::ppu::palette::{self, SYSTEM_PALETTE};
+use crate::region::Region;
+use crate::vs::VsPpu;
 
 pub struct PPU{
-    pub chr_rom: Vec<u8>,
+    pub cartridge: MapperRef,
     pub palette_table: [u8; 32],
     pub vram: [u8; 2048],
     pub oam_data: [u8; 256],
-    pub mirroring: Mirroring,
     pub ctrl: ControlRegister,
     addr: AddrRegister,
-    status: Status,
+    pub(super) status: Status,
     pub mask: Mask,
-    scroll: ScrollReg,
+    pub(super) scroll: ScrollReg,
     data_buf: u8,
     oam_addr: u8,
 
     pub scanline: u16,
     pub cycles: usize,
+    pub nmi_interrupt: Option<u8>,
+    pub frame: Frame,
+    pub(super) palette: &'static [(u8, u8, u8); 64],
+    status_id: Option<u8>,
+    swap_ctrl_mask: bool,
+    region: Region,
+    // the current line was drawn ahead of time for the light sensor
+    line_drawn: bool,
 }
 
 //------------------------------------------------| 0xFFFF
@@ -38,11 +50,14 @@ pub struct PPU{
 //------------------------------------------------| 0x0000
 
 
+// how long and how bright a pixel has to be for the Zapper
+const LIGHT_SENSE_LINES: usize = 20;
+const LIGHT_SENSE_LUMA: usize = 0xC0;
+
 impl PPU {
-    pub fn new(chr_rom: Vec<u8>, mirroring: Mirroring) -> Self {
+    pub fn new(cartridge: MapperRef) -> Self {
         PPU {
-            chr_rom: chr_rom,
-            mirroring: mirroring,
+            cartridge,
             vram: [0; 2048],
             oam_data: [0; 64 * 4],
             palette_table: [0; 32],
@@ -55,15 +70,50 @@ impl PPU {
             oam_addr: 0,
             cycles: 0,
             scanline:0,
+            nmi_interrupt: None,
+            frame: Frame::new(),
+            palette: &SYSTEM_PALETTE,
+            status_id: None,
+            swap_ctrl_mask: false,
+            region: Region::Ntsc,
+            line_drawn: false,
         }
     }
 
+    // arcade RGB PPUs in place of the 2C02
+    pub fn set_vs_ppu(&mut self, ppu: VsPpu) {
+        self.palette = match ppu {
+            VsPpu::Rp2c04_0001 => &palette::RP2C04_0001_PALETTE,
+            VsPpu::Rp2c04_0002 => &palette::RP2C04_0002_PALETTE,
+            VsPpu::Rp2c04_0003 => &palette::RP2C04_0003_PALETTE,
+            VsPpu::Rp2c04_0004 => &palette::RP2C04_0004_PALETTE,
+            _ => &palette::RGB_PALETTE,
+        };
+        self.status_id = ppu.status_id();
+        self.swap_ctrl_mask = ppu.swaps_ctrl_mask();
+    }
+
+    // the 2C05 has $2000 and $2001 the other way round
+    pub fn swaps_ctrl_mask(&self) -> bool {
+        self.swap_ctrl_mask
+    }
+
+    // the number of lines and where vblank starts
+    pub fn set_region(&mut self, region: Region) {
+        self.region = region;
+    }
+
     pub fn write_to_ppu_addr(&mut self, value: u8) {
         self.addr.update(value);
     }
 
     pub fn write_to_ctrl(&mut self, value: u8) {
+        let before_nmi_status = self.ctrl.generate_vblank_nmi();
         self.ctrl.update(value);
+        //enabling NMI during vblank triggers it right away
+        if !before_nmi_status && self.ctrl.generate_vblank_nmi() && self.status.contains(Status::VBLANK) {
+            self.nmi_interrupt = Some(1);
+        }
     }
 
     fn increment_vram_addr(&mut self) {
@@ -74,27 +124,36 @@ impl PPU {
         let mirrored_vram = addr & 0b10111111111111; // mirror down 0x3000-0x3eff to 0x2000 - 0x2eff
         let vram_index = mirrored_vram - 0x2000; // to vram vector
         let name_table = vram_index / 0x400; // to the name table index
-        match (&self.mirroring, name_table) {
+        match (self.cartridge.borrow().mirroring(), name_table) {
             (Mirroring::VERTICAL, 2) | (Mirroring::VERTICAL, 3) => vram_index - 0x800,
             (Mirroring::HORIZONTAL, 2) => vram_index - 0x400,
             (Mirroring::HORIZONTAL, 1) => vram_index - 0x400,
             (Mirroring::HORIZONTAL, 3) => vram_index - 0x800,
+            (Mirroring::ONE_SCREEN_LOWER, _) => vram_index % 0x400,
+            (Mirroring::ONE_SCREEN_UPPER, _) => vram_index % 0x400 + 0x400,
             _ => vram_index,
         }
     }
  
+    pub(super) fn read_nametable(&mut self, addr: u16) -> u8 {
+        match self.cartridge.borrow_mut().read_nametable(addr) {
+            Some(data) => data,
+            None => self.vram[self.mirror_vram_addr(addr) as usize],
+        }
+    }
+
     pub fn read_data(&mut self) -> u8 {
         let addr = self.addr.get();
         self.increment_vram_addr();
         match addr {
             0..=0x1fff => {
                 let result = self.data_buf;
-                self.data_buf = self.chr_rom[addr as usize];
+                self.data_buf = self.cartridge.borrow_mut().read_chr(addr);
                 result
             }
             0x2000..=0x2fff => {
                 let result = self.data_buf;
-                self.data_buf = self.vram[self.mirror_vram_addr(addr) as usize];
+                self.data_buf = self.read_nametable(addr);
                 result
             }
             0x3000..=0x3eff => panic!("addr space 0x3000..0x3eff is not expected to be used, requested = {} ", addr),
@@ -116,11 +175,11 @@ impl PPU {
         let addr = self.addr.get();
         self.increment_vram_addr();
         match addr {
-            0..=0x1fff => {
-                panic!("Unexpected write to chr ROM {:x}",addr);
-            }
+            0..=0x1fff => self.cartridge.borrow_mut().write_chr(addr, data),
             0x2000..=0x2fff => {
-                self.vram[self.mirror_vram_addr(addr) as usize] = data;
+                if !self.cartridge.borrow_mut().write_nametable(addr, data) {
+                    self.vram[self.mirror_vram_addr(addr) as usize] = data;
+                }
             }
             0x3000..=0x3eff => unimplemented!("addr {} shouldn't be used in reallity", addr),
 
@@ -137,7 +196,10 @@ impl PPU {
     }
 
     pub fn read_status(&mut self) -> u8{
-        let r = self.status.bits();
+        let r = match self.status_id {
+            Some(id) => (self.status.bits() & 0xE0) | id,
+            None => self.status.bits(),
+        };
         self.addr.reset_latch();
         self.scroll.reset_latch();
         self.status.clear_vblank();
@@ -171,23 +233,61 @@ impl PPU {
     pub fn tick(&mut self, cycles: u8) -> bool {
         self.cycles += cycles as usize;
         if self.cycles >= 341 {
+            if self.scanline < 240 && !self.line_drawn {
+                self.render_scanline(self.scanline as usize);
+            }
+            self.line_drawn = false;
             self.cycles = self.cycles - 341;
             self.scanline += 1;
  
-            if self.scanline == 241 {
+            if self.scanline == self.region.vblank_line() {
+                self.status.set_vblank(true);
                 if self.ctrl.generate_vblank_nmi() {
-                    self.status.set_vblank(true);
-                    todo!("Should trigger NMI interrupt")
+                    self.nmi_interrupt = Some(1);
                 }
             }
  
-            if self.scanline >= 262 {
+            if self.scanline >= self.region.scanlines() {
                 self.scanline = 0;
+                self.nmi_interrupt = None;
                 self.status.clear_vblank();
+                self.status.remove(Status::SPRITE_0_HIT | Status::SPRITE_OVERFLOW);
                 return true;
             }
         }
         return false;
     }
+
+    // Whether a light gun aimed at x, y sees light now. A pixel counts as
+    // lit once the beam has passed its dot and for a few lines after while
+    // the photodiode still reacts, if it is close to white. Lines are drawn
+    // when the beam finishes them, so the current one is drawn early when
+    // the beam is already past x.
+    pub fn light_at(&mut self, x: usize, y: usize) -> bool {
+        if x >= Frame::WIDTH || y >= Frame::HEIGHT {
+            return false;
+        }
+        let line = self.scanline as usize;
+        if line < y || line - y > LIGHT_SENSE_LINES {
+            return false;
+        }
+        if line == y {
+            // pixel x comes out at dot x + 1
+            if self.cycles <= x + 1 {
+                return false;
+            }
+            if !self.line_drawn {
+                self.render_scanline(y);
+                self.line_drawn = true;
+            }
+        }
+        let (r, g, b) = self.frame.get_pixel(x, y);
+        let luma = (r as usize * 299 + g as usize * 587 + b as usize * 114) / 1000;
+        luma >= LIGHT_SENSE_LUMA
+    }
+
+    pub fn poll_nmi_interrupt(&mut self) -> Option<u8> {
+        self.nmi_interrupt.take()
+    }
 }
  
\ No newline at end of file
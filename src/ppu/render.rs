@@ -0,0 +1,182 @@
+use crate::ppu::control_reg::ControlRegister;
+use crate::ppu::frame::Frame;
+use crate::ppu::mask::Mask;
+use crate::ppu::nes_ppu::PPU;
+use crate::ppu::status::Status;
+
+// Scanline renderer.
+//
+// A whole scanline is drawn once the PPU has finished it. The pattern table
+// is read through the cartridge in the same order as the hardware does:
+// the background tiles from left to right, followed by the sprite patterns.
+// Mappers like MMC2/MMC4 watch these reads to switch banks mid-frame.
+
+struct SpritePixel {
+    color: u8,
+    behind_background: bool,
+    sprite_zero: bool,
+}
+
+impl PPU {
+    pub(super) fn render_scanline(&mut self, line: usize) {
+        let show_bg = self.mask.contains(Mask::SHOW_BACKGROUND);
+        let show_sprites = self.mask.contains(Mask::SHOW_SPRITES);
+        if !show_bg && !show_sprites {
+            let backdrop = self.color(self.palette_table[0]);
+            for x in 0..Frame::WIDTH {
+                self.frame.set_pixel(x, line, backdrop);
+            }
+            return;
+        }
+
+        let background = if show_bg { self.background_line(line) } else { [None; 256] };
+        let sprites = self.sprite_line(line);
+
+        for x in 0..Frame::WIDTH {
+            let mut bg = background[x];
+            if x < 8 && !self.mask.contains(Mask::BACKGROUND_LEFT) {
+                bg = None;
+            }
+            let mut sprite = if show_sprites { sprites[x].as_ref() } else { None };
+            if x < 8 && !self.mask.contains(Mask::SPRITES_LEFT) {
+                sprite = None;
+            }
+
+            if let (Some(_), Some(sp)) = (bg, sprite) {
+                if sp.sprite_zero && x != 255 {
+                    self.status.insert(Status::SPRITE_0_HIT);
+                }
+            }
+
+            let palette_idx = match (bg, sprite) {
+                (_, Some(sp)) if bg.is_none() || !sp.behind_background => sp.color,
+                (Some(color), _) => color,
+                _ => 0,
+            };
+            let rgb = self.color(self.palette_table[palette_idx as usize]);
+            self.frame.set_pixel(x, line, rgb);
+        }
+    }
+
+    fn color(&self, value: u8) -> (u8, u8, u8) {
+        let value = if self.mask.contains(Mask::GREY_SCALE) { value & 0x30 } else { value & 0x3F };
+        self.palette[value as usize]
+    }
+
+    // palette index (0-15) of every opaque background pixel on the line
+    fn background_line(&mut self, line: usize) -> [Option<u8>; 256] {
+        let mut pixels = [None; 256];
+        let base = self.ctrl.nametable_addr() - 0x2000;
+        let scroll_x = self.scroll.x() as usize + (base as usize & 0x400) / 4;
+        let scroll_y = (self.scroll.y() as usize + if base & 0x800 != 0 { 240 } else { 0 } + line) % 480;
+        let pattern_table = self.ctrl.bknd_pattern_addr();
+
+        let fine_x = scroll_x % 8;
+        let row = (scroll_y % 240) / 8;
+        let fine_y = (scroll_y % 240) % 8;
+        let bottom_half = scroll_y >= 240;
+
+        for tile in 0..33 {
+            let world_col = (scroll_x / 8 + tile) % 64;
+            let col = world_col % 32;
+            let nametable = 0x2000
+                + if world_col >= 32 { 0x400 } else { 0 }
+                + if bottom_half { 0x800 } else { 0 };
+
+            let tile_idx = self.read_nametable(nametable + (row * 32 + col) as u16);
+            let attr_addr = nametable + 0x3C0 + ((row / 4) * 8 + col / 4) as u16;
+            let attr = self.read_nametable(attr_addr);
+            let shift = ((row % 4) / 2) * 4 + ((col % 4) / 2) * 2;
+            let palette = (attr >> shift) & 0b11;
+
+            let addr = pattern_table + tile_idx as u16 * 16 + fine_y as u16;
+            let lo = self.cartridge.borrow_mut().read_chr(addr);
+            let hi = self.cartridge.borrow_mut().read_chr(addr + 8);
+
+            for bit in 0..8 {
+                let x = (tile * 8 + bit) as isize - fine_x as isize;
+                if !(0..256).contains(&x) {
+                    continue;
+                }
+                let value = ((hi >> (7 - bit)) & 1) << 1 | ((lo >> (7 - bit)) & 1);
+                if value != 0 {
+                    pixels[x as usize] = Some(palette * 4 + value);
+                }
+            }
+        }
+        pixels
+    }
+
+    fn sprite_line(&mut self, line: usize) -> Vec<Option<SpritePixel>> {
+        let mut pixels: Vec<Option<SpritePixel>> = (0..256).map(|_| None).collect();
+        let height = self.ctrl.sprite_size() as usize;
+
+        // sprite evaluation, the first 8 sprites in OAM order are drawn
+        let mut found = Vec::with_capacity(8);
+        for i in 0..64 {
+            let top = self.oam_data[i * 4] as usize + 1;
+            if line >= top && line < top + height {
+                if found.len() == 8 {
+                    self.status.insert(Status::SPRITE_OVERFLOW);
+                    break;
+                }
+                found.push(i);
+            }
+        }
+
+        for slot in 0..8 {
+            let i = match found.get(slot) {
+                Some(i) => *i,
+                None => {
+                    // unused slots still fetch tile $FF
+                    let addr = self.sprite_pattern_addr(0xFF, 0);
+                    self.cartridge.borrow_mut().read_chr(addr);
+                    self.cartridge.borrow_mut().read_chr(addr + 8);
+                    continue;
+                }
+            };
+            let top = self.oam_data[i * 4] as usize + 1;
+            let tile = self.oam_data[i * 4 + 1];
+            let attributes = self.oam_data[i * 4 + 2];
+            let left = self.oam_data[i * 4 + 3] as usize;
+
+            let flip_vertical = attributes & 0x80 != 0;
+            let flip_horizontal = attributes & 0x40 != 0;
+            let mut row = line - top;
+            if flip_vertical {
+                row = height - 1 - row;
+            }
+
+            let addr = self.sprite_pattern_addr(tile, row);
+            let lo = self.cartridge.borrow_mut().read_chr(addr);
+            let hi = self.cartridge.borrow_mut().read_chr(addr + 8);
+
+            for bit in 0..8 {
+                let x = left + bit;
+                if x >= 256 || pixels[x].is_some() {
+                    continue;
+                }
+                let shift = if flip_horizontal { bit } else { 7 - bit };
+                let value = ((hi >> shift) & 1) << 1 | ((lo >> shift) & 1);
+                if value != 0 {
+                    pixels[x] = Some(SpritePixel {
+                        color: 0x10 + (attributes & 0b11) * 4 + value,
+                        behind_background: attributes & 0x20 != 0,
+                        sprite_zero: i == 0,
+                    });
+                }
+            }
+        }
+        pixels
+    }
+
+    fn sprite_pattern_addr(&self, tile: u8, row: usize) -> u16 {
+        if self.ctrl.contains(ControlRegister::SPRITE_SIZE) {
+            let table = if tile & 1 != 0 { 0x1000 } else { 0 };
+            let tile = (tile & 0xFE) as u16 + if row >= 8 { 1 } else { 0 };
+            table + tile * 16 + (row % 8) as u16
+        } else {
+            self.ctrl.sprt_pattern_addr() + tile as u16 * 16 + row as u16
+        }
+    }
+}
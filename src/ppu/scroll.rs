@@ -28,4 +28,12 @@ impl ScrollReg{
     pub fn reset_latch(&mut self){
         self.hrz_pt = true;
     }
+
+    pub fn x(&self) -> u8{
+        self.value.0
+    }
+
+    pub fn y(&self) -> u8{
+        self.value.1
+    }
 }
\ No newline at end of file
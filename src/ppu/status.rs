@@ -32,7 +32,7 @@ bitflags::bitflags! {
         const UNUSED3 = 0b0000_1000;
         const UNUSED4 = 0b0001_0000;
         const SPRITE_OVERFLOW = 0b0010_0000;
-        const SPRITE_0_HIT = 0b010_0000;
+        const SPRITE_0_HIT = 0b0100_0000;
         const VBLANK = 0b1000_0000;
     }
 }
@@ -0,0 +1,185 @@
+use crate::cartridge::{Rom, Timing};
+
+// Console timing profiles. Everything runs off one master clock, the CPU and
+// the PPU divide it down by different amounts:
+//
+//          master clock   CPU   PPU   lines  vblank from
+//  NTSC    21.477272 MHz  /12   /4    262    241
+//  PAL     26.601712 MHz  /16   /5    312    241
+//  Dendy   26.601712 MHz  /15   /5    312    291
+//
+// So NTSC and Dendy run 3 dots per CPU cycle and PAL 3.2. The Dendy keeps
+// the PAL frame but starts vblank 50 lines late so NTSC games see about the
+// same number of CPU cycles in it, and its APU uses the NTSC tables.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Region {
+    Ntsc,
+    Pal,
+    Dendy,
+}
+
+const NTSC_NOISE_PERIODS: [u16; 16] = [
+    4, 8, 16, 32, 64, 96, 128, 160, 202, 254, 380, 508, 762, 1016, 2034, 4068,
+];
+const PAL_NOISE_PERIODS: [u16; 16] = [
+    4, 8, 14, 30, 60, 88, 118, 148, 188, 236, 354, 472, 708, 944, 1890, 3778,
+];
+
+const NTSC_DMC_RATES: [u16; 16] = [
+    428, 380, 340, 320, 286, 254, 226, 214, 190, 160, 142, 128, 106, 84, 72, 54,
+];
+const PAL_DMC_RATES: [u16; 16] = [
+    398, 354, 316, 298, 276, 236, 210, 198, 176, 148, 132, 118, 98, 78, 66, 50,
+];
+
+// CPU cycles from the start of the frame sequence to its steps, the 4-step
+// sequence wraps one cycle after the fourth and the 5-step one after the fifth
+const NTSC_FRAME_STEPS: [usize; 5] = [7457, 14913, 22371, 29829, 37281];
+const PAL_FRAME_STEPS: [usize; 5] = [8313, 16627, 24939, 33253, 41565];
+
+impl Region {
+    pub fn from_timing(timing: Timing) -> Region {
+        match timing {
+            Timing::Pal => Region::Pal,
+            Timing::Dendy => Region::Dendy,
+            // multi-region games run fine on the NTSC timing
+            Timing::Ntsc | Timing::MultiRegion => Region::Ntsc,
+        }
+    }
+
+    // from the NES 2.0 header, or the ROM database when it had an entry
+    pub fn of(rom: &Rom) -> Region {
+        Region::from_timing(rom.timing)
+    }
+
+    pub fn master_clock_rate(&self) -> f64 {
+        match self {
+            Region::Ntsc => 236_250_000.0 / 11.0,
+            Region::Pal | Region::Dendy => 26_601_712.0,
+        }
+    }
+
+    // master clocks per CPU cycle
+    pub fn cpu_divider(&self) -> usize {
+        match self {
+            Region::Ntsc => 12,
+            Region::Pal => 16,
+            Region::Dendy => 15,
+        }
+    }
+
+    // master clocks per PPU dot
+    pub fn ppu_divider(&self) -> usize {
+        match self {
+            Region::Ntsc => 4,
+            Region::Pal | Region::Dendy => 5,
+        }
+    }
+
+    pub fn cpu_clock_rate(&self) -> f64 {
+        self.master_clock_rate() / self.cpu_divider() as f64
+    }
+
+    pub fn scanlines(&self) -> u16 {
+        match self {
+            Region::Ntsc => 262,
+            Region::Pal | Region::Dendy => 312,
+        }
+    }
+
+    // the line the vblank flag and NMI come on
+    pub fn vblank_line(&self) -> u16 {
+        match self {
+            Region::Ntsc | Region::Pal => 241,
+            Region::Dendy => 291,
+        }
+    }
+
+    pub fn frame_rate(&self) -> f64 {
+        self.master_clock_rate() / self.ppu_divider() as f64 / (341 * self.scanlines() as usize) as f64
+    }
+
+    // noise channel periods in CPU cycles
+    pub fn noise_periods(&self) -> &'static [u16; 16] {
+        match self {
+            Region::Ntsc | Region::Dendy => &NTSC_NOISE_PERIODS,
+            Region::Pal => &PAL_NOISE_PERIODS,
+        }
+    }
+
+    // APU frame counter steps in CPU cycles
+    pub fn frame_steps(&self) -> &'static [usize; 5] {
+        match self {
+            Region::Ntsc | Region::Dendy => &NTSC_FRAME_STEPS,
+            Region::Pal => &PAL_FRAME_STEPS,
+        }
+    }
+
+    // DMC output rates in CPU cycles
+    pub fn dmc_rates(&self) -> &'static [u16; 16] {
+        match self {
+            Region::Ntsc | Region::Dendy => &NTSC_DMC_RATES,
+            Region::Pal => &PAL_DMC_RATES,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bus::Bus;
+    use crate::cartridge::test::test_rom;
+
+    #[test]
+    fn test_region_from_rom() {
+        let mut rom = test_rom();
+        assert_eq!(Region::of(&rom), Region::Ntsc);
+        rom.timing = Timing::Dendy;
+        assert_eq!(Region::of(&rom), Region::Dendy);
+        rom.timing = Timing::MultiRegion;
+        assert_eq!(Region::of(&rom), Region::Ntsc);
+    }
+
+    #[test]
+    fn test_clock_rates() {
+        assert_eq!(Region::Ntsc.cpu_clock_rate().round(), 1_789_773.0);
+        assert_eq!(Region::Pal.cpu_clock_rate().round(), 1_662_607.0);
+        assert_eq!(Region::Dendy.cpu_clock_rate().round(), 1_773_447.0);
+        assert!((Region::Pal.frame_rate() - 50.007).abs() < 0.001);
+    }
+
+    #[test]
+    fn test_pal_dot_ratio() {
+        let mut bus = Bus::new(test_rom()).unwrap();
+        bus.set_region(Region::Pal);
+        // 3.2 dots per cycle, the fraction carries over
+        bus.tick(2);
+        assert_eq!(bus.ppu.cycles, 6);
+        bus.tick(3);
+        assert_eq!(bus.ppu.cycles, 16);
+    }
+
+    #[test]
+    fn test_frame_length() {
+        for region in [Region::Ntsc, Region::Pal, Region::Dendy] {
+            let mut bus = Bus::new(test_rom()).unwrap();
+            bus.set_region(region);
+            let mut cycles = 0;
+            let mut vblank_at = None;
+            let mut line = 0;
+            // until the scanline wraps around
+            while bus.ppu.scanline >= line {
+                line = bus.ppu.scanline;
+                bus.tick(1);
+                cycles += 1;
+                if vblank_at.is_none() && bus.ppu.scanline == region.vblank_line() {
+                    vblank_at = Some(cycles);
+                }
+            }
+            let dots = 341 * region.scanlines() as usize;
+            assert_eq!(cycles, (dots * region.ppu_divider()).div_ceil(region.cpu_divider()));
+            let vblank_dots = 341 * region.vblank_line() as usize;
+            assert_eq!(vblank_at, Some((vblank_dots * region.ppu_divider()).div_ceil(region.cpu_divider())));
+        }
+    }
+}
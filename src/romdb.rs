@@ -0,0 +1,426 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::cartridge::{ConsoleType, Mirroring, Rom, Timing};
+use crate::hash;
+
+// Game database in the NES 2.0 XML format (nes20db.xml):
+//
+//  <nes20db>
+//    <game>
+//      <!-- \Licensed\Some Game (USA).nes -->
+//      <prgrom size="131072" crc32="..." sha1="..."/>
+//      <rom size="262144" crc32="..." sha1="..."/>
+//      <prgnvram size="8192"/>
+//      <pcb mapper="1" submapper="0" mirroring="H" battery="1"/>
+//      <console type="0" region="0"/>
+//    </game>
+//  </nes20db>
+//
+// Games are matched on the hashes of <rom>, which covers PRG and CHR ROM
+// together. The title is taken from the comment inside <game>, the board from an optional
+// name attribute of <pcb> and otherwise from the mapper number.
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RomDbEntry {
+    pub title: String,
+    pub board: Option<String>,
+    pub crc32: u32,
+    pub sha1: Option<String>,
+
+    pub mapper: u16,
+    pub submapper: u8,
+    pub mirroring: Option<Mirroring>,
+    pub battery: bool,
+    pub prg_ram_size: usize,
+    pub prg_nvram_size: usize,
+    pub chr_ram_size: usize,
+    pub chr_nvram_size: usize,
+    pub timing: Option<Timing>,
+    pub console_type: Option<ConsoleType>,
+    pub vs_ppu_type: u8,
+    pub vs_hardware_type: u8,
+    pub expansion_device: u8,
+}
+
+#[derive(Debug)]
+pub enum RomDbError {
+    Io(io::Error),
+    Parse(String),
+}
+
+impl fmt::Display for RomDbError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RomDbError::Io(e) => write!(f, "Can't read rom database: {}", e),
+            RomDbError::Parse(message) => write!(f, "Bad rom database: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for RomDbError {}
+
+impl From<io::Error> for RomDbError {
+    fn from(e: io::Error) -> Self {
+        RomDbError::Io(e)
+    }
+}
+
+pub struct RomDb {
+    entries: Vec<RomDbEntry>,
+    by_crc32: HashMap<u32, usize>,
+    by_sha1: HashMap<String, usize>,
+}
+
+impl RomDb {
+    pub fn load(path: impl AsRef<Path>) -> Result<RomDb, RomDbError> {
+        let xml = fs::read_to_string(path)?;
+        RomDb::parse(&xml)
+    }
+
+    pub fn parse(xml: &str) -> Result<RomDb, RomDbError> {
+        let mut db = RomDb {
+            entries: vec![],
+            by_crc32: HashMap::new(),
+            by_sha1: HashMap::new(),
+        };
+
+        let mut game: Option<GameBuilder> = None;
+        for token in tokenize(xml)? {
+            match token {
+                Token::Comment(text) => {
+                    if let Some(game) = game.as_mut() {
+                        game.entry.title = title_from_comment(&text);
+                    }
+                }
+                Token::Start { name, .. } if name == "game" => game = Some(GameBuilder::new()),
+                Token::Start { name, attrs } => {
+                    if let Some(game) = game.as_mut() {
+                        game.element(&name, &attrs)?;
+                    }
+                }
+                Token::End(name) if name == "game" => {
+                    if let Some(entry) = game.take().and_then(GameBuilder::build) {
+                        db.add(entry);
+                    }
+                }
+                Token::End(_) => {}
+            }
+        }
+        Ok(db)
+    }
+
+    fn add(&mut self, entry: RomDbEntry) {
+        let index = self.entries.len();
+        self.by_crc32.entry(entry.crc32).or_insert(index);
+        if let Some(sha1) = &entry.sha1 {
+            self.by_sha1.entry(sha1.clone()).or_insert(index);
+        }
+        self.entries.push(entry);
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn lookup(&self, rom: &Rom) -> Option<&RomDbEntry> {
+        let mut data = rom.prg_rom.clone();
+        data.extend_from_slice(&rom.chr_rom);
+
+        let sha1 = hash::to_hex(&hash::sha1(&data));
+        let index = match self.by_sha1.get(&sha1) {
+            Some(index) => Some(*index),
+            None => self.by_crc32.get(&hash::crc32(&data)).copied(),
+        };
+        index.map(|i| &self.entries[i])
+    }
+
+    // Replaces what the header says with the database entry, if the rom is known.
+    pub fn apply(&self, rom: &mut Rom) -> Option<&RomDbEntry> {
+        let entry = self.lookup(rom)?;
+        rom.mapper = entry.mapper;
+        rom.submapper = entry.submapper;
+        if let Some(mirroring) = entry.mirroring {
+            rom.screen_mirroring = mirroring;
+        }
+        rom.battery = entry.battery;
+        rom.prg_ram_size = entry.prg_ram_size;
+        rom.prg_nvram_size = entry.prg_nvram_size;
+        rom.chr_ram_size = entry.chr_ram_size;
+        rom.chr_nvram_size = entry.chr_nvram_size;
+        if let Some(timing) = entry.timing {
+            rom.timing = timing;
+        }
+        if let Some(console_type) = entry.console_type {
+            rom.console_type = console_type;
+            rom.vs_ppu_type = entry.vs_ppu_type;
+            rom.vs_hardware_type = entry.vs_hardware_type;
+        }
+        rom.expansion_device = entry.expansion_device;
+        rom.title = Some(entry.title.clone());
+        rom.board = entry.board.clone();
+        Some(entry)
+    }
+}
+
+// "\Licensed\Some Game (USA).nes" -> "Some Game (USA)"
+fn title_from_comment(comment: &str) -> String {
+    let name = comment.trim().rsplit(['\\', '/']).next().unwrap_or("");
+    match name.rsplit_once('.') {
+        Some((stem, ext)) if ext.len() <= 4 => stem.to_string(),
+        _ => name.to_string(),
+    }
+}
+
+struct GameBuilder {
+    entry: RomDbEntry,
+    has_rom: bool,
+}
+
+impl GameBuilder {
+    fn new() -> Self {
+        GameBuilder {
+            entry: RomDbEntry {
+                title: String::new(),
+                board: None,
+                crc32: 0,
+                sha1: None,
+                mapper: 0,
+                submapper: 0,
+                mirroring: None,
+                battery: false,
+                prg_ram_size: 0,
+                prg_nvram_size: 0,
+                chr_ram_size: 0,
+                chr_nvram_size: 0,
+                timing: None,
+                console_type: None,
+                vs_ppu_type: 0,
+                vs_hardware_type: 0,
+                expansion_device: 0,
+            },
+            has_rom: false,
+        }
+    }
+
+    fn element(&mut self, name: &str, attrs: &HashMap<String, String>) -> Result<(), RomDbError> {
+        let number = |key: &str| -> Result<usize, RomDbError> {
+            match attrs.get(key) {
+                None => Ok(0),
+                Some(value) => value
+                    .parse()
+                    .map_err(|_| RomDbError::Parse(format!("<{} {}=\"{}\"> is not a number", name, key, value))),
+            }
+        };
+        let entry = &mut self.entry;
+        match name {
+            "rom" => {
+                let crc32 = attrs.get("crc32").map(String::as_str).unwrap_or("");
+                entry.crc32 = u32::from_str_radix(crc32, 16)
+                    .map_err(|_| RomDbError::Parse(format!("<rom crc32=\"{}\"> is not a CRC-32", crc32)))?;
+                entry.sha1 = attrs.get("sha1").map(|sha1| sha1.to_uppercase());
+                self.has_rom = true;
+            }
+            "prgram" => entry.prg_ram_size = number("size")?,
+            "prgnvram" => entry.prg_nvram_size = number("size")?,
+            "chrram" => entry.chr_ram_size = number("size")?,
+            "chrnvram" => entry.chr_nvram_size = number("size")?,
+            "pcb" => {
+                entry.mapper = number("mapper")? as u16;
+                entry.submapper = number("submapper")? as u8;
+                entry.battery = number("battery")? != 0;
+                entry.mirroring = match attrs.get("mirroring").map(String::as_str) {
+                    Some("H") => Some(Mirroring::HORIZONTAL),
+                    Some("V") => Some(Mirroring::VERTICAL),
+                    Some("4") => Some(Mirroring::FOUR_SCREEN),
+                    _ => None,
+                };
+                entry.board = attrs.get("name").cloned();
+            }
+            "console" => {
+                entry.console_type = Some(match number("type")? {
+                    0 => ConsoleType::Nes,
+                    1 => ConsoleType::VsSystem,
+                    2 => ConsoleType::Playchoice10,
+                    other => ConsoleType::Extended(other as u8),
+                });
+                entry.timing = Some(match number("region")? {
+                    0 => Timing::Ntsc,
+                    1 => Timing::Pal,
+                    2 => Timing::MultiRegion,
+                    _ => Timing::Dendy,
+                });
+            }
+            "vs" => {
+                entry.vs_hardware_type = number("hardware")? as u8;
+                entry.vs_ppu_type = number("ppu")? as u8;
+            }
+            "expansion" => entry.expansion_device = number("type")? as u8,
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn build(mut self) -> Option<RomDbEntry> {
+        if !self.has_rom {
+            return None;
+        }
+        if self.entry.board.is_none() {
+            self.entry.board = crate::mapper::board_name(self.entry.mapper).map(str::to_string);
+        }
+        Some(self.entry)
+    }
+}
+
+enum Token {
+    Comment(String),
+    Start { name: String, attrs: HashMap<String, String> },
+    End(String),
+}
+
+// Just enough XML for the database: elements, attributes and comments.
+fn tokenize(xml: &str) -> Result<Vec<Token>, RomDbError> {
+    let mut tokens = vec![];
+    let mut rest = xml;
+    while let Some(start) = rest.find('<') {
+        rest = &rest[start..];
+        let unclosed = || RomDbError::Parse(format!("unclosed tag near {:?}", &rest[..rest.len().min(40)]));
+        if let Some(body) = rest.strip_prefix("<!--") {
+            let end = body.find("-->").ok_or_else(unclosed)?;
+            tokens.push(Token::Comment(body[..end].to_string()));
+            rest = &body[end + 3..];
+            continue;
+        }
+        let end = rest.find('>').ok_or_else(unclosed)?;
+        let tag = &rest[1..end];
+        rest = &rest[end + 1..];
+
+        if tag.starts_with('?') || tag.starts_with('!') {
+            continue;
+        }
+        if let Some(name) = tag.strip_prefix('/') {
+            tokens.push(Token::End(name.trim().to_string()));
+            continue;
+        }
+        let self_closing = tag.ends_with('/');
+        let tag = tag.trim_end_matches('/');
+        let (name, attrs) = match tag.find(char::is_whitespace) {
+            Some(i) => (&tag[..i], parse_attributes(&tag[i..])?),
+            None => (tag, HashMap::new()),
+        };
+        tokens.push(Token::Start { name: name.to_string(), attrs });
+        if self_closing {
+            tokens.push(Token::End(name.to_string()));
+        }
+    }
+    Ok(tokens)
+}
+
+fn parse_attributes(mut text: &str) -> Result<HashMap<String, String>, RomDbError> {
+    let mut attrs = HashMap::new();
+    loop {
+        text = text.trim_start();
+        if text.is_empty() {
+            return Ok(attrs);
+        }
+        let bad = || RomDbError::Parse(format!("bad attribute {:?}", text));
+        let eq = text.find('=').ok_or_else(bad)?;
+        let key = text[..eq].trim().to_string();
+        let value = text[eq + 1..].trim_start();
+        let quote = value.chars().next().filter(|c| *c == '"' || *c == '\'').ok_or_else(bad)?;
+        let end = value[1..].find(quote).ok_or_else(bad)?;
+        attrs.insert(key, unescape(&value[1..end + 1]));
+        text = &value[end + 2..];
+    }
+}
+
+fn unescape(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cartridge::test::test_rom;
+
+    fn database(crc32: u32, sha1: &str) -> String {
+        format!(
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<nes20db date="2024-01-01">
+  <game>
+    <!-- \Licensed\Other Game (Japan).nes -->
+    <rom size="16" crc32="DEADBEEF"/>
+    <pcb mapper="0" submapper="0" mirroring="H" battery="0"/>
+  </game>
+  <game>
+    <!-- \Licensed\Test & Game (Europe).nes -->
+    <prgrom size="32768" crc32="00000000"/>
+    <rom size="40960" crc32="{:08X}" sha1="{}"/>
+    <prgnvram size="8192"/>
+    <chrram size="8192"/>
+    <pcb mapper="24" submapper="0" mirroring="H" battery="1"/>
+    <console type="0" region="1"/>
+    <expansion type="1"/>
+  </game>
+</nes20db>
+"#,
+            crc32, sha1
+        )
+    }
+
+    fn rom_hashes() -> (u32, String) {
+        let rom = test_rom();
+        let mut data = rom.prg_rom.clone();
+        data.extend_from_slice(&rom.chr_rom);
+        (hash::crc32(&data), hash::to_hex(&hash::sha1(&data)))
+    }
+
+    #[test]
+    fn test_apply_overrides_header() {
+        let (crc32, sha1) = rom_hashes();
+        let db = RomDb::parse(&database(crc32, &sha1.to_lowercase())).unwrap();
+        assert_eq!(db.len(), 2);
+
+        let mut rom = test_rom();
+        let entry = db.apply(&mut rom).unwrap();
+        assert_eq!(entry.title, "Test & Game (Europe)");
+        assert_eq!(entry.board.as_deref(), Some("VRC6a"));
+
+        assert_eq!(rom.mapper, 24);
+        assert_eq!(rom.screen_mirroring, Mirroring::HORIZONTAL);
+        assert!(rom.battery);
+        assert_eq!(rom.prg_ram_size, 0);
+        assert_eq!(rom.prg_nvram_size, 8192);
+        assert_eq!(rom.chr_ram_size, 8192);
+        assert_eq!(rom.timing, Timing::Pal);
+        assert_eq!(rom.expansion_device, 1);
+        assert_eq!(rom.title.as_deref(), Some("Test & Game (Europe)"));
+    }
+
+    #[test]
+    fn test_lookup_falls_back_to_crc32() {
+        let (crc32, _) = rom_hashes();
+        let db = RomDb::parse(&database(crc32, "0000")).unwrap();
+        assert!(db.lookup(&test_rom()).is_some());
+
+        let db = RomDb::parse(&database(crc32 ^ 1, "0000")).unwrap();
+        assert!(db.lookup(&test_rom()).is_none());
+    }
+
+    #[test]
+    fn test_bad_database() {
+        assert!(matches!(RomDb::parse("<game><rom crc32=\"xyz\"/></game>"), Err(RomDbError::Parse(_))));
+        assert!(matches!(RomDb::parse("<game><rom crc32=\"1\""), Err(RomDbError::Parse(_))));
+    }
+}
@@ -0,0 +1,100 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+// Where the battery backed RAM of a cartridge is kept between sessions.
+// The save is loaded once when the storage is attached to the bus, after that
+// the whole RAM is handed to save() whenever it changed.
+pub trait SaveStorage {
+    // Ok(None) when there is no save yet
+    fn load(&mut self) -> io::Result<Option<Vec<u8>>>;
+    fn save(&mut self, data: &[u8]) -> io::Result<()>;
+}
+
+pub struct FileStorage {
+    path: PathBuf,
+}
+
+impl FileStorage {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        FileStorage { path: path.into() }
+    }
+
+    // game.nes keeps its save in game.sav
+    pub fn next_to_rom(rom_path: impl AsRef<Path>) -> Self {
+        FileStorage::new(rom_path.as_ref().with_extension("sav"))
+    }
+
+    // disk writes are kept as an IPS patch so the image itself stays untouched
+    pub fn next_to_disk_image(image_path: impl AsRef<Path>) -> Self {
+        FileStorage::new(image_path.as_ref().with_extension("ips"))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl SaveStorage for FileStorage {
+    fn load(&mut self) -> io::Result<Option<Vec<u8>>> {
+        match fs::read(&self.path) {
+            Ok(data) => Ok(Some(data)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn save(&mut self, data: &[u8]) -> io::Result<()> {
+        fs::write(&self.path, data)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bus::Bus;
+    use crate::cartridge::test::test_rom;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    struct SharedStorage(Rc<RefCell<Option<Vec<u8>>>>);
+
+    impl SaveStorage for SharedStorage {
+        fn load(&mut self) -> io::Result<Option<Vec<u8>>> {
+            Ok(self.0.borrow().clone())
+        }
+
+        fn save(&mut self, data: &[u8]) -> io::Result<()> {
+            *self.0.borrow_mut() = Some(data.to_vec());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_file_storage_round_trip() {
+        let rom_path = std::env::temp_dir().join(format!("save_test_{}.nes", std::process::id()));
+        let mut storage = FileStorage::next_to_rom(&rom_path);
+        assert_eq!(storage.path().extension().unwrap(), "sav");
+        assert_eq!(storage.load().unwrap(), None);
+
+        storage.save(&[1, 2, 3]).unwrap();
+        assert_eq!(storage.load().unwrap(), Some(vec![1, 2, 3]));
+        fs::remove_file(storage.path()).unwrap();
+    }
+
+    #[test]
+    fn test_battery_ram_is_loaded_and_flushed() {
+        let mut rom = test_rom();
+        rom.battery = true;
+        let shared = Rc::new(RefCell::new(Some(vec![0x42; 0x2000])));
+        let mut bus = Bus::new(rom).unwrap();
+        bus.set_save_storage(Box::new(SharedStorage(shared.clone())));
+        assert_eq!(bus.read_mem(0x6000), 0x42);
+
+        bus.write_mem(0x7FFF, 0x99);
+        bus.flush_save_ram();
+        let saved = shared.borrow().clone().unwrap();
+        assert_eq!(saved[0x1FFF], 0x99);
+        assert_eq!(saved[0], 0x42);
+    }
+}
@@ -11,6 +11,7 @@ use crate::cpu::CPU;
 use crate::cartridge;
 use crate::bus;
 use crate::log;
+use crate::player::handle_channel_keys;
 
 fn color(byte: u8) -> Color {
     match byte {
@@ -43,7 +44,7 @@ fn read_screen_state(cpu: &mut CPU, frame: &mut [u8; 32 * 3 * 32]) -> bool {
     update
 }
 
-fn handle_user_input(cpu: &mut CPU, event_pump: &mut EventPump) {
+fn handle_user_input(cpu: &mut CPU, event_pump: &mut EventPump, selected_channel: &mut usize) {
     for event in event_pump.poll_iter() {
         match event {
             Event::Quit { .. } | Event::KeyDown { keycode: Some(Keycode::Escape), .. } => {
@@ -61,6 +62,9 @@ fn handle_user_input(cpu: &mut CPU, event_pump: &mut EventPump) {
             Event::KeyDown { keycode: Some(Keycode::D), .. } => {
                 cpu.write_mem(0xff, 0x64);
             }
+            Event::KeyDown { keycode: Some(key), keymod, .. } => {
+                handle_channel_keys(&mut cpu.bus, key, keymod, selected_channel);
+            }
             _ => {/* do nothing */}
         }
     }
@@ -88,15 +92,16 @@ pub fn snake() {
         .create_texture_target(PixelFormatEnum::RGB24, 32, 32).unwrap();
 
     let bytes: Vec<u8> = std::fs::read("./games/snake.nes").unwrap();
-    let rom = cartridge::Rom::new(&bytes).unwrap();
-    let mut cpu = CPU::new(bus::Bus::new(rom));
+    let rom = cartridge::Rom::from_bytes(&bytes).unwrap();
+    let mut cpu = CPU::new(bus::Bus::new(rom).unwrap());
     cpu.reset();
 
     let mut screen_state = [0 as u8; 32 * 3 * 32];
     let mut rng = rand::thread_rng();
+    let mut selected_channel = 0;
 
     cpu.run_with_callback(move |cpu| {
-        handle_user_input(cpu, &mut event_pump);
+        handle_user_input(cpu, &mut event_pump, &mut selected_channel);
         println!("{}",log::log(cpu));
         cpu.write_mem(0xfe, rng.gen_range(1..16));
         if read_screen_state(cpu, &mut screen_state) {
@@ -0,0 +1,197 @@
+use crate::cartridge::{HeaderFormat, Mirroring, Rom, RomError, RomWarning, Timing};
+
+// UNIF, the board-centric alternative to iNES.
+//
+// A 32 byte header ("UNIF", a 32 bit revision, zero padding) is followed by
+// chunks of a 4 character id, a 32 bit little endian length and the data:
+//
+//  MAPR       board name, e.g. "NES-CNROM"
+//  PRG0-PRGF  PRG ROM pieces, concatenated in that order
+//  CHR0-CHRF  CHR ROM pieces, the same
+//  MIRR       0 horizontal, 1 vertical, 2/3 one screen, 4 four screen, 5 by mapper
+//  BATR       present when the board has a battery
+//  TVCI       0 NTSC, 1 PAL, 2 both
+//  NAME       game title
+//
+// Other chunks are skipped.
+
+const HEADER_SIZE: usize = 32;
+
+// prefixes naming the manufacturer rather than the board
+const BOARD_PREFIXES: [&str; 8] = ["NES-", "HVC-", "UNL-", "BTL-", "BMC-", "IREM-", "KONAMI-", "TAITO-"];
+
+fn c_string(data: &[u8]) -> String {
+    let end = data.iter().position(|b| *b == 0).unwrap_or(data.len());
+    String::from_utf8_lossy(&data[..end]).to_string()
+}
+
+pub fn parse(raw: &[u8]) -> Result<Rom, RomError> {
+    if raw.len() < HEADER_SIZE {
+        return Err(RomError::TooShort(raw.len()));
+    }
+
+    let mut rom = Rom::empty(HeaderFormat::Unif);
+    rom.prg_ram_size = 0x2000;
+    let mut prg: [Option<&[u8]>; 16] = [None; 16];
+    let mut chr: [Option<&[u8]>; 16] = [None; 16];
+    let mut board = None;
+
+    let mut pos = HEADER_SIZE;
+    while pos < raw.len() {
+        if raw.len() - pos < 8 {
+            rom.warnings.push(RomWarning::TrailingData(raw.len() - pos));
+            break;
+        }
+        let id = &raw[pos..pos + 4];
+        let len = u32::from_le_bytes([raw[pos + 4], raw[pos + 5], raw[pos + 6], raw[pos + 7]]) as usize;
+        let start = pos + 8;
+        let available = raw.len() - start;
+        if available < len {
+            match id {
+                [b'P', b'R', b'G', _] => return Err(RomError::TruncatedPrg { expected: len, found: available }),
+                [b'C', b'H', b'R', _] => return Err(RomError::TruncatedChr { expected: len, found: available }),
+                _ => {
+                    rom.warnings.push(RomWarning::TrailingData(raw.len() - pos));
+                    break;
+                }
+            }
+        }
+        let data = &raw[start..start + len];
+        pos = start + len;
+
+        // PRG0-PRGF and CHR0-CHRF only, anything else would overwrite piece 0
+        let index = (id[3] as char).to_digit(16);
+        if (id.starts_with(b"PRG") || id.starts_with(b"CHR")) && index.is_none() {
+            rom.warnings.push(RomWarning::UnknownChunk(String::from_utf8_lossy(id).to_string()));
+            continue;
+        }
+        let index = index.unwrap_or(0) as usize;
+        match id {
+            b"MAPR" => board = Some(c_string(data)),
+            b"NAME" => rom.title = Some(c_string(data)),
+            [b'P', b'R', b'G', _] => prg[index] = Some(data),
+            [b'C', b'H', b'R', _] => chr[index] = Some(data),
+            b"MIRR" => {
+                rom.screen_mirroring = match data.first() {
+                    Some(1) => Mirroring::VERTICAL,
+                    Some(2) => Mirroring::ONE_SCREEN_LOWER,
+                    Some(3) => Mirroring::ONE_SCREEN_UPPER,
+                    Some(4) => Mirroring::FOUR_SCREEN,
+                    _ => Mirroring::HORIZONTAL,
+                }
+            }
+            b"BATR" => rom.battery = true,
+            b"TVCI" => {
+                rom.timing = match data.first() {
+                    Some(1) => Timing::Pal,
+                    Some(2) => Timing::MultiRegion,
+                    _ => Timing::Ntsc,
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let board = board.ok_or_else(|| RomError::UnsupportedBoard(String::new()))?;
+    let name = BOARD_PREFIXES
+        .iter()
+        .find_map(|prefix| board.strip_prefix(prefix))
+        .unwrap_or(&board);
+    rom.mapper = crate::mapper::unif_board_mapper(name).ok_or_else(|| RomError::UnsupportedBoard(board.clone()))?;
+    rom.board = Some(board);
+
+    rom.prg_rom = prg.iter().flatten().flat_map(|piece| piece.iter().copied()).collect();
+    rom.chr_rom = chr.iter().flatten().flat_map(|piece| piece.iter().copied()).collect();
+    if rom.prg_rom.is_empty() || !rom.prg_rom.len().is_multiple_of(0x2000) {
+        return Err(RomError::SizeMismatch { prg_rom: rom.prg_rom.len() });
+    }
+    if rom.chr_rom.is_empty() {
+        rom.chr_ram_size = 0x2000;
+    }
+    Ok(rom)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn chunk(id: &[u8], data: &[u8]) -> Vec<u8> {
+        let mut result = id.to_vec();
+        result.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        result.extend_from_slice(data);
+        result
+    }
+
+    fn unif(chunks: &[Vec<u8>]) -> Vec<u8> {
+        let mut raw = b"UNIF".to_vec();
+        raw.extend_from_slice(&7u32.to_le_bytes());
+        raw.resize(HEADER_SIZE, 0);
+        for chunk in chunks {
+            raw.extend_from_slice(chunk);
+        }
+        raw
+    }
+
+    #[test]
+    fn test_unif() {
+        let raw = unif(&[
+            chunk(b"MAPR", b"NES-CNROM\0"),
+            chunk(b"NAME", b"Some Game\0"),
+            chunk(b"PRG1", &[2; 0x4000]),
+            chunk(b"PRG0", &[1; 0x4000]),
+            chunk(b"CHR0", &[3; 0x2000]),
+            chunk(b"MIRR", &[1]),
+            chunk(b"BATR", &[1]),
+            chunk(b"TVCI", &[1]),
+            chunk(b"DINF", &[0; 204]),
+        ]);
+        let rom = Rom::from_bytes(&raw).unwrap();
+
+        assert_eq!(rom.header_format, HeaderFormat::Unif);
+        assert_eq!(rom.mapper, 3);
+        assert_eq!(rom.board.as_deref(), Some("NES-CNROM"));
+        assert_eq!(rom.title.as_deref(), Some("Some Game"));
+        assert_eq!(rom.prg_rom.len(), 0x8000);
+        assert_eq!(rom.prg_rom[0], 1);
+        assert_eq!(rom.prg_rom[0x4000], 2);
+        assert_eq!(rom.chr_rom, vec![3; 0x2000]);
+        assert_eq!(rom.screen_mirroring, Mirroring::VERTICAL);
+        assert!(rom.battery);
+        assert_eq!(rom.timing, Timing::Pal);
+        assert!(rom.warnings.is_empty());
+    }
+
+    #[test]
+    fn test_unif_chr_ram() {
+        let raw = unif(&[chunk(b"MAPR", b"NES-NROM-256\0"), chunk(b"PRG0", &[1; 0x8000])]);
+        let rom = Rom::from_bytes(&raw).unwrap();
+        assert_eq!(rom.mapper, 0);
+        assert!(rom.chr_rom.is_empty());
+        assert_eq!(rom.chr_ram_size, 0x2000);
+    }
+
+    #[test]
+    fn test_unif_errors() {
+        let raw = unif(&[chunk(b"MAPR", b"UNL-SOMETHING\0"), chunk(b"PRG0", &[1; 0x8000])]);
+        assert!(matches!(Rom::from_bytes(&raw), Err(RomError::UnsupportedBoard(board)) if board == "UNL-SOMETHING"));
+
+        let mut raw = unif(&[chunk(b"MAPR", b"NES-NROM-256\0"), chunk(b"PRG0", &[1; 0x8000])]);
+        raw.truncate(raw.len() - 0x100);
+        assert!(matches!(
+            Rom::from_bytes(&raw),
+            Err(RomError::TruncatedPrg { expected: 0x8000, found: 0x7F00 })
+        ));
+    }
+
+    #[test]
+    fn test_unif_bad_piece_number() {
+        let raw = unif(&[
+            chunk(b"MAPR", b"NES-NROM-256\0"),
+            chunk(b"PRG0", &[1; 0x8000]),
+            chunk(b"PRGx", &[2; 0x8000]),
+        ]);
+        let rom = Rom::from_bytes(&raw).unwrap();
+        assert_eq!(rom.prg_rom, vec![1; 0x8000]);
+        assert_eq!(rom.warnings, vec![RomWarning::UnknownChunk("PRGx".to_string())]);
+    }
+}
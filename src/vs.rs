@@ -0,0 +1,254 @@
+use crate::cartridge::{ConsoleType, Rom};
+
+// Vs. System arcade hardware around the NES parts.
+//
+// $4016 read:  bit 2 service button, bits 3-4 DIP switches 1-2, bits 5-6 coin slots 1-2
+// $4017 read:  bits 2-7 DIP switches 3-8
+// $4020 write: bit 0 drives the coin counter
+//
+// Bit 0 of both ports is still the controller data. Dual System cabinets
+// run only their main side and the protection chips of some games (RBI
+// Baseball, TKO Boxing, Super Xevious) aren't emulated.
+
+// The PPU of the cabinet, NES 2.0 byte 13 low nibble. The RGB PPUs have
+// their own palette, the 2C04s scramble it in four different orders and
+// the 2C05s swap $2000/$2001 and put an id in the low bits of $2002.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum VsPpu {
+    Rp2c03b,
+    Rp2c03g,
+    Rp2c04_0001,
+    Rp2c04_0002,
+    Rp2c04_0003,
+    Rp2c04_0004,
+    Rc2c03b,
+    Rc2c03c,
+    Rc2c05_01,
+    Rc2c05_02,
+    Rc2c05_03,
+    Rc2c05_04,
+    Rc2c05_05,
+}
+
+impl VsPpu {
+    pub fn from_nibble(value: u8) -> Option<VsPpu> {
+        let ppu = match value {
+            0x0 => VsPpu::Rp2c03b,
+            0x1 => VsPpu::Rp2c03g,
+            0x2 => VsPpu::Rp2c04_0001,
+            0x3 => VsPpu::Rp2c04_0002,
+            0x4 => VsPpu::Rp2c04_0003,
+            0x5 => VsPpu::Rp2c04_0004,
+            0x6 => VsPpu::Rc2c03b,
+            0x7 => VsPpu::Rc2c03c,
+            0x8 => VsPpu::Rc2c05_01,
+            0x9 => VsPpu::Rc2c05_02,
+            0xA => VsPpu::Rc2c05_03,
+            0xB => VsPpu::Rc2c05_04,
+            0xC => VsPpu::Rc2c05_05,
+            _ => return None,
+        };
+        Some(ppu)
+    }
+
+    // the RGB PPU of a cart, None for a plain 2C02
+    pub fn of(rom: &Rom) -> Option<VsPpu> {
+        match rom.console_type {
+            ConsoleType::VsSystem => VsPpu::from_nibble(rom.vs_ppu_type),
+            // the PlayChoice-10 shows NES carts through a 2C03
+            ConsoleType::Playchoice10 => Some(VsPpu::Rp2c03b),
+            _ => None,
+        }
+    }
+
+    // what the 2C05s return in bits 0-4 of $2002
+    pub fn status_id(&self) -> Option<u8> {
+        match self {
+            VsPpu::Rc2c05_01 | VsPpu::Rc2c05_04 => Some(0x1B),
+            VsPpu::Rc2c05_02 => Some(0x3D),
+            VsPpu::Rc2c05_03 => Some(0x1C),
+            _ => None,
+        }
+    }
+
+    pub fn swaps_ctrl_mask(&self) -> bool {
+        matches!(
+            self,
+            VsPpu::Rc2c05_01 | VsPpu::Rc2c05_02 | VsPpu::Rc2c05_03 | VsPpu::Rc2c05_04 | VsPpu::Rc2c05_05
+        )
+    }
+}
+
+// NES 2.0 byte 13 high nibble
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum VsHardware {
+    UniSystem,
+    RbiBaseball,
+    TkoBoxing,
+    SuperXevious,
+    IceClimberJapan,
+    DualSystem,
+    RaidOnBungelingBay,
+}
+
+impl VsHardware {
+    pub fn from_nibble(value: u8) -> Option<VsHardware> {
+        let hardware = match value {
+            0 => VsHardware::UniSystem,
+            1 => VsHardware::RbiBaseball,
+            2 => VsHardware::TkoBoxing,
+            3 => VsHardware::SuperXevious,
+            4 => VsHardware::IceClimberJapan,
+            5 => VsHardware::DualSystem,
+            6 => VsHardware::RaidOnBungelingBay,
+            _ => return None,
+        };
+        Some(hardware)
+    }
+}
+
+pub struct VsSystem {
+    pub ppu: Option<VsPpu>,
+    pub hardware: Option<VsHardware>,
+    // switch 1 is bit 0, a set bit means on
+    pub dip_switches: u8,
+    coins: [bool; 2],
+    service: bool,
+    coin_counter: bool,
+    coins_counted: usize,
+}
+
+impl VsSystem {
+    // None unless the cart is a Vs. System game
+    pub fn new(rom: &Rom) -> Option<VsSystem> {
+        if rom.console_type != ConsoleType::VsSystem {
+            return None;
+        }
+        Some(VsSystem {
+            ppu: VsPpu::from_nibble(rom.vs_ppu_type),
+            hardware: VsHardware::from_nibble(rom.vs_hardware_type),
+            dip_switches: 0,
+            coins: [false; 2],
+            service: false,
+            coin_counter: false,
+            coins_counted: 0,
+        })
+    }
+
+    pub fn dip_switch(&self, switch: usize) -> bool {
+        self.dip_switches & (1 << switch) != 0
+    }
+
+    pub fn set_dip_switch(&mut self, switch: usize, on: bool) {
+        if on {
+            self.dip_switches |= 1 << switch;
+        } else {
+            self.dip_switches &= !(1 << switch);
+        }
+    }
+
+    // the coin switch has to stay closed for a few frames to register
+    pub fn set_coin(&mut self, slot: usize, inserted: bool) {
+        self.coins[slot] = inserted;
+    }
+
+    pub fn set_service(&mut self, pressed: bool) {
+        self.service = pressed;
+    }
+
+    // coins the game has counted through $4020
+    pub fn coins_counted(&self) -> usize {
+        self.coins_counted
+    }
+
+    pub fn read_4016(&self) -> u8 {
+        (self.service as u8) << 2
+            | (self.dip_switches & 0b11) << 3
+            | (self.coins[0] as u8) << 5
+            | (self.coins[1] as u8) << 6
+    }
+
+    pub fn read_4017(&self) -> u8 {
+        self.dip_switches & 0xFC
+    }
+
+    pub fn write_4020(&mut self, data: u8) {
+        let counter = data & 1 != 0;
+        if counter && !self.coin_counter {
+            self.coins_counted += 1;
+        }
+        self.coin_counter = counter;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cartridge::HeaderFormat;
+
+    fn vs_rom(ppu: u8, hardware: u8) -> Rom {
+        let mut rom = Rom::empty(HeaderFormat::Nes2);
+        rom.console_type = ConsoleType::VsSystem;
+        rom.vs_ppu_type = ppu;
+        rom.vs_hardware_type = hardware;
+        rom
+    }
+
+    #[test]
+    fn test_types_from_header() {
+        let vs = VsSystem::new(&vs_rom(0x3, 0x5)).unwrap();
+        assert_eq!(vs.ppu, Some(VsPpu::Rp2c04_0002));
+        assert_eq!(vs.hardware, Some(VsHardware::DualSystem));
+        assert!(VsSystem::new(&Rom::empty(HeaderFormat::Nes2)).is_none());
+
+        assert_eq!(VsPpu::of(&vs_rom(0x8, 0)), Some(VsPpu::Rc2c05_01));
+        assert_eq!(VsPpu::Rc2c05_01.status_id(), Some(0x1B));
+        assert!(VsPpu::Rc2c05_01.swaps_ctrl_mask());
+        assert!(!VsPpu::Rp2c04_0001.swaps_ctrl_mask());
+    }
+
+    #[test]
+    fn test_dip_switches_and_coins() {
+        let mut vs = VsSystem::new(&vs_rom(0, 0)).unwrap();
+        vs.set_dip_switch(0, true);
+        vs.set_dip_switch(7, true);
+        vs.set_coin(1, true);
+        vs.set_service(true);
+        assert_eq!(vs.read_4016(), 0b0100_1100);
+        assert_eq!(vs.read_4017(), 0b1000_0000);
+        vs.set_dip_switch(7, false);
+        assert!(!vs.dip_switch(7));
+
+        vs.write_4020(1);
+        vs.write_4020(1);
+        vs.write_4020(0);
+        vs.write_4020(1);
+        assert_eq!(vs.coins_counted(), 2);
+    }
+
+    #[test]
+    fn test_bus_routes_vs_registers() {
+        let mut rom = vs_rom(0x8, 0);
+        rom.mapper = 99;
+        rom.prg_rom = vec![0; 0x8000];
+        rom.chr_rom = (0..0x4000).map(|i| (i / 0x2000) as u8).collect();
+        let mut bus = crate::bus::Bus::new(rom).unwrap();
+
+        let vs = bus.vs_system().unwrap();
+        vs.dip_switches = 0b1010_0101;
+        vs.set_coin(0, true);
+        assert_eq!(bus.read_mem(0x4016), 0b0010_1000);
+        assert_eq!(bus.read_mem(0x4017), 0b1010_0100);
+        bus.write_mem(0x4020, 1);
+        assert_eq!(bus.vs_system().unwrap().coins_counted(), 1);
+
+        // the 2C05 id and the swapped control registers
+        assert_eq!(bus.read_mem(0x2002) & 0x1F, 0x1B);
+        bus.write_mem(0x2001, 0x80);
+        assert!(bus.ppu.ctrl.generate_vblank_nmi());
+
+        // $4016 bit 2 selects the CHR bank
+        bus.write_mem(0x4016, 0b100);
+        assert_eq!(bus.ppu.cartridge.borrow_mut().read_chr(0), 1);
+    }
+}
@@ -0,0 +1,158 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+
+// A minimal RIFF WAVE writer for captured audio. The sizes in the header are
+// written as 0 first and patched by finish().
+//
+//  "RIFF" size "WAVE"
+//  "fmt " 16 (18 for float) format channels rate byte rate block align bits
+//  "fact" 4 frames          (float only)
+//  "data" size samples, interleaved
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum SampleFormat {
+    Pcm16,
+    Float32,
+}
+
+impl SampleFormat {
+    fn bytes(&self) -> u16 {
+        match self {
+            SampleFormat::Pcm16 => 2,
+            SampleFormat::Float32 => 4,
+        }
+    }
+}
+
+pub struct WavWriter<W: Write + Seek> {
+    out: W,
+    format: SampleFormat,
+    channels: u16,
+    frames: u32,
+    // where the "data" size goes
+    data_size_pos: u64,
+}
+
+impl WavWriter<BufWriter<File>> {
+    pub fn create(path: impl AsRef<Path>, sample_rate: u32, channels: u16, format: SampleFormat) -> io::Result<Self> {
+        WavWriter::new(BufWriter::new(File::create(path)?), sample_rate, channels, format)
+    }
+}
+
+impl<W: Write + Seek> WavWriter<W> {
+    pub fn new(mut out: W, sample_rate: u32, channels: u16, format: SampleFormat) -> io::Result<Self> {
+        let block_align = channels * format.bytes();
+        out.write_all(b"RIFF")?;
+        out.write_all(&0u32.to_le_bytes())?;
+        out.write_all(b"WAVE")?;
+
+        out.write_all(b"fmt ")?;
+        let (fmt_size, tag): (u32, u16) = match format {
+            SampleFormat::Pcm16 => (16, 1),
+            SampleFormat::Float32 => (18, 3),
+        };
+        out.write_all(&fmt_size.to_le_bytes())?;
+        out.write_all(&tag.to_le_bytes())?;
+        out.write_all(&channels.to_le_bytes())?;
+        out.write_all(&sample_rate.to_le_bytes())?;
+        out.write_all(&(sample_rate * block_align as u32).to_le_bytes())?;
+        out.write_all(&block_align.to_le_bytes())?;
+        out.write_all(&(format.bytes() * 8).to_le_bytes())?;
+        if format == SampleFormat::Float32 {
+            // no extension
+            out.write_all(&0u16.to_le_bytes())?;
+            out.write_all(b"fact")?;
+            out.write_all(&4u32.to_le_bytes())?;
+            out.write_all(&0u32.to_le_bytes())?;
+        }
+
+        out.write_all(b"data")?;
+        let data_size_pos = out.stream_position()?;
+        out.write_all(&0u32.to_le_bytes())?;
+        Ok(WavWriter {
+            out,
+            format,
+            channels,
+            frames: 0,
+            data_size_pos,
+        })
+    }
+
+    // one sample per channel, -1.0 to 1.0
+    pub fn write_frame(&mut self, samples: &[f32]) -> io::Result<()> {
+        assert_eq!(samples.len(), self.channels as usize);
+        for sample in samples {
+            match self.format {
+                SampleFormat::Pcm16 => {
+                    let pcm = (sample.clamp(-1.0, 1.0) * i16::MAX as f32).round() as i16;
+                    self.out.write_all(&pcm.to_le_bytes())?;
+                }
+                SampleFormat::Float32 => self.out.write_all(&sample.to_le_bytes())?,
+            }
+        }
+        self.frames += 1;
+        Ok(())
+    }
+
+    pub fn frames(&self) -> u32 {
+        self.frames
+    }
+
+    // fills in the sizes and hands back the output
+    pub fn finish(mut self) -> io::Result<W> {
+        let data_size = self.frames * (self.channels * self.format.bytes()) as u32;
+        let end = self.out.stream_position()?;
+        self.out.seek(SeekFrom::Start(4))?;
+        self.out.write_all(&((end - 8) as u32).to_le_bytes())?;
+        if self.format == SampleFormat::Float32 {
+            // the fact chunk sits right before "data"
+            self.out.seek(SeekFrom::Start(self.data_size_pos - 8))?;
+            self.out.write_all(&self.frames.to_le_bytes())?;
+        }
+        self.out.seek(SeekFrom::Start(self.data_size_pos))?;
+        self.out.write_all(&data_size.to_le_bytes())?;
+        self.out.seek(SeekFrom::Start(end))?;
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Cursor;
+
+    fn u32_at(data: &[u8], pos: usize) -> u32 {
+        u32::from_le_bytes(data[pos..pos + 4].try_into().unwrap())
+    }
+
+    #[test]
+    fn test_pcm16() {
+        let mut wav = WavWriter::new(Cursor::new(vec![]), 44_100, 2, SampleFormat::Pcm16).unwrap();
+        wav.write_frame(&[1.0, -1.0]).unwrap();
+        wav.write_frame(&[0.0, 2.0]).unwrap();
+        let data = wav.finish().unwrap().into_inner();
+
+        assert_eq!(&data[0..4], b"RIFF");
+        assert_eq!(u32_at(&data, 4) as usize, data.len() - 8);
+        assert_eq!(&data[36..40], b"data");
+        assert_eq!(u32_at(&data, 40), 8);
+        assert_eq!(u32_at(&data, 28), 44_100 * 4);
+        assert_eq!(&data[44..], [0xFF, 0x7F, 0x01, 0x80, 0x00, 0x00, 0xFF, 0x7F]);
+    }
+
+    #[test]
+    fn test_float32() {
+        let mut wav = WavWriter::new(Cursor::new(vec![]), 48_000, 1, SampleFormat::Float32).unwrap();
+        wav.write_frame(&[0.5]).unwrap();
+        let data = wav.finish().unwrap().into_inner();
+
+        assert_eq!(u16::from_le_bytes([data[20], data[21]]), 3);
+        assert_eq!(&data[38..42], b"fact");
+        assert_eq!(u32_at(&data, 46), 1);
+        assert_eq!(&data[50..54], b"data");
+        assert_eq!(u32_at(&data, 54), 4);
+        assert_eq!(&data[58..], 0.5f32.to_le_bytes());
+    }
+}